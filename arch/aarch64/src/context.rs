@@ -0,0 +1,85 @@
+//! Kernel thread context switch.
+//!
+//! `context_switch` saves x19-x30 on the current stack, stores SP into
+//! `from`, loads SP from `to` and restores the same frame. A new thread's
+//! frame returns into `thread_trampoline`, which calls `entry(arg)` from
+//! x20/x19.
+
+use core::arch::global_asm;
+
+use hal::context::{Context, ContextOps, ThreadEntry};
+
+global_asm!(
+    ".global context_switch",
+    "context_switch:",
+    "sub sp, sp, #96",
+    "stp x19, x20, [sp, #0]",
+    "stp x21, x22, [sp, #16]",
+    "stp x23, x24, [sp, #32]",
+    "stp x25, x26, [sp, #48]",
+    "stp x27, x28, [sp, #64]",
+    "stp x29, x30, [sp, #80]",
+    "mov x9, sp",
+    "str x9, [x0]",
+    "ldr x9, [x1]",
+    "mov sp, x9",
+    "ldp x19, x20, [sp, #0]",
+    "ldp x21, x22, [sp, #16]",
+    "ldp x23, x24, [sp, #32]",
+    "ldp x25, x26, [sp, #48]",
+    "ldp x27, x28, [sp, #64]",
+    "ldp x29, x30, [sp, #80]",
+    "add sp, sp, #96",
+    "ret",
+    "",
+    ".global thread_trampoline",
+    "thread_trampoline:",
+    "mov x0, x19",
+    "blr x20",
+    "brk #0",
+);
+
+unsafe extern "C" {
+    fn context_switch(from: *mut Context, to: *const Context);
+    fn thread_trampoline();
+}
+
+/// Initial frame, in restore order.
+#[repr(C)]
+struct InitialFrame {
+    x19: u64,
+    x20: u64,
+    x21_x28: [u64; 8],
+    x29: u64,
+    x30: u64,
+}
+
+pub struct Aarch64Context;
+
+impl ContextOps for Aarch64Context {
+    unsafe fn init(&self, ctx: &mut Context, stack_top: u64, entry: ThreadEntry, arg: usize) {
+        let top = stack_top & !0xf;
+        let frame = (top - size_of::<InitialFrame>() as u64) as *mut InitialFrame;
+        unsafe {
+            frame.write(InitialFrame {
+                x19: arg as u64,
+                x20: entry as usize as u64,
+                x21_x28: [0; 8],
+                x29: 0,
+                x30: thread_trampoline as *const () as u64,
+            });
+        }
+        ctx.sp = frame as u64;
+    }
+
+    unsafe fn switch(&self, from: *mut Context, to: *const Context) {
+        unsafe { context_switch(from, to) }
+    }
+}
+
+pub fn register() {
+    static OPS: Aarch64Context = Aarch64Context;
+    unsafe {
+        hal::context::register_context_ops(&OPS);
+    }
+}
@@ -0,0 +1,70 @@
+//! IPIs as GICv3 software-generated interrupts, written through
+//! ICC_SGI1R_EL1. Each `IpiKind` has a reserved SGI; CPUs are addressed
+//! by the MPIDR affinity kept in their per-CPU areas.
+
+use core::arch::asm;
+
+use hal::ipi::{IpiKind, IpiOps};
+
+use crate::percpu;
+
+pub const SGI_RESCHEDULE: u8 = 0;
+pub const SGI_CALL_FUNCTION: u8 = 1;
+pub const SGI_STOP: u8 = 2;
+
+/// ICC_SGI1R_EL1.IRM: every PE but the sender.
+const SGI1R_IRM: u64 = 1 << 40;
+
+struct GicIpi;
+
+static OPS: GicIpi = GicIpi;
+
+fn sgi(kind: IpiKind) -> u8 {
+    match kind {
+        IpiKind::Reschedule => SGI_RESCHEDULE,
+        IpiKind::CallFunction => SGI_CALL_FUNCTION,
+        IpiKind::Stop => SGI_STOP,
+    }
+}
+
+fn write_sgi1r(v: u64) {
+    unsafe {
+        asm!(
+            "msr S3_0_C12_C11_5, {}",
+            "isb",
+            in(reg) v,
+            options(nostack, preserves_flags)
+        );
+    }
+}
+
+/// ICC_SGI1R_EL1 value targeting the single PE with affinity `mpidr`.
+fn sgi1r_for(mpidr: u64, intid: u8) -> u64 {
+    let aff0 = mpidr & 0xf;
+    let aff1 = (mpidr >> 8) & 0xff;
+    let aff2 = (mpidr >> 16) & 0xff;
+    let aff3 = (mpidr >> 32) & 0xff;
+    (1 << aff0) | (aff1 << 16) | ((intid as u64 & 0xf) << 24) | (aff2 << 32) | (aff3 << 48)
+}
+
+impl IpiOps for GicIpi {
+    fn send_one(&self, cpu: usize, kind: IpiKind) {
+        let hdr = &percpu::area(cpu).hdr;
+        if !hdr.is_present() {
+            return;
+        }
+        write_sgi1r(sgi1r_for(hdr.hw_id(), sgi(kind)));
+    }
+
+    fn broadcast(&self, kind: IpiKind) {
+        write_sgi1r(SGI1R_IRM | ((sgi(kind) as u64) << 24));
+    }
+}
+
+/// Register SGIs as the IPI backend. Call once the GIC CPU interface is
+/// enabled; until then ICC_SGI1R_EL1 traps.
+pub fn register() {
+    unsafe {
+        hal::ipi::register_ipi_ops(&OPS);
+    }
+}
@@ -3,8 +3,15 @@ pub const ARCH_NAME: &str = "aarch64";
 
 use bootabi::BootInfo;
 
+pub mod context;
+pub mod ipi;
+pub mod percpu;
+
 pub fn early_init() {
     disable_interrupts();
+    unsafe {
+        percpu::install(0);
+    }
 }
 
 pub fn init(_boot: &BootInfo) {
@@ -13,8 +20,58 @@ pub fn init(_boot: &BootInfo) {
 
 pub unsafe fn init_core() {
     // Later: set exception vectors and CPU state.
+    context::register();
+}
+
+/// Per-CPU setup on an application processor. Interrupts stay disabled.
+///
+/// # Safety
+///
+/// Call once on each AP, after the BSP's setup is done.
+pub unsafe fn init_ap(cpu: usize) {
+    // Later: exception vectors, MMU state and the GIC CPU interface.
+    disable_interrupts();
+    unsafe {
+        percpu::install(cpu);
+    }
+}
+
+/// Switch to the stack ending at `stack_top` and call `f(arg)` on it. The
+/// old stack is abandoned.
+///
+/// # Safety
+///
+/// The stack ending at `stack_top` is unused, and nothing on the
+/// current stack is referenced again.
+pub unsafe fn call_on_stack(stack_top: u64, f: extern "C" fn(usize) -> !, arg: usize) -> ! {
+    unsafe {
+        core::arch::asm!(
+            "mov sp, {top}",
+            "mov x29, xzr",
+            "mov x30, xzr",
+            "br {f}",
+            top = in(reg) stack_top & !0xf,
+            f = in(reg) f,
+            in("x0") arg,
+            options(noreturn)
+        )
+    }
 }
 
+/// The generic timer counter is synchronized across CPUs by the
+/// architecture; there is nothing to measure.
+pub fn bsp_clock_sync() -> hal::time::ClockSync {
+    hal::time::ClockSync {
+        offset: 0,
+        adjusted: false,
+        max_warp: 0,
+        stable: true,
+        responded: true,
+    }
+}
+
+pub fn ap_clock_sync() {}
+
 pub unsafe fn init_gdt_tss() {
     // Not applicable on aarch64.
 }
@@ -27,23 +84,69 @@ pub fn init_pic() {
     // Not applicable on aarch64.
 }
 
-pub fn init_time_source() -> bool {
+pub fn init_time_source(_boot: &BootInfo) -> bool {
     false
 }
 
 pub unsafe fn init_irqs(_boot: &BootInfo, _has_time: bool) -> bool {
-    // Later: configure interrupt controller and timers.
+    // Later: configure interrupt controller and timers, then
+    // `ipi::register()` and device line ops for GIC SPIs.
     false
 }
 
+/// Logical index of the executing CPU, from its per-CPU area.
+#[inline(always)]
+pub fn cpu_index() -> usize {
+    percpu::index()
+}
+
+pub fn send_nmi_all_others() {
+    // Later: GIC pseudo-NMI via interrupt priority masking.
+}
+
 pub fn cpu_relax() {
     unsafe { core::arch::asm!("yield", options(nomem, nostack, preserves_flags)) }
 }
 
 pub fn disable_interrupts() {
-    unsafe { core::arch::asm!("msr daifset, #2", options(nomem, nostack, preserves_flags)) }
+    unsafe { core::arch::asm!("msr daifset, #2", options(nostack, preserves_flags)) }
 }
 
 pub fn enable_interrupts() {
-    unsafe { core::arch::asm!("msr daifclr, #2", options(nomem, nostack, preserves_flags)) }
+    unsafe { core::arch::asm!("msr daifclr, #2", options(nostack, preserves_flags)) }
+}
+
+/// Whether IRQs are unmasked (DAIF.I clear).
+#[inline(always)]
+pub fn interrupts_enabled() -> bool {
+    let daif: u64;
+    unsafe {
+        core::arch::asm!("mrs {}, daif", out(reg) daif, options(nomem, nostack, preserves_flags));
+    }
+    (daif & (1 << 7)) == 0
+}
+
+/// Wait for an interrupt, then unmask IRQs so it is taken. Call with IRQs
+/// masked; a pending IRQ still wakes `wfi`. No `nomem`, as on x86_64.
+#[inline(always)]
+pub fn wait_for_interrupt() {
+    unsafe { core::arch::asm!("wfi", "msr daifclr, #2", options(nostack)) }
+}
+
+/// Mask IRQs and return the previous DAIF for `irq_restore`.
+#[inline(always)]
+pub fn irq_save() -> usize {
+    let daif: u64;
+    unsafe {
+        core::arch::asm!("mrs {}, daif", "msr daifset, #2", out(reg) daif, options(nostack));
+    }
+    daif as usize
+}
+
+/// Restore the DAIF state returned by `irq_save`.
+#[inline(always)]
+pub fn irq_restore(state: usize) {
+    unsafe {
+        core::arch::asm!("msr daif, {}", in(reg) state as u64, options(nostack));
+    }
 }
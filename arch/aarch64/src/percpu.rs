@@ -0,0 +1,95 @@
+//! Per-CPU areas.
+//!
+//! Each CPU owns one `CpuArea`, found through TPIDR_EL1. The area starts
+//! with the hal `PerCpu` header, so the logical CPU index is one load off
+//! that register.
+
+use core::arch::asm;
+
+use hal::cpu::{MAX_CPUS, PERCPU_INDEX_OFFSET, PerCpu, PerCpuOps};
+
+#[repr(C, align(64))]
+pub struct CpuArea {
+    /// Must stay first: TPIDR_EL1 holds the area's address.
+    pub hdr: PerCpu,
+}
+
+impl CpuArea {
+    const fn new() -> Self {
+        Self { hdr: PerCpu::new() }
+    }
+}
+
+static AREAS: [CpuArea; MAX_CPUS] = [const { CpuArea::new() }; MAX_CPUS];
+
+struct Aarch64PerCpu;
+
+impl PerCpuOps for Aarch64PerCpu {
+    fn this_cpu(&self) -> &'static PerCpu {
+        &this().hdr
+    }
+
+    fn cpu(&self, index: usize) -> &'static PerCpu {
+        &AREAS[index].hdr
+    }
+}
+
+static OPS: Aarch64PerCpu = Aarch64PerCpu;
+
+/// MPIDR_EL1 affinity fields (Aff3 and Aff2..Aff0).
+fn mpidr_affinity() -> u64 {
+    let mpidr: u64;
+    unsafe {
+        asm!("mrs {}, mpidr_el1", out(reg) mpidr, options(nomem, nostack, preserves_flags));
+    }
+    mpidr & 0xff_00ff_ffff
+}
+
+/// Make the executing CPU logical CPU `index`: stamp its area and point
+/// TPIDR_EL1 at it. Interrupts must be disabled.
+///
+/// # Safety
+///
+/// No other CPU has installed `index`, and interrupts are disabled.
+pub unsafe fn install(index: usize) {
+    let area = &AREAS[index];
+    area.hdr.init(index, mpidr_affinity());
+    unsafe {
+        asm!("msr tpidr_el1, {}", in(reg) area as *const CpuArea as u64, options(nostack, preserves_flags));
+        if index == 0 {
+            hal::cpu::register_percpu_ops(&OPS);
+        }
+    }
+}
+
+/// Area of logical CPU `cpu`.
+#[inline(always)]
+pub fn area(cpu: usize) -> &'static CpuArea {
+    &AREAS[cpu]
+}
+
+/// Area of the executing CPU.
+#[inline(always)]
+pub fn this() -> &'static CpuArea {
+    let p: *const CpuArea;
+    unsafe {
+        asm!("mrs {}, tpidr_el1", out(reg) p, options(nomem, nostack, preserves_flags));
+        &*p
+    }
+}
+
+/// Logical index of the executing CPU.
+#[inline(always)]
+pub fn index() -> usize {
+    let v: usize;
+    unsafe {
+        asm!(
+            "mrs {v}, tpidr_el1",
+            "ldr {v}, [{v}, #{off}]",
+            v = out(reg) v,
+            off = const PERCPU_INDEX_OFFSET,
+            options(readonly, nostack, preserves_flags)
+        );
+    }
+    v
+}
@@ -0,0 +1,239 @@
+//! Minimal ACPI table discovery.
+//!
+//! Only what the arch layer needs to find platform timers: walk the
+//! RSDT/XSDT from the RSDP and read a handful of fixed FADT fields.
+//! Tables are accessed through the HHDM.
+
+use core::ptr::read_unaligned;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use crate::hhdm_offset;
+
+const SDT_HEADER_LEN: usize = 36;
+
+const RSDP_REVISION: usize = 15;
+const RSDP_RSDT_ADDR: usize = 16;
+const RSDP_XSDT_ADDR: usize = 24;
+
+const FADT_PM_TMR_BLK: usize = 76;
+const FADT_CENTURY: usize = 108;
+const FADT_FLAGS: usize = 112;
+const FADT_X_PM_TMR_BLK: usize = 208;
+
+const FADT_FLAG_TMR_VAL_EXT: u32 = 1 << 8;
+
+const HPET_BASE_ADDR: usize = 40;
+const HPET_MIN_TICK: usize = 53;
+
+const MADT_ENTRIES: usize = 44;
+const MADT_TYPE_IOAPIC: u8 = 1;
+
+const GAS_SPACE_SYSTEM_MEMORY: u8 = 0;
+const GAS_SPACE_SYSTEM_IO: u8 = 1;
+
+/// Virtual address of the RSDP (0 = no ACPI).
+static RSDP: AtomicU64 = AtomicU64::new(0);
+
+/// Generic Address Structure as found in FADT/HPET tables.
+#[derive(Clone, Copy)]
+pub struct GenericAddress {
+    pub space_id: u8,
+    pub bit_width: u8,
+    pub bit_offset: u8,
+    pub access_size: u8,
+    pub address: u64,
+}
+
+#[derive(Clone, Copy)]
+pub struct PmTimerBlock {
+    pub port: u16,
+    /// Counter is 32 bits wide (otherwise 24).
+    pub ext32: bool,
+}
+
+#[derive(Clone, Copy)]
+pub struct HpetBlock {
+    /// Physical MMIO base of the register block.
+    pub base: u64,
+    /// Minimum comparator distance (in counter ticks) that still fires.
+    pub min_tick: u16,
+}
+
+#[derive(Clone, Copy)]
+pub struct IoApicEntry {
+    pub id: u8,
+    /// Physical MMIO base.
+    pub addr: u64,
+    /// First global system interrupt handled by this IOAPIC.
+    pub gsi_base: u32,
+}
+
+/// Convert a bootloader-provided ACPI pointer (physical or HHDM) to virtual.
+#[inline(always)]
+pub(crate) fn to_virt(addr: u64) -> u64 {
+    let hhdm = hhdm_offset();
+    if addr >= hhdm { addr } else { addr + hhdm }
+}
+
+pub fn init(rsdp: u64) {
+    if rsdp == 0 || hhdm_offset() == 0 {
+        return;
+    }
+    let virt = to_virt(rsdp);
+    let sig = unsafe { read_unaligned(virt as *const [u8; 8]) };
+    if &sig != b"RSD PTR " {
+        return;
+    }
+    RSDP.store(virt, Ordering::Relaxed);
+}
+
+#[inline(always)]
+unsafe fn read<T: Copy>(base: u64, off: usize) -> T {
+    unsafe { read_unaligned((base + off as u64) as *const T) }
+}
+
+fn table_matches(table: u64, sig: &[u8; 4]) -> bool {
+    let found: [u8; 4] = unsafe { read(table, 0) };
+    &found == sig
+}
+
+/// Find the first table with signature `sig`; returns its virtual address.
+pub fn find_table(sig: &[u8; 4]) -> Option<u64> {
+    let rsdp = RSDP.load(Ordering::Relaxed);
+    if rsdp == 0 {
+        return None;
+    }
+
+    let revision: u8 = unsafe { read(rsdp, RSDP_REVISION) };
+    let xsdt: u64 = if revision >= 2 {
+        unsafe { read(rsdp, RSDP_XSDT_ADDR) }
+    } else {
+        0
+    };
+    let (root, entry_size) = if xsdt != 0 {
+        (to_virt(xsdt), 8usize)
+    } else {
+        let rsdt: u32 = unsafe { read(rsdp, RSDP_RSDT_ADDR) };
+        if rsdt == 0 {
+            return None;
+        }
+        (to_virt(rsdt as u64), 4usize)
+    };
+
+    let len: u32 = unsafe { read(root, 4) };
+    let count = (len as usize).saturating_sub(SDT_HEADER_LEN) / entry_size;
+    for i in 0..count {
+        let off = SDT_HEADER_LEN + i * entry_size;
+        let phys = if entry_size == 8 {
+            unsafe { read::<u64>(root, off) }
+        } else {
+            unsafe { read::<u32>(root, off) as u64 }
+        };
+        if phys == 0 {
+            continue;
+        }
+        let table = to_virt(phys);
+        if table_matches(table, sig) {
+            return Some(table);
+        }
+    }
+    None
+}
+
+/// Length of a table found by `find_table` (from its SDT header).
+pub fn table_len(table: u64) -> usize {
+    unsafe { read::<u32>(table, 4) as usize }
+}
+
+pub fn read_gas(table: u64, off: usize) -> GenericAddress {
+    unsafe {
+        GenericAddress {
+            space_id: read(table, off),
+            bit_width: read(table, off + 1),
+            bit_offset: read(table, off + 2),
+            access_size: read(table, off + 3),
+            address: read(table, off + 4),
+        }
+    }
+}
+
+/// ACPI PM timer I/O block from the FADT.
+pub fn pm_timer() -> Option<PmTimerBlock> {
+    let fadt = find_table(b"FACP")?;
+    let len = table_len(fadt);
+    if len < FADT_FLAGS + 4 {
+        return None;
+    }
+    let flags: u32 = unsafe { read(fadt, FADT_FLAGS) };
+    let ext32 = (flags & FADT_FLAG_TMR_VAL_EXT) != 0;
+
+    if len >= FADT_X_PM_TMR_BLK + 12 {
+        let gas = read_gas(fadt, FADT_X_PM_TMR_BLK);
+        if gas.space_id == GAS_SPACE_SYSTEM_IO && gas.address != 0 && gas.address <= 0xffff {
+            return Some(PmTimerBlock {
+                port: gas.address as u16,
+                ext32,
+            });
+        }
+    }
+
+    let blk: u32 = unsafe { read(fadt, FADT_PM_TMR_BLK) };
+    if blk == 0 || blk > 0xffff {
+        return None;
+    }
+    Some(PmTimerBlock {
+        port: blk as u16,
+        ext32,
+    })
+}
+
+/// CMOS index of the RTC century register, if the FADT names one.
+pub fn rtc_century_index() -> Option<u8> {
+    let fadt = find_table(b"FACP")?;
+    if table_len(fadt) <= FADT_CENTURY {
+        return None;
+    }
+    let index: u8 = unsafe { read(fadt, FADT_CENTURY) };
+    if index == 0 { None } else { Some(index) }
+}
+
+/// First HPET block from the ACPI "HPET" table.
+pub fn hpet() -> Option<HpetBlock> {
+    let table = find_table(b"HPET")?;
+    if table_len(table) < HPET_MIN_TICK + 2 {
+        return None;
+    }
+    let gas = read_gas(table, HPET_BASE_ADDR);
+    if gas.space_id != GAS_SPACE_SYSTEM_MEMORY || gas.address == 0 {
+        return None;
+    }
+    Some(HpetBlock {
+        base: gas.address,
+        min_tick: unsafe { read(table, HPET_MIN_TICK) },
+    })
+}
+
+/// First IOAPIC listed in the MADT.
+pub fn ioapic() -> Option<IoApicEntry> {
+    let madt = find_table(b"APIC")?;
+    let len = table_len(madt);
+    let mut off = MADT_ENTRIES;
+    while off + 2 <= len {
+        let kind: u8 = unsafe { read(madt, off) };
+        let entry_len: u8 = unsafe { read(madt, off + 1) };
+        if entry_len < 2 {
+            break;
+        }
+        if kind == MADT_TYPE_IOAPIC && entry_len >= 12 {
+            return Some(unsafe {
+                IoApicEntry {
+                    id: read(madt, off + 2),
+                    addr: read::<u32>(madt, off + 4) as u64,
+                    gsi_base: read(madt, off + 8),
+                }
+            });
+        }
+        off += entry_len as usize;
+    }
+    None
+}
@@ -12,6 +12,8 @@ const LAPIC_ID: u32 = 0x020;
 const LAPIC_EOI: u32 = 0x0b0;
 const LAPIC_SVR: u32 = 0x0f0;
 const LAPIC_LVT_TIMER: u32 = 0x320;
+const LAPIC_TIMER_INIT: u32 = 0x380;
+const LAPIC_TIMER_CUR: u32 = 0x390;
 const LAPIC_TIMER_DIV: u32 = 0x3e0;
 const LAPIC_ICR_LOW: u32 = 0x300;
 const LAPIC_ICR_HIGH: u32 = 0x310;
@@ -19,8 +21,13 @@ const LAPIC_ICR_HIGH: u32 = 0x310;
 const SVR_APIC_ENABLE: u32 = 1 << 8;
 
 const LVT_MASKED: u32 = 1 << 16;
+const LVT_MODE_ONE_SHOT: u32 = 0b00 << 17;
+const LVT_MODE_PERIODIC: u32 = 0b01 << 17;
 const LVT_MODE_TSC_DEADLINE: u32 = 0b10 << 17;
 const ICR_DELIVERY_PENDING: u32 = 1 << 12;
+const ICR_DELIVERY_FIXED: u32 = 0b000 << 8;
+const ICR_DELIVERY_NMI: u32 = 0b100 << 8;
+const ICR_DEST_ALL_BUT_SELF: u32 = 0b11 << 18;
 
 const APIC_MODE_NONE: u8 = 0;
 const APIC_MODE_XAPIC: u8 = 1;
@@ -36,12 +43,7 @@ pub unsafe fn init(hhdm_offset: u64) -> bool {
         unsafe { wrmsr(IA32_APIC_BASE, new_base) };
         unsafe {
             APIC_MODE = APIC_MODE_X2APIC;
-            let svr = (SVR_APIC_ENABLE) | 0xff;
-            write(LAPIC_SVR, svr);
-            write(LAPIC_TIMER_DIV, 0b1011);
-            let lvt = (TIMER_VEC as u32) | LVT_MODE_TSC_DEADLINE;
-            write(LAPIC_LVT_TIMER, lvt);
-            let _id = read(LAPIC_ID);
+            setup_local();
         }
         return true;
     }
@@ -57,18 +59,91 @@ pub unsafe fn init(hhdm_offset: u64) -> bool {
     unsafe {
         APIC_MODE = APIC_MODE_XAPIC;
         LAPIC_BASE_VIRT = apic_phys.wrapping_add(hhdm_offset);
+        setup_local();
+    }
+    true
+}
+
+/// Enable an AP's local APIC in the mode `init` picked on the BSP.
+///
+/// # Safety
+///
+/// `init` has succeeded on the BSP. Interrupts must be disabled.
+pub unsafe fn init_cpu() -> bool {
+    unsafe {
+        let apic_base = rdmsr(IA32_APIC_BASE);
+        match APIC_MODE {
+            APIC_MODE_X2APIC => wrmsr(
+                IA32_APIC_BASE,
+                apic_base | APIC_BASE_ENABLE | APIC_BASE_X2APIC,
+            ),
+            APIC_MODE_XAPIC => wrmsr(IA32_APIC_BASE, apic_base | APIC_BASE_ENABLE),
+            _ => return false,
+        }
+        setup_local();
+    }
+    true
+}
 
+unsafe fn setup_local() {
+    unsafe {
         let svr = (SVR_APIC_ENABLE) | 0xff;
         write(LAPIC_SVR, svr);
 
         write(LAPIC_TIMER_DIV, 0b1011);
 
-        let lvt = (TIMER_VEC as u32) | LVT_MODE_TSC_DEADLINE;
+        // Timer stays masked until a time source picks its mode.
+        let lvt = (TIMER_VEC as u32) | LVT_MASKED;
         write(LAPIC_LVT_TIMER, lvt);
 
         let _id = read(LAPIC_ID);
     }
-    true
+}
+
+/// Program the timer LVT for TSC-deadline mode (deadline via IA32_TSC_DEADLINE).
+pub fn timer_mode_tsc_deadline() {
+    unsafe { write(LAPIC_LVT_TIMER, (TIMER_VEC as u32) | LVT_MODE_TSC_DEADLINE) }
+}
+
+/// Program the timer LVT for one-shot mode (armed via `timer_set_count`).
+pub fn timer_mode_one_shot() {
+    unsafe { write(LAPIC_LVT_TIMER, (TIMER_VEC as u32) | LVT_MODE_ONE_SHOT) }
+}
+
+/// Program the timer LVT for periodic mode reloading from `count`.
+pub fn timer_mode_periodic(count: u32) {
+    unsafe {
+        write(LAPIC_LVT_TIMER, (TIMER_VEC as u32) | LVT_MODE_PERIODIC);
+        write(LAPIC_TIMER_INIT, count);
+    }
+}
+
+/// Mask the timer and stop any countdown.
+pub fn timer_mask() {
+    unsafe {
+        write(LAPIC_LVT_TIMER, (TIMER_VEC as u32) | LVT_MASKED);
+        write(LAPIC_TIMER_INIT, 0);
+    }
+}
+
+/// Let the timer count down from `count` without raising an interrupt.
+pub fn timer_start_masked(count: u32) {
+    unsafe {
+        write(
+            LAPIC_LVT_TIMER,
+            (TIMER_VEC as u32) | LVT_MODE_ONE_SHOT | LVT_MASKED,
+        );
+        write(LAPIC_TIMER_INIT, count);
+    }
+}
+
+/// Load the initial count (starts one-shot countdown; 0 stops the timer).
+pub fn timer_set_count(count: u32) {
+    unsafe { write(LAPIC_TIMER_INIT, count) }
+}
+
+pub fn timer_current() -> u32 {
+    unsafe { read(LAPIC_TIMER_CUR) }
 }
 
 pub fn cpu_id() -> u32 {
@@ -82,14 +157,44 @@ pub fn cpu_id() -> u32 {
     }
 }
 
+/// Send fixed `vector` to the CPU with APIC ID `dest`.
+pub fn send_ipi(dest: u32, vector: u8) {
+    let icr = (vector as u32) | ICR_DELIVERY_FIXED;
+    unsafe {
+        match APIC_MODE {
+            APIC_MODE_XAPIC => {
+                // An interrupt sending its own IPI between the two writes
+                // would retarget ours.
+                let irq = crate::irq_save();
+                write(LAPIC_ICR_HIGH, dest << 24);
+                write(LAPIC_ICR_LOW, icr);
+                while (read(LAPIC_ICR_LOW) & ICR_DELIVERY_PENDING) != 0 {}
+                crate::irq_restore(irq);
+            }
+            // x2APIC: one 64-bit ICR write, destination in the high half.
+            APIC_MODE_X2APIC => wrmsr(apic_msr(LAPIC_ICR_LOW), ((dest as u64) << 32) | icr as u64),
+            _ => {}
+        }
+    }
+}
+
 pub fn send_ipi_all_others(vector: u8) {
+    send_icr_all_others((vector as u32) | ICR_DELIVERY_FIXED);
+}
+
+/// Deliver an NMI to every other CPU (vector field is ignored).
+pub fn send_nmi_all_others() {
+    send_icr_all_others(ICR_DELIVERY_NMI);
+}
+
+fn send_icr_all_others(icr: u32) {
     unsafe {
         if APIC_MODE == APIC_MODE_NONE {
             return;
         }
     }
 
-    let icr = (vector as u32) | (0b11 << 18);
+    let icr = icr | ICR_DEST_ALL_BUT_SELF;
 
     unsafe {
         if APIC_MODE == APIC_MODE_XAPIC {
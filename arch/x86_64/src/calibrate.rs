@@ -0,0 +1,25 @@
+//! Frequency calibration against platform reference timers.
+
+use crate::{hpet, pit, pmtimer};
+
+/// Length of one calibration window.
+const CALIBRATE_MS: u32 = 10;
+/// Windows per reference; the lowest reading wins (SMIs only inflate).
+const ROUNDS: usize = 3;
+
+/// Measure the frequency of the counter behind `read` against the best
+/// reference available: HPET, then the ACPI PM timer, then the PIT.
+pub fn measure_hz(read: impl Fn() -> u64 + Copy) -> Option<u64> {
+    min_of(|| hpet::measure_hz(CALIBRATE_MS, read))
+        .or_else(|| min_of(|| pmtimer::measure_hz(CALIBRATE_MS, read)))
+        .or_else(|| min_of(|| pit::measure_hz(CALIBRATE_MS, read)))
+}
+
+fn min_of(mut f: impl FnMut() -> Option<u64>) -> Option<u64> {
+    let mut best: Option<u64> = None;
+    for _ in 0..ROUNDS {
+        let hz = f()?;
+        best = Some(best.map_or(hz, |b| b.min(hz)));
+    }
+    best
+}
@@ -0,0 +1,88 @@
+//! Kernel thread context switch.
+//!
+//! `context_switch` pushes the SysV callee-saved registers on the current
+//! stack, stores RSP into `from`, loads RSP from `to` and pops the same
+//! frame. A new thread's frame returns into `thread_trampoline`, which
+//! calls `entry(arg)` from r12/r13.
+
+use core::arch::global_asm;
+
+use hal::context::{Context, ContextOps, ThreadEntry};
+
+global_asm!(
+    ".global context_switch",
+    "context_switch:",
+    "push rbp",
+    "push rbx",
+    "push r12",
+    "push r13",
+    "push r14",
+    "push r15",
+    "mov [rdi], rsp",
+    "mov rsp, [rsi]",
+    "pop r15",
+    "pop r14",
+    "pop r13",
+    "pop r12",
+    "pop rbx",
+    "pop rbp",
+    "ret",
+    "",
+    ".global thread_trampoline",
+    "thread_trampoline:",
+    "mov rdi, r12",
+    "call r13",
+    "ud2",
+);
+
+unsafe extern "C" {
+    fn context_switch(from: *mut Context, to: *const Context);
+    fn thread_trampoline();
+}
+
+/// Initial frame, in pop order.
+#[repr(C)]
+struct InitialFrame {
+    r15: u64,
+    r14: u64,
+    r13: u64,
+    r12: u64,
+    rbx: u64,
+    rbp: u64,
+    ret: u64,
+    /// Keeps RSP at 16n+8 on entry, as after a `call`.
+    _align: u64,
+}
+
+pub struct X86Context;
+
+impl ContextOps for X86Context {
+    unsafe fn init(&self, ctx: &mut Context, stack_top: u64, entry: ThreadEntry, arg: usize) {
+        let top = stack_top & !0xf;
+        let frame = (top - size_of::<InitialFrame>() as u64) as *mut InitialFrame;
+        unsafe {
+            frame.write(InitialFrame {
+                r15: 0,
+                r14: 0,
+                r13: entry as usize as u64,
+                r12: arg as u64,
+                rbx: 0,
+                rbp: 0,
+                ret: thread_trampoline as *const () as u64,
+                _align: 0,
+            });
+        }
+        ctx.sp = frame as u64;
+    }
+
+    unsafe fn switch(&self, from: *mut Context, to: *const Context) {
+        unsafe { context_switch(from, to) }
+    }
+}
+
+pub fn register() {
+    static OPS: X86Context = X86Context;
+    unsafe {
+        hal::context::register_context_ops(&OPS);
+    }
+}
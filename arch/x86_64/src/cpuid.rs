@@ -22,12 +22,49 @@ pub fn has_invariant_tsc() -> bool {
     (edx & (1 << 8)) != 0
 }
 
+/// CPUID.1H:EDX[4] time-stamp counter present
+pub fn has_tsc() -> bool {
+    let (_, _, _, edx) = cpuid(1, 0);
+    (edx & (1 << 4)) != 0
+}
+
+/// CPUID.1H:ECX[24] LAPIC timer TSC-deadline mode
+pub fn has_tsc_deadline() -> bool {
+    let (_, _, ecx, _) = cpuid(1, 0);
+    (ecx & (1 << 24)) != 0
+}
+
+/// CPUID.(EAX=7,ECX=0):EBX[1] IA32_TSC_ADJUST MSR
+pub fn has_tsc_adjust() -> bool {
+    if !has_leaf(7) {
+        return false;
+    }
+    let (_, ebx, _, _) = cpuid(7, 0);
+    (ebx & (1 << 1)) != 0
+}
+
+/// CPUID.80000001H:EDX[27] RDTSCP and IA32_TSC_AUX
+pub fn has_rdtscp() -> bool {
+    let (max_ext, _, _, _) = cpuid(0x8000_0000, 0);
+    if max_ext < 0x8000_0001 {
+        return false;
+    }
+    let (_, _, _, edx) = cpuid(0x8000_0001, 0);
+    (edx & (1 << 27)) != 0
+}
+
 /// CPUID.1H:ECX[21] x2APIC support
 pub fn has_x2apic() -> bool {
     let (_, _, ecx, _) = cpuid(1, 0);
     (ecx & (1 << 21)) != 0
 }
 
+/// CPUID.1H:EDX[7] machine-check exception and EDX[14] machine-check architecture
+pub fn has_mca() -> bool {
+    let (_, _, _, edx) = cpuid(1, 0);
+    (edx & (1 << 7)) != 0 && (edx & (1 << 14)) != 0
+}
+
 /// CPUID.80000001H:EDX[20] NX bit support.
 pub fn has_nx() -> bool {
     let (max_ext, _, _, _) = cpuid(0x8000_0000, 0);
@@ -64,3 +101,17 @@ pub fn tsc_hz() -> Option<u64> {
     }
     None
 }
+
+/// APIC ID of the executing CPU, readable before the LAPIC is set up: the
+/// x2APIC ID from CPUID.0BH:EDX when that leaf is valid, else the initial
+/// APIC ID in CPUID.1H:EBX[31:24].
+pub fn apic_id() -> u32 {
+    if has_leaf(0xb) {
+        let (_, ebx, _, edx) = cpuid(0xb, 0);
+        if ebx != 0 {
+            return edx;
+        }
+    }
+    let (_, ebx, _, _) = cpuid(1, 0);
+    ebx >> 24
+}
@@ -1,7 +1,7 @@
 use core::arch::asm;
 use core::mem::size_of;
 
-use crate::tss;
+use crate::{percpu, tss};
 use core::cell::SyncUnsafeCell;
 
 #[repr(C, packed)]
@@ -27,18 +27,27 @@ static GDT: SyncUnsafeCell<[GdtEntry; 3]> = SyncUnsafeCell::new([
     GdtEntry(0), // data
 ]);
 
-static mut TSS_DESC: TssDesc = TssDesc { lo: 0, hi: 0 };
-
+/// A CPU's GDT: the code/data entries are shared, the TSS descriptor
+/// points at that CPU's TSS. Lives in the per-CPU area.
 #[repr(C, align(16))]
-struct FullTable {
+pub(crate) struct FullTable {
     gdt: [GdtEntry; 3],
     tss: TssDesc,
 }
 
-static mut FULL: FullTable = FullTable {
-    gdt: [GdtEntry(0); 3],
-    tss: TssDesc { lo: 0, hi: 0 },
-};
+impl FullTable {
+    pub(crate) const fn new() -> Self {
+        Self {
+            gdt: [GdtEntry(0); 3],
+            tss: TssDesc { lo: 0, hi: 0 },
+        }
+    }
+}
+
+#[inline(always)]
+fn full_table(cpu: usize) -> *mut FullTable {
+    percpu::area(cpu).gdt.get()
+}
 
 pub const KERNEL_CS: u16 = 0x08;
 pub const KERNEL_DS: u16 = 0x10;
@@ -72,16 +81,26 @@ unsafe fn make_tss_desc(tss_addr: u64, tss_len: u32) -> TssDesc {
     TssDesc { lo, hi }
 }
 
-pub unsafe fn build_gdt_tss(rsp0_top: u64) {
+/// Build `cpu`'s TSS and GDT without loading them.
+///
+/// # Safety
+///
+/// Only `cpu` itself, during its bring-up, may touch its tables.
+pub unsafe fn build_gdt_tss(cpu: usize, rsp0_top: u64) {
     unsafe {
-        init_tss_only(rsp0_top);
-        build_gdt_entries();
-        build_full_table();
+        init_tss_only(cpu, rsp0_top);
+        build_gdt_entries(cpu);
+        build_full_table(cpu);
     }
 }
 
-pub unsafe fn init_tss_only(rsp0_top: u64) {
-    tss::init_tss(rsp0_top);
+/// Set up `cpu`'s TSS with `rsp0_top` as its ring-0 stack.
+///
+/// # Safety
+///
+/// Only `cpu` itself, during its bring-up, may touch its tables.
+pub unsafe fn init_tss_only(cpu: usize, rsp0_top: u64) {
+    tss::init_tss(cpu, rsp0_top);
 }
 
 pub unsafe fn build_gdt_entries_only() {
@@ -93,36 +112,48 @@ pub unsafe fn build_gdt_entries_only() {
     }
 }
 
-pub unsafe fn build_tss_desc() {
+/// Point `cpu`'s GDT at its TSS.
+///
+/// # Safety
+///
+/// Only `cpu` itself, during its bring-up, may touch its tables.
+pub unsafe fn build_tss_desc(cpu: usize) {
     unsafe {
-        let tss_addr = tss::tss_ptr() as u64;
+        let tss_addr = tss::tss_ptr(cpu) as u64;
         if !is_canonical(tss_addr) {
             return;
         }
         let desc = make_tss_desc(tss_addr, size_of::<tss::Tss64>() as u32);
-        core::ptr::write_unaligned(core::ptr::addr_of_mut!(TSS_DESC.lo), desc.lo);
-        core::ptr::write_unaligned(core::ptr::addr_of_mut!(TSS_DESC.hi), desc.hi);
+        let full = full_table(cpu);
+        core::ptr::write_unaligned(core::ptr::addr_of_mut!((*full).tss.lo), desc.lo);
+        core::ptr::write_unaligned(core::ptr::addr_of_mut!((*full).tss.hi), desc.hi);
     }
 }
 
-pub unsafe fn build_gdt_entries() {
+/// Fill the shared code and data entries and `cpu`'s TSS descriptor.
+///
+/// # Safety
+///
+/// Only `cpu` itself, during its bring-up, may touch its tables.
+pub unsafe fn build_gdt_entries(cpu: usize) {
     unsafe {
         build_gdt_entries_only();
-        build_tss_desc();
+        build_tss_desc(cpu);
     }
 }
 
-pub unsafe fn build_full_table() {
+/// Copy the shared entries into `cpu`'s table.
+///
+/// # Safety
+///
+/// Only `cpu` itself, during its bring-up, may touch its tables.
+pub unsafe fn build_full_table(cpu: usize) {
     unsafe {
         let gdt = &*GDT.get();
-        core::ptr::write_volatile(&mut FULL.gdt[0], gdt[0]);
-        core::ptr::write_volatile(&mut FULL.gdt[1], gdt[1]);
-        core::ptr::write_volatile(&mut FULL.gdt[2], gdt[2]);
-
-        let lo = core::ptr::read_unaligned(core::ptr::addr_of!(TSS_DESC.lo));
-        let hi = core::ptr::read_unaligned(core::ptr::addr_of!(TSS_DESC.hi));
-        core::ptr::write_unaligned(core::ptr::addr_of_mut!(FULL.tss.lo), lo);
-        core::ptr::write_unaligned(core::ptr::addr_of_mut!(FULL.tss.hi), hi);
+        let full = full_table(cpu);
+        core::ptr::write_volatile(&mut (*full).gdt[0], gdt[0]);
+        core::ptr::write_volatile(&mut (*full).gdt[1], gdt[1]);
+        core::ptr::write_volatile(&mut (*full).gdt[2], gdt[2]);
     }
 }
 
@@ -137,13 +168,18 @@ fn is_canonical(addr: u64) -> bool {
     }
 }
 
-pub unsafe fn load_gdt() {
+/// Load `cpu`'s table into GDTR.
+///
+/// # Safety
+///
+/// Call on `cpu`, after `build_full_table(cpu)`.
+pub unsafe fn load_gdt(cpu: usize) {
     unsafe {
         const GDT_BYTES: usize =
             core::mem::size_of::<GdtEntry>() * 3 + core::mem::size_of::<TssDesc>();
         let gdtr = Gdtr {
             limit: (GDT_BYTES - 1) as u16,
-            base: (&raw const FULL as *const _ as u64),
+            base: full_table(cpu) as u64,
         };
 
         asm!("lgdt [{}]", in(reg) &gdtr, options(readonly, nostack));
@@ -152,7 +188,9 @@ pub unsafe fn load_gdt() {
 
 pub unsafe fn reload_segments() {
     unsafe {
-        // Reload segment registers (CS needs far jump)
+        // Reload segment registers (CS needs far jump). GS is left alone:
+        // loading it would clear the GS base that points at the per-CPU
+        // area.
         asm!(
             "push {cs}",
             "lea rax, [rip + 2f]",
@@ -169,7 +207,6 @@ pub unsafe fn reload_segments() {
             "mov es, ax",
             "mov ss, ax",
             "mov fs, ax",
-            "mov gs, ax",
             ds = const KERNEL_DS,
             options(nostack)
         );
@@ -178,15 +215,20 @@ pub unsafe fn reload_segments() {
 
 pub unsafe fn load_tss() {
     unsafe {
-        // Selector points to the TSS descriptor in FULL after 3 entries => 0x18
+        // Selector points to the TSS descriptor in the CPU's table after 3 entries => 0x18
         asm!("ltr ax", in("ax") TSS_SEL, options(nostack));
     }
 }
 
-pub unsafe fn init_gdt_and_tss(rsp0_top: u64) {
+/// Build and load `cpu`'s GDT and TSS.
+///
+/// # Safety
+///
+/// Call on `cpu`, during its bring-up, with interrupts disabled.
+pub unsafe fn init_gdt_and_tss(cpu: usize, rsp0_top: u64) {
     unsafe {
-        build_gdt_tss(rsp0_top);
-        load_gdt();
+        build_gdt_tss(cpu, rsp0_top);
+        load_gdt(cpu);
         reload_segments();
         load_tss();
     }
@@ -0,0 +1,270 @@
+//! HPET: monotonic main counter plus comparator-based one-shot events.
+//!
+//! Discovered from the ACPI "HPET" table and accessed through the HHDM.
+//! Comparator 0 is the event source; its interrupt is delivered straight
+//! to the LAPIC via FSB (MSI) when supported, otherwise through an IOAPIC
+//! pin from the comparator's routing mask. Events go to the CPU that
+//! activated the timer, so with more than one CPU the HPET is rated out
+//! and only serves as a reference counter; the per-CPU LAPIC timers
+//! carry the events.
+
+use core::ptr::{read_volatile, write_volatile};
+use core::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+
+use hal::time::TimerOps;
+
+use crate::idt::TIMER_VEC;
+use crate::{acpi, apic, ioapic};
+
+const REG_CAP: u64 = 0x000;
+const REG_CONFIG: u64 = 0x010;
+const REG_COUNTER: u64 = 0x0f0;
+
+const fn reg_timer_config(n: u64) -> u64 {
+    0x100 + 0x20 * n
+}
+const fn reg_timer_comparator(n: u64) -> u64 {
+    0x108 + 0x20 * n
+}
+const fn reg_timer_fsb(n: u64) -> u64 {
+    0x110 + 0x20 * n
+}
+
+const CAP_COUNT_64: u64 = 1 << 13;
+
+const CONFIG_ENABLE: u64 = 1 << 0;
+const CONFIG_LEGACY_ROUTE: u64 = 1 << 1;
+
+const TN_INT_ENABLE: u64 = 1 << 2;
+const TN_PERIODIC: u64 = 1 << 3;
+const TN_32BIT_MODE: u64 = 1 << 8;
+const TN_ROUTE_SHIFT: u64 = 9;
+const TN_ROUTE_MASK: u64 = 0x1f << TN_ROUTE_SHIFT;
+const TN_FSB_ENABLE: u64 = 1 << 14;
+const TN_FSB_CAP: u64 = 1 << 15;
+
+/// LAPIC MSI address window.
+const MSI_ADDR_BASE: u64 = 0xfee0_0000;
+
+/// First non-ISA GSI; preferred so legacy lines stay free.
+const FIRST_PCI_GSI: u32 = 16;
+
+const EVENT_TIMER: u64 = 0;
+
+/// Comparator slack (counter ticks) if the ACPI table gives none.
+const MIN_TICK_FALLBACK: u64 = 128;
+
+const RATING: u32 = 250;
+
+/// Comparator interrupt delivery: an IOAPIC GSI or one of these.
+const DELIVERY_NONE: u32 = u32::MAX;
+const DELIVERY_FSB: u32 = u32::MAX - 1;
+
+static BASE: AtomicU64 = AtomicU64::new(0);
+static HZ: AtomicU64 = AtomicU64::new(0);
+static COUNTER_64: AtomicBool = AtomicBool::new(false);
+static MIN_TICK: AtomicU64 = AtomicU64::new(MIN_TICK_FALLBACK);
+/// Software extension of a 32-bit main counter.
+static LAST_NOW: AtomicU64 = AtomicU64::new(0);
+static DELIVERY: AtomicU32 = AtomicU32::new(DELIVERY_NONE);
+/// More than one CPU will run; the single comparator cannot serve them.
+static SHARED: AtomicBool = AtomicBool::new(false);
+
+pub struct HpetTimer;
+
+/// Find and start the HPET main counter. Returns false if there is none.
+pub fn init() -> bool {
+    let Some(block) = acpi::hpet() else {
+        return false;
+    };
+    BASE.store(acpi::to_virt(block.base), Ordering::Relaxed);
+
+    let cap = unsafe { read(REG_CAP) };
+    let period_fs = cap >> 32;
+    if period_fs == 0 || period_fs > 100_000_000 {
+        BASE.store(0, Ordering::Relaxed);
+        return false;
+    }
+    HZ.store(1_000_000_000_000_000 / period_fs, Ordering::Relaxed);
+    COUNTER_64.store((cap & CAP_COUNT_64) != 0, Ordering::Relaxed);
+    if block.min_tick != 0 {
+        MIN_TICK.store(block.min_tick as u64, Ordering::Relaxed);
+    }
+
+    unsafe {
+        // Quiesce comparator 0 before (re)enabling the counter.
+        let cfg = read(reg_timer_config(EVENT_TIMER));
+        write(
+            reg_timer_config(EVENT_TIMER),
+            cfg & !(TN_INT_ENABLE | TN_PERIODIC | TN_FSB_ENABLE),
+        );
+        let gcfg = read(REG_CONFIG);
+        write(REG_CONFIG, (gcfg & !CONFIG_LEGACY_ROUTE) | CONFIG_ENABLE);
+    }
+    true
+}
+
+#[inline(always)]
+pub fn is_present() -> bool {
+    BASE.load(Ordering::Relaxed) != 0
+}
+
+pub fn hz() -> Option<u64> {
+    let hz = HZ.load(Ordering::Relaxed);
+    if hz == 0 { None } else { Some(hz) }
+}
+
+/// Main counter, extended to 64 bits if the hardware counter is 32-bit.
+#[inline(always)]
+pub fn now() -> u64 {
+    let raw = unsafe { read(REG_COUNTER) };
+    if COUNTER_64.load(Ordering::Relaxed) {
+        return raw;
+    }
+    let last = LAST_NOW.load(Ordering::Acquire);
+    let mut t = (last & !0xffff_ffff) | (raw & 0xffff_ffff);
+    if t < last {
+        t += 1 << 32;
+    }
+    let prev = LAST_NOW.fetch_max(t, Ordering::AcqRel);
+    prev.max(t)
+}
+
+/// Measure the frequency of the counter behind `read_counter` over `ms`
+/// milliseconds of HPET time.
+pub fn measure_hz(ms: u32, read_counter: impl Fn() -> u64) -> Option<u64> {
+    let hz = hz()?;
+    let target = hz * ms as u64 / 1000;
+
+    let h0 = now();
+    let c0 = read_counter();
+    let mut h1 = h0;
+    while h1.wrapping_sub(h0) < target {
+        h1 = now();
+    }
+    let c1 = read_counter();
+
+    let elapsed = h1.wrapping_sub(h0);
+    let delta = c1.wrapping_sub(c0);
+    if elapsed == 0 || delta == 0 {
+        return None;
+    }
+    Some(((delta as u128 * hz as u128) / elapsed as u128) as u64)
+}
+
+/// Decide how comparator 0 interrupts reach a CPU.
+fn pick_delivery(cfg: u64) -> u32 {
+    if (cfg & TN_FSB_CAP) != 0 {
+        return DELIVERY_FSB;
+    }
+    if !ioapic::init() {
+        return DELIVERY_NONE;
+    }
+    let routes = (cfg >> 32) as u32;
+    let usable = |gsi: u32| (routes & (1 << gsi)) != 0 && ioapic::handles(gsi);
+    (FIRST_PCI_GSI..32)
+        .chain(0..FIRST_PCI_GSI)
+        .find(|&gsi| usable(gsi))
+        .unwrap_or(DELIVERY_NONE)
+}
+
+/// Offer the HPET as a timer candidate if its comparator can interrupt.
+/// With `cpus > 1` it is offered at rating 0, as a last resort only.
+pub fn register_timer(cpus: u32) -> bool {
+    static TIMER: HpetTimer = HpetTimer;
+    SHARED.store(cpus > 1, Ordering::Relaxed);
+    if !is_present() {
+        return false;
+    }
+    let cfg = unsafe { read(reg_timer_config(EVENT_TIMER)) };
+    let delivery = pick_delivery(cfg);
+    if delivery == DELIVERY_NONE {
+        return false;
+    }
+    DELIVERY.store(delivery, Ordering::Relaxed);
+    unsafe { hal::time::register_timer(&TIMER) }
+}
+
+impl TimerOps for HpetTimer {
+    fn name(&self) -> &'static str {
+        "hpet"
+    }
+
+    fn rating(&self) -> u32 {
+        if SHARED.load(Ordering::Relaxed) {
+            0
+        } else {
+            RATING
+        }
+    }
+
+    fn now_ticks(&self) -> u64 {
+        now()
+    }
+
+    fn frequency_hz(&self) -> u64 {
+        HZ.load(Ordering::Relaxed)
+    }
+
+    fn arm_one_shot(&self, deadline_ticks: u64) {
+        let min_tick = MIN_TICK.load(Ordering::Relaxed);
+        let mut deadline = deadline_ticks;
+        unsafe {
+            loop {
+                write(reg_timer_comparator(EVENT_TIMER), deadline);
+                // The comparator only matches on equality: if the counter
+                // already passed it, push it out and try again.
+                let now = now();
+                if (deadline as i64).wrapping_sub(now as i64) > 0 {
+                    break;
+                }
+                deadline = now + min_tick;
+            }
+        }
+    }
+
+    fn activate(&self) {
+        let dest = apic::cpu_id();
+        apic::timer_mask();
+        unsafe {
+            let mut cfg = read(reg_timer_config(EVENT_TIMER));
+            cfg &= !(TN_PERIODIC | TN_32BIT_MODE | TN_FSB_ENABLE | TN_ROUTE_MASK);
+            match DELIVERY.load(Ordering::Relaxed) {
+                DELIVERY_NONE => return,
+                DELIVERY_FSB => {
+                    let addr = MSI_ADDR_BASE | ((dest as u64 & 0xff) << 12);
+                    write(reg_timer_fsb(EVENT_TIMER), (addr << 32) | TIMER_VEC as u64);
+                    cfg |= TN_FSB_ENABLE;
+                }
+                gsi => {
+                    ioapic::route(gsi, TIMER_VEC, dest);
+                    cfg |= (gsi as u64) << TN_ROUTE_SHIFT;
+                }
+            }
+            write(reg_timer_config(EVENT_TIMER), cfg | TN_INT_ENABLE);
+        }
+    }
+
+    fn deactivate(&self) {
+        unsafe {
+            let cfg = read(reg_timer_config(EVENT_TIMER));
+            write(reg_timer_config(EVENT_TIMER), cfg & !TN_INT_ENABLE);
+        }
+        let delivery = DELIVERY.load(Ordering::Relaxed);
+        if delivery != DELIVERY_NONE && delivery != DELIVERY_FSB {
+            ioapic::mask(delivery);
+        }
+    }
+}
+
+#[inline(always)]
+unsafe fn read(reg: u64) -> u64 {
+    let base = BASE.load(Ordering::Relaxed);
+    unsafe { read_volatile((base + reg) as *const u64) }
+}
+
+#[inline(always)]
+unsafe fn write(reg: u64, val: u64) {
+    let base = BASE.load(Ordering::Relaxed);
+    unsafe { write_volatile((base + reg) as *mut u64, val) }
+}
@@ -1,6 +1,8 @@
 use core::arch::asm;
 use core::cell::SyncUnsafeCell;
 
+use crate::tss;
+
 #[repr(C, packed)]
 struct Idtr {
     limit: u16,
@@ -42,21 +44,23 @@ unsafe extern "C" {
 
     fn irq_224();
     fn irq_225();
+    fn irq_226();
+    fn irq_227();
+    fn irq_228();
 }
 
 pub const TIMER_VEC: u8 = 0xe0;
 pub const TLB_SHOOTDOWN_VEC: u8 = 0xe1;
+pub const RESCHEDULE_VEC: u8 = 0xe2;
+pub const CALL_FUNCTION_VEC: u8 = 0xe3;
+pub const STOP_VEC: u8 = 0xe4;
 
 pub unsafe fn init_idt() {
     // Exceptions 0..31
     unsafe {
         for vec in 0..32usize {
             let handler = isr_stub_table[vec];
-            set_gate(
-                vec as u8,
-                handler,
-                /*ist=*/ if vec == 8 { 1 } else { 0 },
-            ); // vec 8 = #DF uses IST1
+            set_gate(vec as u8, handler, exception_ist(vec as u8));
         }
 
         // IRQs mapped at 32..47 (we’ll route IOAPIC later)
@@ -68,7 +72,21 @@ pub unsafe fn init_idt() {
         // Install LAPIC timer vector (TSC-deadline)
         set_gate(TIMER_VEC, irq_224 as *const () as u64, 0);
         set_gate(TLB_SHOOTDOWN_VEC, irq_225 as *const () as u64, 0);
+        set_gate(RESCHEDULE_VEC, irq_226 as *const () as u64, 0);
+        set_gate(CALL_FUNCTION_VEC, irq_227 as *const () as u64, 0);
+        set_gate(STOP_VEC, irq_228 as *const () as u64, 0);
+
+        load_idt();
+    }
+}
 
+/// Load the shared IDT on the calling CPU.
+///
+/// # Safety
+///
+/// `init_idt` has filled the table.
+pub unsafe fn load_idt() {
+    unsafe {
         let idtr = Idtr {
             limit: (core::mem::size_of_val(&IDT) - 1) as u16,
             base: (&IDT as *const _ as u64),
@@ -77,6 +95,18 @@ pub unsafe fn init_idt() {
     }
 }
 
+/// Exceptions that can arrive on a corrupt or overflowed kernel stack get
+/// a known-good IST stack.
+fn exception_ist(vec: u8) -> u8 {
+    match vec {
+        1 => tss::IST_DB,
+        2 => tss::IST_NMI,
+        8 => tss::IST_DF,
+        18 => tss::IST_MC,
+        _ => 0,
+    }
+}
+
 unsafe fn set_gate(vec: u8, handler: u64, ist: u8) {
     unsafe {
         let idt = &mut *IDT.get();
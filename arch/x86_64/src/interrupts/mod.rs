@@ -1,7 +1,13 @@
 use crate::apic;
 use crate::idt;
+use crate::lapic_timer;
+use crate::mce;
 use crate::mmu;
-use hal::interrupt::{dispatch, FaultKind, IrqFrame, IrqKind};
+use hal::interrupt::{
+    FaultKind, IrqFrame, IrqKind, dispatch, dispatch_exit, dispatch_machine_check,
+};
+use hal::ipi::IpiKind;
+use hal::irqstats;
 
 #[repr(C)]
 pub struct ExceptionContext {
@@ -31,10 +37,39 @@ pub struct ExceptionContext {
     pub ss: u64,
 }
 
+const NMI_VEC: u8 = 2;
+const MC_VEC: u8 = 18;
+
 #[unsafe(no_mangle)]
 pub extern "C" fn exception_dispatch(ctx: *mut ExceptionContext) {
     let ctx = unsafe { &mut *ctx };
     let vec = ctx.vector as u8;
+    let cpu = crate::cpu_index() as u32;
+
+    match vec {
+        NMI_VEC => {
+            dispatch(IrqFrame {
+                cpu,
+                kind: IrqKind::Nmi,
+                fault_kind: FaultKind::None,
+                irq: 0,
+                error_code: 0,
+                fault_addr: 0,
+                pc: ctx.rip,
+                sp: ctx.rsp,
+            });
+            return;
+        }
+        MC_VEC => {
+            let start = hal::time::now_ticks();
+            mce::handle(ctx.rip, dispatch_machine_check);
+            let ticks = hal::time::now_ticks().wrapping_sub(start);
+            irqstats::account(cpu, IrqKind::Fault, 0, ticks);
+            return;
+        }
+        _ => {}
+    }
+
     let fault_kind = decode_fault_kind(vec);
     let fault_addr = if fault_kind == FaultKind::PageFault {
         read_cr2()
@@ -43,11 +78,14 @@ pub extern "C" fn exception_dispatch(ctx: *mut ExceptionContext) {
     };
 
     dispatch(IrqFrame {
+        cpu,
         kind: IrqKind::Fault,
         fault_kind,
         irq: 0,
         error_code: ctx.error_code,
         fault_addr,
+        pc: ctx.rip,
+        sp: ctx.rsp,
     });
 }
 
@@ -55,30 +93,52 @@ pub extern "C" fn exception_dispatch(ctx: *mut ExceptionContext) {
 pub extern "C" fn irq_dispatch(ctx: *mut ExceptionContext) {
     let ctx = unsafe { &mut *ctx };
     let vec = ctx.vector as u8;
+    let cpu = crate::cpu_index() as u32;
     if vec == idt::TLB_SHOOTDOWN_VEC {
+        let start = hal::time::now_ticks();
         mmu::handle_tlb_shootdown();
+        let ticks = hal::time::now_ticks().wrapping_sub(start);
+        irqstats::account(cpu, IrqKind::Ipi, irqstats::IPI_TLB_SHOOTDOWN, ticks);
         unsafe {
             apic::eoi();
         }
+        dispatch_exit();
         return;
     }
-    let kind = if vec == idt::TIMER_VEC {
-        IrqKind::Timer
-    } else {
-        IrqKind::External
+    if vec == idt::RESCHEDULE_VEC {
+        // Nothing to do here: the kernel's interrupt-exit hook reschedules.
+        irqstats::account(cpu, IrqKind::Ipi, irqstats::IPI_RESCHEDULE, 0);
+        unsafe {
+            apic::eoi();
+        }
+        dispatch_exit();
+        return;
+    }
+    let (kind, irq) = match vec {
+        idt::TIMER_VEC => {
+            lapic_timer::on_interrupt();
+            (IrqKind::Timer, 0)
+        }
+        idt::CALL_FUNCTION_VEC => (IrqKind::Ipi, IpiKind::CallFunction as u16),
+        idt::STOP_VEC => (IrqKind::Ipi, IpiKind::Stop as u16),
+        _ => (IrqKind::External, irq_line(vec)),
     };
 
     dispatch(IrqFrame {
+        cpu,
         kind,
         fault_kind: FaultKind::None,
-        irq: irq_line(vec),
+        irq,
         error_code: ctx.error_code,
         fault_addr: 0,
+        pc: ctx.rip,
+        sp: ctx.rsp,
     });
 
     unsafe {
         apic::eoi();
     }
+    dispatch_exit();
 }
 
 #[inline(always)]
@@ -93,10 +153,12 @@ fn read_cr2() -> u64 {
 fn decode_fault_kind(vec: u8) -> FaultKind {
     match vec {
         0 => FaultKind::DivideByZero,
+        1 => FaultKind::Debug,
         6 => FaultKind::InvalidOpcode,
         8 => FaultKind::DoubleFault,
         13 => FaultKind::GeneralProtection,
         14 => FaultKind::PageFault,
+        18 => FaultKind::MachineCheck,
         _ => FaultKind::Unknown,
     }
 }
@@ -0,0 +1,106 @@
+//! Minimal IOAPIC access: route a GSI to a LAPIC vector, mask and unmask it.
+//!
+//! Only the first IOAPIC from the MADT is used. Entries start masked and
+//! stay that way until something routes them.
+
+use core::ptr::{read_volatile, write_volatile};
+use core::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+
+use crate::acpi;
+
+const IOREGSEL: u64 = 0x00;
+const IOWIN: u64 = 0x10;
+
+const REG_VER: u32 = 0x01;
+const REG_REDTBL: u32 = 0x10;
+
+const RTE_MASKED: u32 = 1 << 16;
+
+static BASE: AtomicU64 = AtomicU64::new(0);
+static GSI_BASE: AtomicU32 = AtomicU32::new(0);
+static GSI_COUNT: AtomicU32 = AtomicU32::new(0);
+
+pub fn init() -> bool {
+    if BASE.load(Ordering::Relaxed) != 0 {
+        return true;
+    }
+    let Some(entry) = acpi::ioapic() else {
+        return false;
+    };
+    let base = acpi::to_virt(entry.addr);
+    BASE.store(base, Ordering::Relaxed);
+    let ver = unsafe { read(REG_VER) };
+    GSI_BASE.store(entry.gsi_base, Ordering::Relaxed);
+    GSI_COUNT.store(((ver >> 16) & 0xff) + 1, Ordering::Relaxed);
+    true
+}
+
+/// Whether `gsi` is handled by this IOAPIC.
+pub fn handles(gsi: u32) -> bool {
+    let base = GSI_BASE.load(Ordering::Relaxed);
+    let count = GSI_COUNT.load(Ordering::Relaxed);
+    gsi >= base && gsi < base + count
+}
+
+/// Route `gsi` to `vector` on the LAPIC with ID `dest` (fixed delivery,
+/// physical destination, edge-triggered, active-high) and unmask it.
+pub fn route(gsi: u32, vector: u8, dest: u32) -> bool {
+    if BASE.load(Ordering::Relaxed) == 0 || !handles(gsi) {
+        return false;
+    }
+    let pin = gsi - GSI_BASE.load(Ordering::Relaxed);
+    unsafe {
+        write(REG_REDTBL + pin * 2, RTE_MASKED);
+        write(REG_REDTBL + pin * 2 + 1, (dest & 0xff) << 24);
+        write(REG_REDTBL + pin * 2, vector as u32);
+    }
+    true
+}
+
+pub fn mask(gsi: u32) {
+    if BASE.load(Ordering::Relaxed) == 0 || !handles(gsi) {
+        return;
+    }
+    let pin = gsi - GSI_BASE.load(Ordering::Relaxed);
+    unsafe {
+        let low = read(REG_REDTBL + pin * 2);
+        write(REG_REDTBL + pin * 2, low | RTE_MASKED);
+    }
+}
+
+pub fn unmask(gsi: u32) {
+    if BASE.load(Ordering::Relaxed) == 0 || !handles(gsi) {
+        return;
+    }
+    let pin = gsi - GSI_BASE.load(Ordering::Relaxed);
+    unsafe {
+        let low = read(REG_REDTBL + pin * 2);
+        write(REG_REDTBL + pin * 2, low & !RTE_MASKED);
+    }
+}
+
+/// Vector `gsi` is routed to, if it has been routed at all.
+pub fn vector(gsi: u32) -> Option<u8> {
+    if BASE.load(Ordering::Relaxed) == 0 || !handles(gsi) {
+        return None;
+    }
+    let pin = gsi - GSI_BASE.load(Ordering::Relaxed);
+    let vector = unsafe { read(REG_REDTBL + pin * 2) } as u8;
+    (vector != 0).then_some(vector)
+}
+
+unsafe fn read(reg: u32) -> u32 {
+    let base = BASE.load(Ordering::Relaxed);
+    unsafe {
+        write_volatile((base + IOREGSEL) as *mut u32, reg);
+        read_volatile((base + IOWIN) as *const u32)
+    }
+}
+
+unsafe fn write(reg: u32, val: u32) {
+    let base = BASE.load(Ordering::Relaxed);
+    unsafe {
+        write_volatile((base + IOREGSEL) as *mut u32, reg);
+        write_volatile((base + IOWIN) as *mut u32, val);
+    }
+}
@@ -0,0 +1,41 @@
+//! IPIs through the local APIC. Each `IpiKind` has a reserved vector;
+//! CPUs are addressed by logical index and translated to APIC IDs through
+//! their per-CPU areas.
+
+use hal::ipi::{IpiKind, IpiOps};
+
+use crate::{apic, idt, percpu};
+
+struct ApicIpi;
+
+static OPS: ApicIpi = ApicIpi;
+
+fn vector(kind: IpiKind) -> u8 {
+    match kind {
+        IpiKind::Reschedule => idt::RESCHEDULE_VEC,
+        IpiKind::CallFunction => idt::CALL_FUNCTION_VEC,
+        IpiKind::Stop => idt::STOP_VEC,
+    }
+}
+
+impl IpiOps for ApicIpi {
+    fn send_one(&self, cpu: usize, kind: IpiKind) {
+        let area = percpu::area(cpu);
+        // A CPU that never came up has no APIC ID to send to.
+        if !area.hdr.is_present() {
+            return;
+        }
+        apic::send_ipi(area.hdr.hw_id() as u32, vector(kind));
+    }
+
+    fn broadcast(&self, kind: IpiKind) {
+        apic::send_ipi_all_others(vector(kind));
+    }
+}
+
+/// Register the APIC as the IPI backend. Call once the LAPIC is up.
+pub fn register() {
+    unsafe {
+        hal::ipi::register_ipi_ops(&OPS);
+    }
+}
@@ -0,0 +1,51 @@
+//! Device interrupt lines over the IOAPIC. Line `n` is GSI `n`, delivered
+//! to the boot CPU on the IDT's IRQ vector `32 + n`.
+
+use hal::interrupt::IrqLineOps;
+
+use crate::ioapic;
+
+/// Lines with an IDT stub.
+const LINES: u16 = 16;
+const FIRST_VEC: u8 = 32;
+
+struct IoApicLines;
+
+static OPS: IoApicLines = IoApicLines;
+
+impl IrqLineOps for IoApicLines {
+    fn count(&self) -> u16 {
+        LINES
+    }
+
+    fn enable(&self, line: u16) -> bool {
+        let gsi = line as u32;
+        let vector = FIRST_VEC + line as u8;
+        // Leave alone a GSI something else (the HPET) routed.
+        if ioapic::vector(gsi).is_some_and(|v| v != vector) {
+            return false;
+        }
+        let Some(dest) = hal::cpu::hw_id(0) else {
+            return false;
+        };
+        ioapic::route(gsi, vector, dest as u32)
+    }
+
+    fn mask(&self, line: u16) {
+        ioapic::mask(line as u32);
+    }
+
+    fn unmask(&self, line: u16) {
+        ioapic::unmask(line as u32);
+    }
+}
+
+/// Register the IOAPIC as the device line backend, if there is one.
+pub fn register() {
+    if !ioapic::init() {
+        return;
+    }
+    unsafe {
+        hal::interrupt::register_irq_line_ops(&OPS);
+    }
+}
@@ -0,0 +1,190 @@
+//! LAPIC timer fallback for CPUs without TSC-deadline mode.
+//!
+//! With a usable TSC the timer can run in one-shot mode: the TSC stays the
+//! counter and each deadline is converted into a LAPIC countdown. As a last
+//! resort it runs periodically and the counter is the LAPIC count extended
+//! by the number of elapsed periods.
+
+use core::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+
+use hal::time::TimerOps;
+
+use crate::{apic, calibrate, tsc};
+
+/// Interrupt rate in periodic mode.
+const PERIODIC_HZ: u64 = 1000;
+
+/// One-shot mode keeps the TSC counter but pays for a rate conversion.
+const RATING_ONE_SHOT_PENALTY: u32 = 20;
+const RATING_PERIODIC: u32 = 100;
+
+/// Calibration window when measuring against the TSC.
+const CALIBRATE_MS: u64 = 10;
+
+static LAPIC_HZ: AtomicU64 = AtomicU64::new(0);
+
+static PERIODIC: AtomicBool = AtomicBool::new(false);
+static PERIOD_COUNT: AtomicU32 = AtomicU32::new(0);
+static PERIODS: AtomicU64 = AtomicU64::new(0);
+/// APIC ID of the CPU whose periods drive the counter.
+static COUNTER_CPU: AtomicU32 = AtomicU32::new(0);
+static LAST_NOW: AtomicU64 = AtomicU64::new(0);
+
+pub struct LapicOneShotTimer;
+pub struct LapicPeriodicTimer;
+
+/// Calibrate the LAPIC timer and offer its modes as timer candidates.
+/// `tsc_ok` says whether the TSC frequency is known and can serve as the
+/// counter for one-shot mode.
+pub fn init(tsc_ok: bool) -> bool {
+    static ONE_SHOT: LapicOneShotTimer = LapicOneShotTimer;
+    static PERIODIC_TIMER: LapicPeriodicTimer = LapicPeriodicTimer;
+
+    let Some(hz) = calibrate_hz(tsc_ok) else {
+        return false;
+    };
+    LAPIC_HZ.store(hz, Ordering::Relaxed);
+
+    let count = (hz / PERIODIC_HZ).clamp(1, u32::MAX as u64) as u32;
+    PERIOD_COUNT.store(count, Ordering::Relaxed);
+    unsafe {
+        if tsc_ok {
+            hal::time::register_timer(&ONE_SHOT);
+        }
+        hal::time::register_timer(&PERIODIC_TIMER);
+    }
+    true
+}
+
+fn calibrate_hz(tsc_ok: bool) -> Option<u64> {
+    apic::timer_start_masked(u32::MAX);
+    let read = || (u32::MAX - apic::timer_current()) as u64;
+
+    let hz = if tsc_ok {
+        measure_against_tsc(read)
+    } else {
+        calibrate::measure_hz(read)
+    };
+    apic::timer_set_count(0);
+    hz
+}
+
+fn measure_against_tsc(read: impl Fn() -> u64) -> Option<u64> {
+    let tsc_hz = tsc::hz()?;
+    let window = tsc_hz * CALIBRATE_MS / 1000;
+
+    let t0 = tsc::now();
+    let c0 = read();
+    let mut t1 = t0;
+    while t1.wrapping_sub(t0) < window {
+        t1 = tsc::now();
+    }
+    let c1 = read();
+
+    let elapsed = t1.wrapping_sub(t0);
+    let delta = c1.wrapping_sub(c0);
+    if elapsed == 0 || delta == 0 {
+        return None;
+    }
+    Some(((delta as u128 * tsc_hz as u128) / elapsed as u128) as u64)
+}
+
+/// Timer-vector hook: account one elapsed period in periodic mode.
+#[inline(always)]
+pub fn on_interrupt() {
+    if PERIODIC.load(Ordering::Relaxed) && apic::cpu_id() == COUNTER_CPU.load(Ordering::Relaxed) {
+        PERIODS.fetch_add(1, Ordering::AcqRel);
+    }
+}
+
+impl TimerOps for LapicOneShotTimer {
+    fn name(&self) -> &'static str {
+        "lapic-oneshot"
+    }
+
+    fn rating(&self) -> u32 {
+        tsc::rating().saturating_sub(RATING_ONE_SHOT_PENALTY)
+    }
+
+    fn now_ticks(&self) -> u64 {
+        tsc::now()
+    }
+
+    fn frequency_hz(&self) -> u64 {
+        tsc::hz().unwrap_or(0)
+    }
+
+    fn arm_one_shot(&self, deadline_ticks: u64) {
+        let tsc_hz = tsc::hz().unwrap_or(0);
+        let lapic_hz = LAPIC_HZ.load(Ordering::Relaxed);
+        if tsc_hz == 0 || lapic_hz == 0 {
+            return;
+        }
+        let delta = deadline_ticks.saturating_sub(tsc::now());
+        let count = (delta as u128 * lapic_hz as u128) / tsc_hz as u128;
+        // 0 would stop the timer; fire as soon as possible instead.
+        apic::timer_set_count(count.clamp(1, u32::MAX as u128) as u32);
+    }
+
+    fn activate(&self) {
+        apic::timer_mode_one_shot();
+    }
+
+    fn activate_cpu(&self) {
+        apic::timer_mode_one_shot();
+    }
+
+    fn deactivate(&self) {
+        apic::timer_mask();
+    }
+}
+
+impl TimerOps for LapicPeriodicTimer {
+    fn name(&self) -> &'static str {
+        "lapic-periodic"
+    }
+
+    fn rating(&self) -> u32 {
+        RATING_PERIODIC
+    }
+
+    fn now_ticks(&self) -> u64 {
+        let count = PERIOD_COUNT.load(Ordering::Relaxed) as u64;
+        loop {
+            let p0 = PERIODS.load(Ordering::Acquire);
+            let cur = apic::timer_current() as u64;
+            let p1 = PERIODS.load(Ordering::Acquire);
+            if p0 != p1 {
+                continue;
+            }
+            // The counter may have reloaded before its interrupt was
+            // accounted; never let time run backwards.
+            let t = p0 * count + count.saturating_sub(cur);
+            let prev = LAST_NOW.fetch_max(t, Ordering::AcqRel);
+            return prev.max(t);
+        }
+    }
+
+    fn frequency_hz(&self) -> u64 {
+        LAPIC_HZ.load(Ordering::Relaxed)
+    }
+
+    fn arm_one_shot(&self, _deadline_ticks: u64) {
+        // Periodic mode fires every period regardless; nothing to arm.
+    }
+
+    fn activate(&self) {
+        COUNTER_CPU.store(apic::cpu_id(), Ordering::Relaxed);
+        PERIODIC.store(true, Ordering::Release);
+        apic::timer_mode_periodic(PERIOD_COUNT.load(Ordering::Relaxed));
+    }
+
+    fn activate_cpu(&self) {
+        apic::timer_mode_periodic(PERIOD_COUNT.load(Ordering::Relaxed));
+    }
+
+    fn deactivate(&self) {
+        PERIODIC.store(false, Ordering::Release);
+        apic::timer_mask();
+    }
+}
@@ -4,15 +4,29 @@
 
 pub const ARCH_NAME: &str = "x86_64";
 
+pub mod acpi;
 pub mod apic;
+pub mod calibrate;
+pub mod context;
 pub mod cpuid;
 pub mod gdt;
+pub mod hpet;
 pub mod idt;
 pub mod interrupts;
+pub mod ioapic;
+pub mod ipi;
+pub mod irqlines;
+pub mod lapic_timer;
+pub mod mce;
 pub mod mmu;
 pub mod msr;
+pub mod percpu;
+pub mod pit;
+pub mod pmtimer;
+pub mod rtc;
 pub mod serial;
 pub mod tsc;
+pub mod tsc_sync;
 pub mod tss;
 
 use bootabi::BootInfo;
@@ -36,6 +50,9 @@ pub(crate) fn hhdm_offset() -> u64 {
 
 pub fn early_init() {
     disable_interrupts();
+    unsafe {
+        percpu::install(0);
+    }
     unsafe {
         core::arch::asm!("cld", options(nomem, nostack));
     }
@@ -77,7 +94,7 @@ pub unsafe fn init(boot: &BootInfo) {
         init_mmu(boot);
         init_core();
     }
-    let has_time = init_time_source();
+    let has_time = init_time_source(boot);
     unsafe {
         init_irqs(boot, has_time);
     }
@@ -92,43 +109,128 @@ pub unsafe fn init_mmu(boot: &BootInfo) {
 
 pub unsafe fn init_core() {
     unsafe {
+        let cpu = cpu_index();
         let rsp0_top = current_rsp();
-        init_gdt_and_segments(rsp0_top);
+        init_gdt_and_segments(cpu, rsp0_top);
         idt::init_idt();
+        mce::init();
+        context::register();
         mask_legacy_pic();
         // Build the TSS descriptor after IDT/handlers are live.
-        load_tss();
+        load_tss(cpu);
     }
 }
 
-pub fn init_time_source() -> bool {
-    tsc::init()
+/// Per-CPU setup on an application processor, after the BSP finished
+/// `init_core`, `init_time_source` and `init_irqs`: control registers,
+/// its own GDT and TSS, the shared IDT, machine checks, its LAPIC (timer
+/// masked) and TSC tagging. `cpu` becomes its logical index.
+/// Interrupts stay disabled.
+///
+/// # Safety
+///
+/// Call once on each AP, after the BSP's setup is done.
+pub unsafe fn init_ap(cpu: usize) {
+    disable_interrupts();
+    unsafe {
+        percpu::install(cpu);
+        core::arch::asm!("cld", options(nomem, nostack));
+        enable_sse();
+        mmu::init_cpu();
+        apic::init_cpu();
+        init_gdt_and_segments(cpu, current_rsp());
+        idt::load_idt();
+        mce::init();
+        load_tss(cpu);
+        tsc::init_cpu(cpu as u32);
+    }
 }
 
-unsafe fn init_gdt_and_segments(rsp0_top: u64) {
+/// Switch to the stack ending at `stack_top` and call `f(arg)` on it. The
+/// old stack is abandoned.
+///
+/// # Safety
+///
+/// The stack ending at `stack_top` is unused, and nothing on the
+/// current stack is referenced again.
+pub unsafe fn call_on_stack(stack_top: u64, f: extern "C" fn(usize) -> !, arg: usize) -> ! {
     unsafe {
-        gdt::init_tss_only(rsp0_top);
+        core::arch::asm!(
+            "mov rsp, {top}",
+            "xor ebp, ebp",
+            "call {f}",
+            "ud2",
+            top = in(reg) stack_top & !0xf,
+            f = in(reg) f,
+            in("rdi") arg,
+            options(noreturn)
+        )
+    }
+}
+
+/// BSP half of the boot-time counter check; an AP must be running
+/// `ap_clock_sync` at the same time.
+pub fn bsp_clock_sync() -> hal::time::ClockSync {
+    let r = tsc_sync::bsp_check();
+    hal::time::ClockSync {
+        offset: r.offset,
+        adjusted: r.adjusted,
+        max_warp: r.max_warp,
+        stable: r.stable,
+        responded: r.responded,
+    }
+}
+
+/// AP half of the boot-time counter check. Interrupts must be disabled.
+pub fn ap_clock_sync() {
+    tsc_sync::ap_check();
+}
+
+/// Discover platform timers and the TSC frequency. Returns true if any
+/// clocksource is usable.
+pub fn init_time_source(boot: &BootInfo) -> bool {
+    acpi::init(boot.acpi_rsdp.0);
+    let hpet_ok = hpet::init();
+    pmtimer::init();
+    rtc::init();
+    let tsc_ok = tsc::init();
+    tsc::init_cpu(0);
+    tsc_ok || hpet_ok
+}
+
+unsafe fn init_gdt_and_segments(cpu: usize, rsp0_top: u64) {
+    unsafe {
+        gdt::init_tss_only(cpu, rsp0_top);
         gdt::build_gdt_entries_only();
-        gdt::build_full_table();
-        gdt::load_gdt();
+        gdt::build_full_table(cpu);
+        gdt::load_gdt(cpu);
         gdt::reload_segments();
     }
 }
 
-unsafe fn load_tss() {
+unsafe fn load_tss(cpu: usize) {
     unsafe {
-        gdt::build_tss_desc();
-        gdt::build_full_table();
+        gdt::build_tss_desc(cpu);
+        gdt::build_full_table(cpu);
         gdt::load_tss();
     }
 }
 
 pub unsafe fn init_irqs(boot: &BootInfo, has_time: bool) -> bool {
     let apic_ok = unsafe { apic::init(boot.hhdm_offset) };
-    if has_time {
+    if !apic_ok {
+        return false;
+    }
+    ipi::register();
+    irqlines::register();
+    // Offer every usable timer; the kernel picks one by rating.
+    let tsc_ok = has_time && tsc::hz().is_some();
+    if tsc_ok && cpuid::has_tsc_deadline() {
         tsc::register_timer();
     }
-    apic_ok
+    hpet::register_timer(boot.cpu_count);
+    lapic_timer::init(tsc_ok);
+    true
 }
 
 #[inline(always)]
@@ -150,16 +252,67 @@ fn mask_legacy_pic() {
     crate::serial::outb(0xa0, 0x20);
 }
 
+/// Logical index of the executing CPU, from its per-CPU area.
+#[inline(always)]
+pub fn cpu_index() -> usize {
+    percpu::index()
+}
+
+/// Ask every other CPU to take an NMI (used for cross-CPU state dumps).
+pub fn send_nmi_all_others() {
+    apic::send_nmi_all_others();
+}
+
 pub fn cpu_relax() {
     unsafe { core::arch::asm!("pause", options(nomem, nostack, preserves_flags)) }
 }
 
 pub fn disable_interrupts() {
-    unsafe { core::arch::asm!("cli", options(nomem, nostack, preserves_flags)) }
+    unsafe { core::arch::asm!("cli", options(nostack, preserves_flags)) }
 }
 
 pub fn enable_interrupts() {
-    unsafe { core::arch::asm!("sti", options(nomem, nostack, preserves_flags)) }
+    unsafe { core::arch::asm!("sti", options(nostack, preserves_flags)) }
+}
+
+/// Whether maskable interrupts are enabled (RFLAGS.IF).
+#[inline(always)]
+pub fn interrupts_enabled() -> bool {
+    let flags: u64;
+    unsafe {
+        core::arch::asm!("pushfq", "pop {}", out(reg) flags, options(nomem, preserves_flags));
+    }
+    (flags & (1 << 9)) != 0
+}
+
+/// Enable interrupts and halt until the next one arrives. Call with
+/// interrupts disabled; `sti` delays delivery past `hlt`, so a wakeup
+/// cannot slip in between. No `nomem`: handlers run in here and change
+/// memory the caller reads afterwards.
+#[inline(always)]
+pub fn wait_for_interrupt() {
+    unsafe { core::arch::asm!("sti", "hlt", options(nostack)) }
+}
+
+/// Disable interrupts and return the previous state for `irq_restore`.
+///
+/// Like `enable_interrupts`/`disable_interrupts`, the asm omits `nomem`
+/// so it is a compiler barrier: accesses cannot move out of the section.
+#[inline(always)]
+pub fn irq_save() -> usize {
+    let flags: u64;
+    unsafe {
+        core::arch::asm!("pushfq", "pop {}", "cli", out(reg) flags);
+    }
+    (flags & (1 << 9)) as usize
+}
+
+/// Restore the interrupt state returned by `irq_save`.
+#[inline(always)]
+pub fn irq_restore(state: usize) {
+    if state != 0 {
+        enable_interrupts();
+    }
 }
 
 /// MMU backend
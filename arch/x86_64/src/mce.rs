@@ -0,0 +1,143 @@
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use hal::interrupt::{MachineCheckRecord, McSeverity};
+
+use crate::{cpuid, msr::*};
+
+const MCG_CAP_COUNT_MASK: u64 = 0xff;
+const MCG_CAP_CTL_P: u64 = 1 << 8;
+
+const MCG_STATUS_RIPV: u64 = 1 << 0;
+
+const MCI_STATUS_VAL: u64 = 1 << 63;
+const MCI_STATUS_UC: u64 = 1 << 61;
+const MCI_STATUS_MISCV: u64 = 1 << 59;
+const MCI_STATUS_ADDRV: u64 = 1 << 58;
+const MCI_STATUS_PCC: u64 = 1 << 57;
+
+const CR4_MCE: u64 = 1 << 6;
+
+/// Bank count discovered at init (0 = MCA unavailable).
+static BANKS: AtomicU32 = AtomicU32::new(0);
+
+#[inline(always)]
+fn mc_ctl(bank: u32) -> u32 {
+    IA32_MC0_CTL + bank * 4
+}
+
+#[inline(always)]
+fn mc_status(bank: u32) -> u32 {
+    IA32_MC0_CTL + bank * 4 + 1
+}
+
+#[inline(always)]
+fn mc_addr(bank: u32) -> u32 {
+    IA32_MC0_CTL + bank * 4 + 2
+}
+
+#[inline(always)]
+fn mc_misc(bank: u32) -> u32 {
+    IA32_MC0_CTL + bank * 4 + 3
+}
+
+/// Enable error reporting in every bank and turn on CR4.MCE.
+///
+/// Without CR4.MCE a machine check shuts the CPU down instead of raising #MC.
+///
+/// # Safety
+///
+/// The IDT must have a #MC handler installed first.
+pub unsafe fn init() -> bool {
+    if !cpuid::has_mca() {
+        return false;
+    }
+
+    unsafe {
+        let cap = rdmsr(IA32_MCG_CAP);
+        let banks = (cap & MCG_CAP_COUNT_MASK) as u32;
+        if (cap & MCG_CAP_CTL_P) != 0 {
+            wrmsr(IA32_MCG_CTL, !0);
+        }
+        for bank in 0..banks {
+            wrmsr(mc_ctl(bank), !0);
+            wrmsr(mc_status(bank), 0);
+        }
+        BANKS.store(banks, Ordering::Relaxed);
+
+        let mut cr4: u64;
+        core::arch::asm!("mov {}, cr4", out(reg) cr4, options(nomem, nostack, preserves_flags));
+        cr4 |= CR4_MCE;
+        core::arch::asm!("mov cr4, {}", in(reg) cr4, options(nomem, nostack, preserves_flags));
+    }
+    true
+}
+
+fn severity(status: u64, mcg_status: u64) -> McSeverity {
+    if (status & MCI_STATUS_UC) == 0 {
+        McSeverity::Corrected
+    } else if (status & MCI_STATUS_PCC) != 0 || (mcg_status & MCG_STATUS_RIPV) == 0 {
+        McSeverity::Fatal
+    } else {
+        McSeverity::Recoverable
+    }
+}
+
+/// Decode every valid error bank after a #MC, hand each record to `report`,
+/// then clear the banks and MCG_STATUS.MCIP so a later #MC does not
+/// escalate into a shutdown.
+pub fn handle(pc: u64, mut report: impl FnMut(&MachineCheckRecord)) {
+    let cpu = crate::cpu_index() as u32;
+    let banks = BANKS.load(Ordering::Relaxed);
+    let mcg_status = unsafe { rdmsr(IA32_MCG_STATUS) };
+    let mut reported = false;
+
+    for bank in 0..banks {
+        let status = unsafe { rdmsr(mc_status(bank)) };
+        if (status & MCI_STATUS_VAL) == 0 {
+            continue;
+        }
+        let addr_valid = (status & MCI_STATUS_ADDRV) != 0;
+        let rec = MachineCheckRecord {
+            cpu,
+            bank: bank as u16,
+            severity: severity(status, mcg_status),
+            addr_valid,
+            syndrome: status,
+            addr: if addr_valid {
+                unsafe { rdmsr(mc_addr(bank)) }
+            } else {
+                0
+            },
+            misc: if (status & MCI_STATUS_MISCV) != 0 {
+                unsafe { rdmsr(mc_misc(bank)) }
+            } else {
+                0
+            },
+            pc,
+        };
+        report(&rec);
+        reported = true;
+        unsafe { wrmsr(mc_status(bank), 0) };
+    }
+
+    if !reported {
+        // #MC with no valid bank: still surface it so policy can decide.
+        let rec = MachineCheckRecord {
+            cpu,
+            bank: u16::MAX,
+            severity: if (mcg_status & MCG_STATUS_RIPV) == 0 {
+                McSeverity::Fatal
+            } else {
+                McSeverity::Recoverable
+            },
+            addr_valid: false,
+            syndrome: mcg_status,
+            addr: 0,
+            misc: 0,
+            pc,
+        };
+        report(&rec);
+    }
+
+    unsafe { wrmsr(IA32_MCG_STATUS, 0) };
+}
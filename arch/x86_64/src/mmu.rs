@@ -6,7 +6,7 @@ use hal::mmu::{
     AddressSpace, MapError, MapFlags, Mmu, PageTableFrameAlloc, PhysAddr, TranslateError, VirtAddr,
 };
 
-use crate::{apic, cpuid, hhdm_offset, idt, msr};
+use crate::{apic, cpuid, hhdm_offset, idt, msr, percpu};
 
 pub struct X86Mmu;
 
@@ -23,10 +23,6 @@ struct X86AddressSpace {
 static KAS: SyncUnsafeCell<Option<X86AddressSpace>> = SyncUnsafeCell::new(None);
 static KAS_HANDLE: SyncUnsafeCell<Option<AddressSpace>> = SyncUnsafeCell::new(None);
 
-const MAX_CPUS: usize = 256;
-static CURRENT_PER_CPU: SyncUnsafeCell<[Option<AddressSpace>; MAX_CPUS]> =
-    SyncUnsafeCell::new([None; MAX_CPUS]);
-
 const MAX_ADDRESS_SPACES: usize = 64;
 
 const PAGE_SIZE: u64 = 4096;
@@ -75,26 +71,14 @@ fn aligned_4k(x: u64) -> bool {
     (x & (PAGE_SIZE - 1)) == 0
 }
 
-#[inline(always)]
-fn cpu_index() -> usize {
-    let id = apic::cpu_id() as usize;
-    if id < MAX_CPUS {
-        id
-    } else {
-        0
-    }
-}
-
 #[inline(always)]
 unsafe fn current_slot_mut() -> &'static mut Option<AddressSpace> {
-    let idx = cpu_index();
-    unsafe { &mut (*CURRENT_PER_CPU.get())[idx] }
+    unsafe { &mut *percpu::this().aspace.get() }
 }
 
 #[inline(always)]
 unsafe fn current_slot() -> Option<AddressSpace> {
-    let idx = cpu_index();
-    unsafe { (*CURRENT_PER_CPU.get())[idx] }
+    unsafe { *percpu::this().aspace.get() }
 }
 
 #[inline(always)]
@@ -199,6 +183,20 @@ pub unsafe fn init_features(enable_nx: bool) {
     NXE_ENABLED.store(nxe, Ordering::Relaxed);
 }
 
+/// Per-CPU MMU setup on an AP: the paging features the BSP turned on,
+/// and the kernel address space as current. The AP already runs on the
+/// kernel's page tables.
+///
+/// # Safety
+///
+/// The BSP has finished MMU setup, and this runs once per AP.
+pub unsafe fn init_cpu() {
+    unsafe {
+        init_features(nxe_enabled());
+        *current_slot_mut() = *KAS_HANDLE.get();
+    }
+}
+
 pub unsafe fn enable_nx() -> Result<(), MapError> {
     if nxe_enabled() {
         return Ok(());
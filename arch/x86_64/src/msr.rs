@@ -2,6 +2,11 @@ use core::arch::asm;
 
 pub const IA32_TSC_DEADLINE: u32 = 0x0000_06e0;
 pub const IA32_APIC_BASE: u32 = 0x1b;
+pub const IA32_TSC_ADJUST: u32 = 0x3b;
+pub const IA32_MCG_CAP: u32 = 0x179;
+pub const IA32_MCG_STATUS: u32 = 0x17a;
+pub const IA32_MCG_CTL: u32 = 0x17b;
+pub const IA32_MC0_CTL: u32 = 0x400;
 pub const IA32_EFER: u32 = 0xc000_0080;
 pub const IA32_STAR: u32 = 0xc000_0081;
 pub const IA32_LSTAR: u32 = 0xc000_0082;
@@ -9,6 +14,7 @@ pub const IA32_FMASK: u32 = 0xc000_0084;
 pub const IA32_FS_BASE: u32 = 0xc000_0100;
 pub const IA32_GS_BASE: u32 = 0xc000_0101;
 pub const IA32_KERNEL_GS_BASE: u32 = 0xc000_0102;
+pub const IA32_TSC_AUX: u32 = 0xc000_0103;
 
 #[inline(always)]
 pub unsafe fn wrmsr(msr: u32, val: u64) {
@@ -0,0 +1,105 @@
+//! Per-CPU areas.
+//!
+//! Each CPU owns one `CpuArea`, found through GS base. The area starts
+//! with the hal `PerCpu` header, so the logical CPU index is a single
+//! `gs:`-relative load, and holds the state no two CPUs may share: TSS,
+//! IST stacks, GDT and the current address space.
+//!
+//! Nothing reloads GS afterwards (see `gdt::reload_segments`), so the base
+//! stays put once `install` has written it.
+
+use core::arch::asm;
+use core::cell::SyncUnsafeCell;
+
+use hal::cpu::{MAX_CPUS, PERCPU_INDEX_OFFSET, PerCpu, PerCpuOps};
+use hal::mmu::AddressSpace;
+
+use crate::{cpuid, gdt, msr, tss};
+
+#[repr(C, align(64))]
+pub struct CpuArea {
+    /// Must stay first: `gs:[0]` is the area's own address.
+    pub hdr: PerCpu,
+    /// Address space loaded in CR3.
+    pub(crate) aspace: SyncUnsafeCell<Option<AddressSpace>>,
+    pub(crate) tss: SyncUnsafeCell<tss::Tss64>,
+    pub(crate) gdt: SyncUnsafeCell<gdt::FullTable>,
+    pub(crate) ist: SyncUnsafeCell<tss::IstStacks>,
+}
+
+impl CpuArea {
+    const fn new() -> Self {
+        Self {
+            hdr: PerCpu::new(),
+            aspace: SyncUnsafeCell::new(None),
+            tss: SyncUnsafeCell::new(tss::zeroed()),
+            gdt: SyncUnsafeCell::new(gdt::FullTable::new()),
+            ist: SyncUnsafeCell::new(tss::IstStacks::new()),
+        }
+    }
+}
+
+#[unsafe(link_section = ".bss.boot")]
+static AREAS: [CpuArea; MAX_CPUS] = [const { CpuArea::new() }; MAX_CPUS];
+
+struct X86PerCpu;
+
+impl PerCpuOps for X86PerCpu {
+    fn this_cpu(&self) -> &'static PerCpu {
+        &this().hdr
+    }
+
+    fn cpu(&self, index: usize) -> &'static PerCpu {
+        &AREAS[index].hdr
+    }
+}
+
+static OPS: X86PerCpu = X86PerCpu;
+
+/// Make the executing CPU logical CPU `index`: stamp its area with the
+/// index and APIC ID and point GS base at it. Interrupts must be disabled.
+///
+/// # Safety
+///
+/// No other CPU has installed `index`, and interrupts are disabled.
+pub unsafe fn install(index: usize) {
+    let area = &AREAS[index];
+    area.hdr.init(index, cpuid::apic_id() as u64);
+    unsafe {
+        msr::wrmsr(msr::IA32_GS_BASE, area as *const CpuArea as u64);
+        if index == 0 {
+            hal::cpu::register_percpu_ops(&OPS);
+        }
+    }
+}
+
+/// Area of logical CPU `cpu`.
+#[inline(always)]
+pub fn area(cpu: usize) -> &'static CpuArea {
+    &AREAS[cpu]
+}
+
+/// Area of the executing CPU.
+#[inline(always)]
+pub fn this() -> &'static CpuArea {
+    let p: *const CpuArea;
+    unsafe {
+        asm!("mov {}, gs:[0]", out(reg) p, options(nostack, readonly, preserves_flags));
+        &*p
+    }
+}
+
+/// Logical index of the executing CPU.
+#[inline(always)]
+pub fn index() -> usize {
+    let v: usize;
+    unsafe {
+        asm!(
+            "mov {}, gs:[{off}]",
+            out(reg) v,
+            off = const PERCPU_INDEX_OFFSET,
+            options(nostack, readonly, preserves_flags)
+        );
+    }
+    v
+}
@@ -0,0 +1,55 @@
+//! Legacy 8254 PIT, used only as a calibration reference.
+//!
+//! Channel 2 is gated through port 0x61 so it can be polled without
+//! routing an interrupt.
+
+use crate::serial::{inb, outb};
+
+const PIT_HZ: u64 = 1_193_182;
+
+const PIT_CH2: u16 = 0x42;
+const PIT_CMD: u16 = 0x43;
+const PORT_B: u16 = 0x61;
+
+const PORT_B_GATE2: u8 = 1 << 0;
+const PORT_B_SPEAKER: u8 = 1 << 1;
+const PORT_B_OUT2: u8 = 1 << 5;
+
+/// Channel 2, lobyte/hibyte access, mode 0 (terminal count), binary.
+const CMD_CH2_ONESHOT: u8 = 0b1011_0000;
+
+/// Give up if OUT2 never rises (no PIT on this platform).
+const MAX_SPINS: u64 = 10_000_000;
+
+/// Measure the frequency of the counter behind `read` over a `ms`-long
+/// PIT countdown.
+pub fn measure_hz(ms: u32, read: impl Fn() -> u64) -> Option<u64> {
+    let latch = PIT_HZ * ms as u64 / 1000;
+    if latch == 0 || latch > 0xffff {
+        return None;
+    }
+
+    let saved = inb(PORT_B);
+    outb(PORT_B, (saved & !PORT_B_SPEAKER) | PORT_B_GATE2);
+    outb(PIT_CMD, CMD_CH2_ONESHOT);
+    outb(PIT_CH2, latch as u8);
+    outb(PIT_CH2, (latch >> 8) as u8);
+
+    let start = read();
+    let mut spins = 0u64;
+    while (inb(PORT_B) & PORT_B_OUT2) == 0 {
+        spins += 1;
+        if spins > MAX_SPINS {
+            outb(PORT_B, saved);
+            return None;
+        }
+    }
+    let end = read();
+    outb(PORT_B, saved);
+
+    let delta = end.wrapping_sub(start);
+    if delta == 0 {
+        return None;
+    }
+    Some(((delta as u128 * PIT_HZ as u128) / latch as u128) as u64)
+}
@@ -0,0 +1,62 @@
+//! ACPI power-management timer, used as a calibration reference.
+
+use core::sync::atomic::{AtomicU16, AtomicU32, Ordering};
+
+use crate::acpi;
+use crate::serial::inl;
+
+const PM_TIMER_HZ: u64 = 3_579_545;
+
+/// Give up if the counter never advances far enough.
+const MAX_SPINS: u64 = 50_000_000;
+
+static PORT: AtomicU16 = AtomicU16::new(0);
+static MASK: AtomicU32 = AtomicU32::new(0);
+
+pub fn init() -> bool {
+    let Some(blk) = acpi::pm_timer() else {
+        return false;
+    };
+    let mask = if blk.ext32 { u32::MAX } else { 0x00ff_ffff };
+    MASK.store(mask, Ordering::Relaxed);
+    PORT.store(blk.port, Ordering::Relaxed);
+    true
+}
+
+#[inline(always)]
+fn read(port: u16, mask: u32) -> u32 {
+    inl(port) & mask
+}
+
+/// Measure the frequency of the counter behind `read_counter` over roughly
+/// `ms` milliseconds of PM timer time.
+pub fn measure_hz(ms: u32, read_counter: impl Fn() -> u64) -> Option<u64> {
+    let port = PORT.load(Ordering::Relaxed);
+    let mask = MASK.load(Ordering::Relaxed);
+    if port == 0 {
+        return None;
+    }
+
+    let target = PM_TIMER_HZ * ms as u64 / 1000;
+    let pm_start = read(port, mask);
+    let start = read_counter();
+
+    let mut spins = 0u64;
+    let elapsed = loop {
+        let e = (read(port, mask).wrapping_sub(pm_start) & mask) as u64;
+        if e >= target {
+            break e;
+        }
+        spins += 1;
+        if spins > MAX_SPINS {
+            return None;
+        }
+    };
+    let end = read_counter();
+
+    let delta = end.wrapping_sub(start);
+    if delta == 0 {
+        return None;
+    }
+    Some(((delta as u128 * PM_TIMER_HZ as u128) / elapsed as u128) as u64)
+}
@@ -0,0 +1,154 @@
+//! CMOS real-time clock.
+//!
+//! Registers are read twice (each time after the update-in-progress bit
+//! clears) until two consecutive reads agree, so a read never straddles an
+//! update. BCD vs binary and 12/24-hour modes come from status register B;
+//! the century comes from the register the FADT names, if any.
+
+use core::sync::atomic::{AtomicU8, Ordering};
+
+use hal::rtc::{RtcOps, RtcTime};
+
+use crate::acpi;
+use crate::serial::{inb, outb};
+
+const CMOS_INDEX: u16 = 0x70;
+const CMOS_DATA: u16 = 0x71;
+
+const REG_SECONDS: u8 = 0x00;
+const REG_MINUTES: u8 = 0x02;
+const REG_HOURS: u8 = 0x04;
+const REG_DAY: u8 = 0x07;
+const REG_MONTH: u8 = 0x08;
+const REG_YEAR: u8 = 0x09;
+const REG_STATUS_A: u8 = 0x0a;
+const REG_STATUS_B: u8 = 0x0b;
+
+const STATUS_A_UIP: u8 = 1 << 7;
+const STATUS_B_24H: u8 = 1 << 1;
+const STATUS_B_BINARY: u8 = 1 << 2;
+
+const HOUR_PM: u8 = 1 << 7;
+
+/// Give up on a clock whose update flag never clears.
+const MAX_SPINS: u32 = 1_000_000;
+const MAX_READS: u32 = 8;
+
+/// CMOS index of the century register (0 = none).
+static CENTURY_REG: AtomicU8 = AtomicU8::new(0);
+
+pub struct CmosRtc;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+struct Raw {
+    second: u8,
+    minute: u8,
+    hour: u8,
+    day: u8,
+    month: u8,
+    year: u8,
+    century: u8,
+}
+
+pub fn init() {
+    static RTC: CmosRtc = CmosRtc;
+    CENTURY_REG.store(acpi::rtc_century_index().unwrap_or(0), Ordering::Relaxed);
+    unsafe {
+        hal::rtc::register_rtc(&RTC);
+    }
+}
+
+fn cmos_read(reg: u8) -> u8 {
+    outb(CMOS_INDEX, reg);
+    inb(CMOS_DATA)
+}
+
+fn wait_update_done() -> bool {
+    for _ in 0..MAX_SPINS {
+        if cmos_read(REG_STATUS_A) & STATUS_A_UIP == 0 {
+            return true;
+        }
+    }
+    false
+}
+
+fn read_raw() -> Option<Raw> {
+    if !wait_update_done() {
+        return None;
+    }
+    let century_reg = CENTURY_REG.load(Ordering::Relaxed);
+    Some(Raw {
+        second: cmos_read(REG_SECONDS),
+        minute: cmos_read(REG_MINUTES),
+        hour: cmos_read(REG_HOURS),
+        day: cmos_read(REG_DAY),
+        month: cmos_read(REG_MONTH),
+        year: cmos_read(REG_YEAR),
+        century: if century_reg != 0 {
+            cmos_read(century_reg)
+        } else {
+            0
+        },
+    })
+}
+
+/// Read until two consecutive snapshots agree.
+fn read_stable() -> Option<Raw> {
+    let mut last = read_raw()?;
+    for _ in 0..MAX_READS {
+        let next = read_raw()?;
+        if next == last {
+            return Some(next);
+        }
+        last = next;
+    }
+    None
+}
+
+#[inline(always)]
+fn bcd(v: u8) -> u8 {
+    (v & 0x0f) + (v >> 4) * 10
+}
+
+impl RtcOps for CmosRtc {
+    fn read(&self) -> Option<RtcTime> {
+        let raw = read_stable()?;
+        let status_b = cmos_read(REG_STATUS_B);
+        let binary = status_b & STATUS_B_BINARY != 0;
+        let conv = |v: u8| if binary { v } else { bcd(v) };
+
+        let pm = raw.hour & HOUR_PM != 0;
+        let mut hour = conv(raw.hour & !HOUR_PM);
+        if status_b & STATUS_B_24H == 0 {
+            // 12-hour clock: 12 AM is 0, 12 PM is 12.
+            hour %= 12;
+            if pm {
+                hour += 12;
+            }
+        }
+
+        let year = conv(raw.year) as u16;
+        let year = if raw.century != 0 {
+            conv(raw.century) as u16 * 100 + year
+        } else if year < 70 {
+            2000 + year
+        } else {
+            1900 + year
+        };
+
+        let time = RtcTime {
+            year,
+            month: conv(raw.month),
+            day: conv(raw.day),
+            hour,
+            minute: conv(raw.minute),
+            second: conv(raw.second),
+        };
+        let valid = (1..=12).contains(&time.month)
+            && (1..=31).contains(&time.day)
+            && time.hour < 24
+            && time.minute < 60
+            && time.second < 60;
+        if valid { Some(time) } else { None }
+    }
+}
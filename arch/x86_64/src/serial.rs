@@ -16,6 +16,14 @@ pub fn inb(port: u16) -> u8 {
     }
 }
 
+pub fn inl(port: u16) -> u32 {
+    unsafe {
+        let mut v: u32;
+        core::arch::asm!("in eax, dx", in("dx") port, out("eax") v, options(nomem, nostack, preserves_flags));
+        v
+    }
+}
+
 pub fn com1_write(b: u8) {
     while (inb(COM1 + 5) & 0x20) == 0 {}
     outb(COM1, b);
@@ -1,23 +1,66 @@
-use crate::{cpuid, msr::*};
+use crate::{apic, calibrate, cpuid, msr::*};
 use core::arch::x86_64::{__rdtscp, _mm_lfence};
+use core::sync::atomic::{AtomicBool, Ordering};
 use hal::time::TimerOps;
 
 static mut TSC_HZ: u64 = 0;
+static mut TSC_INVARIANT: bool = false;
+/// Set when CPUs disagree on the TSC beyond what TSC_ADJUST can fix.
+static TSC_UNSTABLE: AtomicBool = AtomicBool::new(false);
+
+/// Invariant TSC beats any platform timer; a TSC that may stop or change
+/// rate in power states ranks below the HPET.
+const RATING_INVARIANT: u32 = 300;
+const RATING_VARIANT: u32 = 150;
 
 pub struct TscTimer;
 
-#[inline(always)]
+/// Determine the TSC frequency: CPUID leaves 0x15/0x16 when they are
+/// populated, otherwise calibrate against a platform reference timer.
+///
+/// Returns true if the TSC is usable as a time source.
 pub fn init() -> bool {
-    if !cpuid::has_invariant_tsc() {
+    if !cpuid::has_tsc() {
         unsafe {
             TSC_HZ = 0;
         }
         return false;
     }
+    let hz = cpuid::tsc_hz()
+        .or_else(|| calibrate::measure_hz(now))
+        .unwrap_or(0);
     unsafe {
-        TSC_HZ = cpuid::tsc_hz().unwrap_or(0);
+        TSC_HZ = hz;
+        TSC_INVARIANT = cpuid::has_invariant_tsc();
+    }
+    hz != 0
+}
+
+/// Per-CPU TSC setup: tag `rdtscp` results with the CPU index.
+pub fn init_cpu(index: u32) {
+    if cpuid::has_rdtscp() {
+        unsafe { wrmsr(IA32_TSC_AUX, index as u64) };
+    }
+}
+
+/// Take the TSC out of the running as a clocksource.
+pub fn mark_unstable() {
+    TSC_UNSTABLE.store(true, Ordering::Relaxed);
+}
+
+pub fn is_unstable() -> bool {
+    TSC_UNSTABLE.load(Ordering::Relaxed)
+}
+
+/// Rating of TSC-based timers.
+pub fn rating() -> u32 {
+    if is_unstable() {
+        0
+    } else if unsafe { TSC_INVARIANT } {
+        RATING_INVARIANT
+    } else {
+        RATING_VARIANT
     }
-    true
 }
 
 #[inline(always)]
@@ -28,11 +71,17 @@ pub fn hz() -> Option<u64> {
 
 #[inline(always)]
 pub fn now() -> u64 {
+    now_with_cpu().0
+}
+
+/// TSC value and the IA32_TSC_AUX tag (CPU index) of the CPU it was read on.
+#[inline(always)]
+pub fn now_with_cpu() -> (u64, u32) {
     unsafe {
         let mut aux: u32 = 0;
         let tsc = __rdtscp(&mut aux as *mut u32);
         _mm_lfence();
-        tsc
+        (tsc, aux)
     }
 }
 
@@ -57,6 +106,14 @@ pub fn set_deadline_after_ticks(ticks: u64) {
 }
 
 impl TimerOps for TscTimer {
+    fn name(&self) -> &'static str {
+        "tsc-deadline"
+    }
+
+    fn rating(&self) -> u32 {
+        rating()
+    }
+
     fn now_ticks(&self) -> u64 {
         now()
     }
@@ -68,6 +125,19 @@ impl TimerOps for TscTimer {
     fn arm_one_shot(&self, deadline_ticks: u64) {
         set_deadline_tsc(deadline_ticks);
     }
+
+    fn activate(&self) {
+        apic::timer_mode_tsc_deadline();
+    }
+
+    fn activate_cpu(&self) {
+        apic::timer_mode_tsc_deadline();
+    }
+
+    fn deactivate(&self) {
+        set_deadline_tsc(0);
+        apic::timer_mask();
+    }
 }
 
 pub fn register_timer() {
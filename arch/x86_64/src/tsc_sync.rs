@@ -0,0 +1,187 @@
+//! Boot-time TSC synchronization check between the BSP and one AP.
+//!
+//! Run `bsp_check` on the BSP and `ap_check` on a freshly started AP at the
+//! same time; APs are checked one at a time. The pair first estimates the
+//! AP's offset from ping-pong round trips (the sample with the shortest
+//! round trip wins), corrects it through IA32_TSC_ADJUST when it is small
+//! enough, and then runs a warp test: both CPUs take turns reading the TSC
+//! under a lock and any read that goes backwards is a warp. An offset that
+//! cannot be corrected, or any remaining warp, marks the TSC unstable.
+
+use core::sync::atomic::{AtomicBool, AtomicI64, AtomicU32, AtomicU64, Ordering};
+
+use crate::msr::*;
+use crate::{cpuid, tsc};
+
+const PING_ROUNDS: u32 = 64;
+const WARP_ITERATIONS: u32 = 100_000;
+
+/// Offsets below this many cycles are measurement noise.
+const OFFSET_TOLERANCE: u64 = 1_000;
+/// Largest offset corrected through TSC_ADJUST, in ms of TSC time.
+const MAX_CORRECTION_MS: u64 = 100;
+
+/// Give up on an AP that stops responding.
+const MAX_SPINS: u64 = 100_000_000;
+
+const PHASE_IDLE: u32 = 0;
+const PHASE_ADJUST: u32 = 1;
+const PHASE_WARP: u32 = 2;
+
+static AP_ARRIVED: AtomicBool = AtomicBool::new(false);
+static PING: AtomicU32 = AtomicU32::new(0);
+static PONG: AtomicU32 = AtomicU32::new(0);
+static AP_TSC: AtomicU64 = AtomicU64::new(0);
+
+static PHASE: AtomicU32 = AtomicU32::new(PHASE_IDLE);
+static AP_DONE: AtomicU32 = AtomicU32::new(PHASE_IDLE);
+static ADJUST: AtomicI64 = AtomicI64::new(0);
+
+static WARP_LOCK: AtomicBool = AtomicBool::new(false);
+static WARP_LAST: AtomicU64 = AtomicU64::new(0);
+static WARP_MAX: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Clone, Copy, Debug)]
+pub struct SyncResult {
+    /// Measured AP - BSP offset in TSC cycles before correction.
+    pub offset: i64,
+    /// Offset was written into the AP's IA32_TSC_ADJUST.
+    pub adjusted: bool,
+    /// Largest backwards step seen in the warp test.
+    pub max_warp: u64,
+    /// False if the TSC has been marked unstable.
+    pub stable: bool,
+    /// False if the AP never showed up.
+    pub responded: bool,
+}
+
+fn spin_until(cond: impl Fn() -> bool) -> bool {
+    for _ in 0..MAX_SPINS {
+        if cond() {
+            return true;
+        }
+        core::hint::spin_loop();
+    }
+    false
+}
+
+fn reset() {
+    AP_ARRIVED.store(false, Ordering::Relaxed);
+    PING.store(0, Ordering::Relaxed);
+    PONG.store(0, Ordering::Relaxed);
+    AP_TSC.store(0, Ordering::Relaxed);
+    ADJUST.store(0, Ordering::Relaxed);
+    WARP_LOCK.store(false, Ordering::Relaxed);
+    WARP_LAST.store(0, Ordering::Relaxed);
+    WARP_MAX.store(0, Ordering::Relaxed);
+    AP_DONE.store(PHASE_IDLE, Ordering::Relaxed);
+    PHASE.store(PHASE_IDLE, Ordering::Release);
+}
+
+fn warp_loop() {
+    for _ in 0..WARP_ITERATIONS {
+        while WARP_LOCK.swap(true, Ordering::Acquire) {
+            core::hint::spin_loop();
+        }
+        let prev = WARP_LAST.load(Ordering::Relaxed);
+        let now = tsc::now();
+        WARP_LAST.store(now, Ordering::Relaxed);
+        WARP_LOCK.store(false, Ordering::Release);
+        if now < prev {
+            WARP_MAX.fetch_max(prev - now, Ordering::Relaxed);
+        }
+    }
+}
+
+/// BSP side. Returns once the AP has finished `ap_check`.
+pub fn bsp_check() -> SyncResult {
+    let mut result = SyncResult {
+        offset: 0,
+        adjusted: false,
+        max_warp: 0,
+        stable: !tsc::is_unstable(),
+        responded: false,
+    };
+    if !spin_until(|| AP_ARRIVED.load(Ordering::Acquire)) {
+        reset();
+        return result;
+    }
+
+    // Offset estimate from the tightest round trip.
+    let mut best_rtt = u64::MAX;
+    for round in 1..=PING_ROUNDS {
+        let t0 = tsc::now();
+        PING.store(round, Ordering::Release);
+        if !spin_until(|| PONG.load(Ordering::Acquire) == round) {
+            reset();
+            return result;
+        }
+        let t1 = tsc::now();
+        let rtt = t1.wrapping_sub(t0);
+        if rtt < best_rtt {
+            best_rtt = rtt;
+            let mid = t0.wrapping_add(rtt / 2);
+            result.offset = AP_TSC.load(Ordering::Relaxed).wrapping_sub(mid) as i64;
+        }
+    }
+    result.responded = true;
+
+    let magnitude = result.offset.unsigned_abs();
+    let max_correction = tsc::hz().unwrap_or(0) * MAX_CORRECTION_MS / 1000;
+    if magnitude > OFFSET_TOLERANCE.max(best_rtt / 2) {
+        if cpuid::has_tsc_adjust() && magnitude <= max_correction {
+            ADJUST.store(result.offset, Ordering::Relaxed);
+            result.adjusted = true;
+        } else {
+            tsc::mark_unstable();
+        }
+    }
+    PHASE.store(PHASE_ADJUST, Ordering::Release);
+    if !spin_until(|| AP_DONE.load(Ordering::Acquire) == PHASE_ADJUST) {
+        reset();
+        return result;
+    }
+
+    PHASE.store(PHASE_WARP, Ordering::Release);
+    warp_loop();
+    spin_until(|| AP_DONE.load(Ordering::Acquire) == PHASE_WARP);
+
+    result.max_warp = WARP_MAX.load(Ordering::Relaxed);
+    if result.max_warp != 0 {
+        tsc::mark_unstable();
+    }
+    result.stable = !tsc::is_unstable();
+    reset();
+    result
+}
+
+/// AP side. Call early on the AP, with interrupts disabled.
+pub fn ap_check() {
+    AP_ARRIVED.store(true, Ordering::Release);
+
+    for round in 1..=PING_ROUNDS {
+        if !spin_until(|| PING.load(Ordering::Acquire) == round) {
+            return;
+        }
+        AP_TSC.store(tsc::now(), Ordering::Relaxed);
+        PONG.store(round, Ordering::Release);
+    }
+
+    if !spin_until(|| PHASE.load(Ordering::Acquire) == PHASE_ADJUST) {
+        return;
+    }
+    let adjust = ADJUST.load(Ordering::Relaxed);
+    if adjust != 0 {
+        unsafe {
+            let cur = rdmsr(IA32_TSC_ADJUST) as i64;
+            wrmsr(IA32_TSC_ADJUST, cur.wrapping_sub(adjust) as u64);
+        }
+    }
+    AP_DONE.store(PHASE_ADJUST, Ordering::Release);
+
+    if !spin_until(|| PHASE.load(Ordering::Acquire) == PHASE_WARP) {
+        return;
+    }
+    warp_loop();
+    AP_DONE.store(PHASE_WARP, Ordering::Release);
+}
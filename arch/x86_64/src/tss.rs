@@ -1,4 +1,4 @@
-use core::cell::SyncUnsafeCell;
+use crate::percpu;
 
 #[repr(C, align(16))]
 pub struct Tss64 {
@@ -32,41 +32,72 @@ impl Tss64 {
 /// IST stacks
 const IST_STACK_SIZE: usize = 16 * 1024;
 
+/// IST slots (1-based, as encoded in the IDT gate).
+pub const IST_DF: u8 = 1;
+pub const IST_NMI: u8 = 2;
+pub const IST_MC: u8 = 3;
+pub const IST_DB: u8 = 4;
+
 #[repr(align(16))]
-struct Stack([u8; IST_STACK_SIZE]);
+pub(crate) struct Stack([u8; IST_STACK_SIZE]);
 
-#[unsafe(link_section = ".bss.boot")]
-static mut DF_IST_STACK: Stack = Stack([0; IST_STACK_SIZE]);
+/// One CPU's IST stacks; lives in its per-CPU area.
+pub(crate) struct IstStacks {
+    df: Stack,
+    nmi: Stack,
+    mc: Stack,
+    db: Stack,
+}
+
+impl IstStacks {
+    pub(crate) const fn new() -> Self {
+        Self {
+            df: Stack([0; IST_STACK_SIZE]),
+            nmi: Stack([0; IST_STACK_SIZE]),
+            mc: Stack([0; IST_STACK_SIZE]),
+            db: Stack([0; IST_STACK_SIZE]),
+        }
+    }
+}
 
 #[inline(always)]
 fn stack_top(s: *const Stack) -> u64 {
     (s as u64) + (IST_STACK_SIZE as u64)
 }
 
-#[unsafe(link_section = ".bss.boot")]
-static TSS: SyncUnsafeCell<Tss64> = SyncUnsafeCell::new(Tss64 {
-    _rsv0: 0,
-    rsp0: 0,
-    rsp1: 0,
-    rsp2: 0,
-    _rsv3: 0,
-    ist: [0; 7],
-    _rsv1: 0,
-    _rsv2: 0,
-    iopb_offset: 0,
-});
+/// All-zero TSS for per-CPU storage that must stay in .bss; `init_tss`
+/// fills it in.
+pub(crate) const fn zeroed() -> Tss64 {
+    Tss64 {
+        _rsv0: 0,
+        rsp0: 0,
+        rsp1: 0,
+        rsp2: 0,
+        _rsv3: 0,
+        ist: [0; 7],
+        _rsv1: 0,
+        _rsv2: 0,
+        iopb_offset: 0,
+    }
+}
 unsafe impl Sync for Tss64 {}
 
+/// `cpu`'s TSS. One per CPU: a TSS is marked busy once loaded, so CPUs
+/// cannot share.
 #[inline(always)]
-pub fn tss_ptr() -> *mut Tss64 {
-    TSS.get()
+pub fn tss_ptr(cpu: usize) -> *mut Tss64 {
+    percpu::area(cpu).tss.get()
 }
 
-pub fn init_tss(rsp0_top: u64) {
+pub fn init_tss(cpu: usize, rsp0_top: u64) {
     unsafe {
-        let tss = tss_ptr();
+        let tss = tss_ptr(cpu);
+        let stacks = percpu::area(cpu).ist.get() as *const IstStacks;
         (*tss).rsp0 = rsp0_top;
-        (*tss).ist[0] = stack_top(core::ptr::addr_of_mut!(DF_IST_STACK).cast::<Stack>());
+        (*tss).ist[(IST_DF - 1) as usize] = stack_top(core::ptr::addr_of!((*stacks).df));
+        (*tss).ist[(IST_NMI - 1) as usize] = stack_top(core::ptr::addr_of!((*stacks).nmi));
+        (*tss).ist[(IST_MC - 1) as usize] = stack_top(core::ptr::addr_of!((*stacks).mc));
+        (*tss).ist[(IST_DB - 1) as usize] = stack_top(core::ptr::addr_of!((*stacks).db));
         (*tss).iopb_offset = core::mem::size_of::<Tss64>() as u16;
     }
 }
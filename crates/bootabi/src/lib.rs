@@ -1,7 +1,7 @@
 #![no_std]
 
 pub const BOOTABI_MAGIC: u32 = 0x424f4f54; // 'BOOT'
-pub const BOOTABI_VERSION: u16 = 1;
+pub const BOOTABI_VERSION: u16 = 2;
 
 #[repr(C)]
 #[derive(Clone, Copy, Debug)]
@@ -135,6 +135,32 @@ pub struct MemMapView {
     pub flags: u16,
 }
 
+#[repr(u32)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CpuFlags {
+    NONE = 0,
+    BSP = 1 << 0,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct CpuEntry {
+    /// Hardware ID: LAPIC ID on x86_64, MPIDR on aarch64
+    pub hw_id: u64,
+    /// ACPI processor UID (0 if unknown)
+    pub acpi_id: u32,
+    pub flags: u32,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct CpuListView {
+    pub entries_ptr: u64,
+    pub entry_count: u32,
+    /// Index of the boot CPU in the list
+    pub bsp_index: u32,
+}
+
 #[repr(u8)]
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum FbFormat {
@@ -193,4 +219,7 @@ pub struct BootInfo {
     /// Base revision
     pub boot_flags: u32,
     pub _pad2: u32,
+
+    /// CPUs present at boot, boot CPU included (entries_ptr is virtual)
+    pub cpus: CpuListView,
 }
@@ -3,6 +3,7 @@
 use bootabi::*;
 use core::ffi::c_char;
 use core::mem::MaybeUninit;
+use core::sync::atomic::{AtomicUsize, Ordering};
 use limine::BaseRevision;
 use limine::firmware_type::FirmwareType;
 use limine::request::*;
@@ -36,6 +37,9 @@ static CMDLINE: ExecutableCmdlineRequest = ExecutableCmdlineRequest::new();
 static FIRMWARE_TYPE: FirmwareTypeRequest = FirmwareTypeRequest::new();
 #[used]
 #[unsafe(link_section = ".limine_reqs")]
+static MP: MpRequest = MpRequest::new();
+#[used]
+#[unsafe(link_section = ".limine_reqs")]
 static STACK_SIZE: StackSizeRequest = StackSizeRequest::new().with_size(128 * 1024);
 #[used]
 #[unsafe(link_section = ".limine_reqs_end_marker")]
@@ -48,6 +52,13 @@ const MAX_MEMMAP: usize = 1024;
 #[unsafe(link_section = ".bss.boot")]
 static mut MEMBUF: MaybeUninit<[MemMapEntry; MAX_MEMMAP]> = MaybeUninit::uninit();
 
+// Fixed-capacity array for the CPU list
+const MAX_BOOT_CPUS: usize = 256;
+
+#[used]
+#[unsafe(link_section = ".bss.boot")]
+static mut CPUBUF: MaybeUninit<[CpuEntry; MAX_BOOT_CPUS]> = MaybeUninit::uninit();
+
 #[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
 compile_error!("bootloader_limine: unsupported target_arch");
 
@@ -77,6 +88,11 @@ unsafe fn membuf_mut() -> &'static mut [MemMapEntry; MAX_MEMMAP] {
     unsafe { &mut *core::ptr::addr_of_mut!(MEMBUF).cast::<[MemMapEntry; MAX_MEMMAP]>() }
 }
 
+#[inline(always)]
+unsafe fn cpubuf_mut() -> &'static mut [CpuEntry; MAX_BOOT_CPUS] {
+    unsafe { &mut *core::ptr::addr_of_mut!(CPUBUF).cast::<[CpuEntry; MAX_BOOT_CPUS]>() }
+}
+
 #[inline(always)]
 unsafe fn strbuf_mut() -> &'static mut [u8; STRBUF_SIZE] {
     unsafe { &mut *core::ptr::addr_of_mut!(STRBUF).cast::<[u8; STRBUF_SIZE]>() }
@@ -219,14 +235,14 @@ fn strbuf_alloc_join(a: &[u8], b: &[u8]) -> ByteSpan {
     }
 }
 
-pub fn gather_bootinfo(cpu_count: u32) -> BootInfo {
+pub fn gather_bootinfo() -> BootInfo {
     let arch = current_arch();
     unsafe {
         STRBUF_USED = 0;
     }
 
     if !BASE_REVISION.is_valid() || !BASE_REVISION.is_supported() {
-        return minimal_bootinfo(arch, cpu_count);
+        return minimal_bootinfo(arch);
     }
 
     let boot_mode = match FIRMWARE_TYPE.get_response().map(|r| r.firmware_type()) {
@@ -307,6 +323,11 @@ pub fn gather_bootinfo(cpu_count: u32) -> BootInfo {
         })
         .unwrap_or(ByteSpan::empty());
 
+    let cpus = MP
+        .get_response()
+        .map(convert_cpus)
+        .unwrap_or_else(boot_cpu_only);
+
     let (phys_bits, virt_bits) = default_addr_bits(arch);
 
     BootInfo {
@@ -318,7 +339,7 @@ pub fn gather_bootinfo(cpu_count: u32) -> BootInfo {
         arch,
         boot_mode: boot_mode.as_raw(),
         _pad0: [0; 2],
-        cpu_count,
+        cpu_count: cpus.entry_count,
         phys_addr_bits: phys_bits,
         virt_addr_bits: virt_bits,
         _pad1: [0; 2],
@@ -333,10 +354,11 @@ pub fn gather_bootinfo(cpu_count: u32) -> BootInfo {
         bootloader,
         boot_flags: BootFlags::NONE as u32 | memmap_flags,
         _pad2: 0,
+        cpus,
     }
 }
 
-fn minimal_bootinfo(arch: Arch, cpu_count: u32) -> BootInfo {
+fn minimal_bootinfo(arch: Arch) -> BootInfo {
     let (phys_bits, virt_bits) = default_addr_bits(arch);
     BootInfo {
         hdr: BootHeader {
@@ -347,7 +369,7 @@ fn minimal_bootinfo(arch: Arch, cpu_count: u32) -> BootInfo {
         arch,
         boot_mode: BootMode::Unknown.as_raw(),
         _pad0: [0; 2],
-        cpu_count,
+        cpu_count: 1,
         phys_addr_bits: phys_bits,
         virt_addr_bits: virt_bits,
         _pad1: [0; 2],
@@ -372,6 +394,7 @@ fn minimal_bootinfo(arch: Arch, cpu_count: u32) -> BootInfo {
         bootloader: ByteSpan::empty(),
         boot_flags: BootFlags::NONE as u32,
         _pad2: 0,
+        cpus: boot_cpu_only(),
     }
 }
 
@@ -449,3 +472,112 @@ fn map_memtype(t: limine::memory_map::EntryType) -> MemType {
         _ => MemType::Reserved,
     }
 }
+
+/// CPU list when the bootloader did not answer the MP request: just the
+/// boot CPU, with an unknown hardware ID.
+fn boot_cpu_only() -> CpuListView {
+    let entries_ptr = unsafe {
+        let cpubuf = cpubuf_mut();
+        cpubuf[0] = CpuEntry {
+            hw_id: 0,
+            acpi_id: 0,
+            flags: CpuFlags::BSP as u32,
+        };
+        cpubuf.as_ptr() as u64
+    };
+    CpuListView {
+        entries_ptr,
+        entry_count: 1,
+        bsp_index: 0,
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+fn cpu_hw_id(cpu: &limine::mp::Cpu) -> u64 {
+    cpu.lapic_id as u64
+}
+
+#[cfg(target_arch = "x86_64")]
+fn bsp_hw_id(resp: &limine::response::MpResponse) -> u64 {
+    resp.bsp_lapic_id() as u64
+}
+
+#[cfg(target_arch = "aarch64")]
+fn cpu_hw_id(cpu: &limine::mp::Cpu) -> u64 {
+    cpu.mpidr
+}
+
+#[cfg(target_arch = "aarch64")]
+fn bsp_hw_id(resp: &limine::response::MpResponse) -> u64 {
+    resp.bsp_mpidr()
+}
+
+fn convert_cpus(resp: &limine::response::MpResponse) -> CpuListView {
+    let cpus = resp.cpus();
+    if cpus.is_empty() {
+        return boot_cpu_only();
+    }
+
+    let bsp = bsp_hw_id(resp);
+    let n = core::cmp::min(cpus.len(), MAX_BOOT_CPUS);
+    let mut bsp_index = 0;
+    let entries_ptr = unsafe {
+        let cpubuf = cpubuf_mut();
+        for (i, cpu) in cpus.iter().take(n).enumerate() {
+            let hw_id = cpu_hw_id(cpu);
+            let flags = if hw_id == bsp {
+                bsp_index = i as u32;
+                CpuFlags::BSP as u32
+            } else {
+                CpuFlags::NONE as u32
+            };
+            cpubuf[i] = CpuEntry {
+                hw_id,
+                acpi_id: cpu.id,
+                flags,
+            };
+        }
+        cpubuf.as_ptr() as u64
+    };
+
+    CpuListView {
+        entries_ptr,
+        entry_count: n as u32,
+        bsp_index,
+    }
+}
+
+/// Entry point for application processors. It runs on a stack in
+/// bootloader-reclaimable memory and receives the CPU's index in
+/// `BootInfo::cpus`.
+pub type ApEntry = extern "C" fn(u32) -> !;
+
+static AP_ENTRY: AtomicUsize = AtomicUsize::new(0);
+
+unsafe extern "C" fn ap_trampoline(cpu: &limine::mp::Cpu) -> ! {
+    let entry = AP_ENTRY.load(Ordering::Acquire);
+    let entry: ApEntry = unsafe { core::mem::transmute::<usize, ApEntry>(entry) };
+    entry(cpu.extra.load(Ordering::Acquire) as u32)
+}
+
+/// Send every application processor listed in `BootInfo::cpus` to
+/// `entry`. Call before bootloader-reclaimable memory is reused: the APs
+/// wait for their start address there. Returns how many were started.
+pub fn start_aps(entry: ApEntry) -> u32 {
+    let Some(resp) = MP.get_response() else {
+        return 0;
+    };
+    AP_ENTRY.store(entry as usize, Ordering::Release);
+
+    let bsp = bsp_hw_id(resp);
+    let mut started = 0;
+    for (i, cpu) in resp.cpus().iter().take(MAX_BOOT_CPUS).enumerate() {
+        if cpu_hw_id(cpu) == bsp {
+            continue;
+        }
+        cpu.extra.store(i as u64, Ordering::Release);
+        cpu.goto_address.write(ap_trampoline);
+        started += 1;
+    }
+    started
+}
@@ -0,0 +1,74 @@
+/// Saved kernel execution context of a thread that is not running.
+///
+/// Only the stack pointer lives here; the arch switch routine keeps the
+/// callee-saved registers on the thread's own stack.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Context {
+    pub sp: u64,
+}
+
+/// Entry point of a new thread. Receives the `arg` given to `init` and
+/// must never return.
+pub type ThreadEntry = extern "C" fn(arg: usize) -> !;
+
+pub trait ContextOps {
+    /// Build an initial frame on the stack ending at `stack_top` so that
+    /// switching to `ctx` calls `entry(arg)` on that stack.
+    ///
+    /// # Safety
+    ///
+    /// `stack_top` ends a stack that nothing else uses, and `ctx` is not
+    /// switched to until this returns.
+    unsafe fn init(&self, ctx: &mut Context, stack_top: u64, entry: ThreadEntry, arg: usize);
+
+    /// Save the current context into `from` and resume `to`. Returns when
+    /// something switches back to `from`.
+    ///
+    /// # Safety
+    ///
+    /// `from` is writable, `to` holds a context saved by `switch` or built
+    /// by `init`, and no other CPU is running `to`'s thread.
+    unsafe fn switch(&self, from: *mut Context, to: *const Context);
+}
+
+static mut OPS: Option<&'static dyn ContextOps> = None;
+
+/// Install arch's context switch.
+///
+/// # Safety
+///
+/// Call once, during single-threaded boot and before anything uses
+/// the ops.
+pub unsafe fn register_context_ops(ops: &'static dyn ContextOps) {
+    unsafe {
+        OPS = Some(ops);
+    }
+}
+
+/// `ContextOps::init` through the registered ops.
+///
+/// # Safety
+///
+/// As for `ContextOps::init`.
+pub unsafe fn init(ctx: &mut Context, stack_top: u64, entry: ThreadEntry, arg: usize) {
+    unsafe {
+        if let Some(ops) = OPS {
+            ops.init(ctx, stack_top, entry, arg);
+        }
+    }
+}
+
+/// `ContextOps::switch` through the registered ops.
+///
+/// # Safety
+///
+/// As for `ContextOps::switch`.
+#[inline(always)]
+pub unsafe fn switch(from: *mut Context, to: *const Context) {
+    unsafe {
+        if let Some(ops) = OPS {
+            ops.switch(from, to);
+        }
+    }
+}
@@ -0,0 +1,114 @@
+use core::sync::atomic::{AtomicPtr, AtomicU64, AtomicUsize, Ordering};
+
+/// Upper bound on CPUs any per-CPU table in hal, arch or kernel is sized for.
+pub const MAX_CPUS: usize = 64;
+
+/// Header at the start of every per-CPU area.
+///
+/// Arch keeps a register (GS base on x86_64, TPIDR_EL1 on aarch64)
+/// pointing at the running CPU's area, so finding it costs one load. The
+/// rest of the area belongs to arch.
+///
+/// CPUs are numbered densely from 0 in the order they come up, the boot
+/// CPU first; the hardware ID is kept alongside for interrupt routing.
+#[repr(C)]
+pub struct PerCpu {
+    /// Address of this header, readable through the per-CPU register.
+    this: AtomicPtr<PerCpu>,
+    index: AtomicUsize,
+    /// LAPIC ID on x86_64, MPIDR affinity on aarch64.
+    hw_id: AtomicU64,
+}
+
+/// Offset of the logical index in `PerCpu`, for arch code that loads it
+/// relative to the per-CPU register.
+pub const PERCPU_INDEX_OFFSET: usize = 8;
+
+impl PerCpu {
+    pub const fn new() -> Self {
+        Self {
+            this: AtomicPtr::new(core::ptr::null_mut()),
+            index: AtomicUsize::new(0),
+            hw_id: AtomicU64::new(0),
+        }
+    }
+
+    /// Stamp the header with its CPU's identity before the area is
+    /// installed.
+    pub fn init(&self, index: usize, hw_id: u64) {
+        self.index.store(index, Ordering::Relaxed);
+        self.hw_id.store(hw_id, Ordering::Relaxed);
+        self.this
+            .store(self as *const Self as *mut Self, Ordering::Release);
+    }
+
+    /// Whether `init` has run.
+    pub fn is_present(&self) -> bool {
+        !self.this.load(Ordering::Acquire).is_null()
+    }
+
+    #[inline(always)]
+    pub fn index(&self) -> usize {
+        self.index.load(Ordering::Relaxed)
+    }
+
+    #[inline(always)]
+    pub fn hw_id(&self) -> u64 {
+        self.hw_id.load(Ordering::Relaxed)
+    }
+}
+
+impl Default for PerCpu {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub trait PerCpuOps {
+    /// Header of the executing CPU's area.
+    fn this_cpu(&self) -> &'static PerCpu;
+
+    /// Header of logical CPU `index`; not present until that CPU has set
+    /// up its area.
+    fn cpu(&self, index: usize) -> &'static PerCpu;
+}
+
+static mut OPS: Option<&'static dyn PerCpuOps> = None;
+
+/// Install arch's per-CPU area lookup.
+///
+/// # Safety
+///
+/// Call once, during single-threaded boot and before anything uses
+/// the ops.
+pub unsafe fn register_percpu_ops(ops: &'static dyn PerCpuOps) {
+    unsafe {
+        OPS = Some(ops);
+    }
+}
+
+/// Header of the executing CPU's area, if arch has registered per-CPU
+/// areas.
+#[inline(always)]
+pub fn this_cpu() -> Option<&'static PerCpu> {
+    unsafe { OPS.map(|ops| ops.this_cpu()) }
+}
+
+/// Logical index of the executing CPU; 0 before per-CPU areas exist.
+#[inline(always)]
+pub fn current() -> usize {
+    this_cpu().map_or(0, PerCpu::index)
+}
+
+/// Header of logical CPU `index`, if it has one.
+pub fn cpu(index: usize) -> Option<&'static PerCpu> {
+    if index >= MAX_CPUS {
+        return None;
+    }
+    unsafe { OPS.map(|ops| ops.cpu(index)) }.filter(|c| c.is_present())
+}
+
+/// Hardware ID of logical CPU `index`.
+pub fn hw_id(index: usize) -> Option<u64> {
+    cpu(index).map(PerCpu::hw_id)
+}
@@ -5,6 +5,8 @@ pub enum IrqKind {
     Timer = 2,
     External = 3,
     Spurious = 4,
+    Nmi = 5,
+    Ipi = 6,
     Unknown = 0xff,
 }
 
@@ -17,21 +19,65 @@ pub enum FaultKind {
     InvalidOpcode = 3,
     DivideByZero = 4,
     DoubleFault = 5,
+    Debug = 6,
+    MachineCheck = 7,
     Unknown = 0xff,
 }
 
 #[repr(C)]
 #[derive(Clone, Copy)]
 pub struct IrqFrame {
+    /// Index of the CPU that took the interrupt.
+    pub cpu: u32,
     pub kind: IrqKind,
     pub fault_kind: FaultKind,
     pub irq: u16,
     pub error_code: u64,
     pub fault_addr: u64,
+    /// Program counter of the interrupted context.
+    pub pc: u64,
+    /// Stack pointer of the interrupted context.
+    pub sp: u64,
+}
+
+#[repr(u8)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum McSeverity {
+    /// Hardware corrected the error; informational.
+    Corrected = 0,
+    /// Uncorrected, but the interrupted context can be restarted.
+    Recoverable = 1,
+    /// Uncorrected and the interrupted context is lost.
+    Fatal = 2,
+}
+
+/// One hardware error record reported by a machine-check exception.
+///
+/// `syndrome`/`misc` carry the raw error-bank contents for logging only;
+/// their encoding is architecture-defined and must not drive policy.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct MachineCheckRecord {
+    pub cpu: u32,
+    /// Error bank / error record index (`u16::MAX` if no bank was valid).
+    pub bank: u16,
+    pub severity: McSeverity,
+    pub addr_valid: bool,
+    pub syndrome: u64,
+    pub addr: u64,
+    pub misc: u64,
+    pub pc: u64,
 }
 
 pub trait InterruptHandler {
     fn on_interrupt(&self, frame: IrqFrame);
+
+    /// Called by arch after the interrupt has been acknowledged (EOI),
+    /// just before returning to the interrupted context.
+    fn on_interrupt_exit(&self) {}
+
+    /// Called once per error record from the machine-check path.
+    fn on_machine_check(&self, _rec: &MachineCheckRecord) {}
 }
 
 static mut HANDLER_DATA: usize = 0;
@@ -49,16 +95,99 @@ pub unsafe fn register_handler(h: &'static dyn InterruptHandler) {
 }
 
 #[inline(always)]
-pub fn dispatch(frame: IrqFrame) {
+unsafe fn handler() -> Option<&'static dyn InterruptHandler> {
     unsafe {
         if HANDLER_SET == 0 {
-            return;
+            return None;
         }
-        let ptr: *const dyn InterruptHandler =
-            core::mem::transmute::<(usize, usize), *const dyn InterruptHandler>((
-                HANDLER_DATA,
-                HANDLER_VTABLE,
-            ));
-        (&*ptr).on_interrupt(frame);
+        let ptr: *const dyn InterruptHandler = core::mem::transmute::<
+            (usize, usize),
+            *const dyn InterruptHandler,
+        >((HANDLER_DATA, HANDLER_VTABLE));
+        Some(&*ptr)
+    }
+}
+
+#[inline(always)]
+pub fn dispatch(frame: IrqFrame) {
+    let start = crate::time::now_ticks();
+    if let Some(h) = unsafe { handler() } {
+        h.on_interrupt(frame);
+    }
+    let ticks = crate::time::now_ticks().wrapping_sub(start);
+    crate::irqstats::account(frame.cpu, frame.kind, frame.irq, ticks);
+}
+
+#[inline(always)]
+pub fn dispatch_exit() {
+    if let Some(h) = unsafe { handler() } {
+        h.on_interrupt_exit();
+    }
+}
+
+#[inline(always)]
+pub fn dispatch_machine_check(rec: &MachineCheckRecord) {
+    if let Some(h) = unsafe { handler() } {
+        h.on_machine_check(rec);
+    }
+}
+
+/// Device interrupt lines: the `irq` of `IrqKind::External` frames.
+///
+/// Arch maps lines onto its interrupt controller (IOAPIC pins on x86_64)
+/// and EOIs every interrupt itself. Masking is how the kernel holds a
+/// line off while a driver handles it.
+pub trait IrqLineOps {
+    /// Lines `0..count()` exist.
+    fn count(&self) -> u16;
+
+    /// Route `line` to a CPU and unmask it. Fails if the line is missing
+    /// or already routed elsewhere.
+    fn enable(&self, line: u16) -> bool;
+
+    fn mask(&self, line: u16);
+
+    fn unmask(&self, line: u16);
+}
+
+static mut LINE_OPS: Option<&'static dyn IrqLineOps> = None;
+
+/// Install arch's device line control.
+///
+/// # Safety
+///
+/// Call once, during single-threaded boot and before anything uses
+/// the ops.
+pub unsafe fn register_irq_line_ops(ops: &'static dyn IrqLineOps) {
+    unsafe {
+        LINE_OPS = Some(ops);
+    }
+}
+
+/// Device lines arch can deliver; 0 until it registers line ops.
+pub fn line_count() -> u16 {
+    unsafe { LINE_OPS }.map_or(0, |ops| ops.count())
+}
+
+pub fn enable_line(line: u16) -> bool {
+    match unsafe { LINE_OPS } {
+        Some(ops) if line < ops.count() => ops.enable(line),
+        _ => false,
+    }
+}
+
+pub fn mask_line(line: u16) {
+    if let Some(ops) = unsafe { LINE_OPS }
+        && line < ops.count()
+    {
+        ops.mask(line);
+    }
+}
+
+pub fn unmask_line(line: u16) {
+    if let Some(ops) = unsafe { LINE_OPS }
+        && line < ops.count()
+    {
+        ops.unmask(line);
     }
 }
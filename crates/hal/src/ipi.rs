@@ -0,0 +1,91 @@
+//! Inter-processor interrupts.
+//!
+//! Arch maps each `IpiKind` to a vector it reserves (x86_64) or an SGI
+//! (aarch64 GIC) and addresses CPUs by their logical index. On receipt,
+//! reschedule needs no handler of its own: the kernel's interrupt-exit
+//! hook does the work. The other kinds are dispatched as `IrqKind::Ipi`
+//! frames whose `irq` is the kind.
+
+use crate::cpu::MAX_CPUS;
+
+/// Kinds of IPI the kernel can send. The value is the `IrqFrame::irq` of
+/// the dispatched frame and the `irqstats` IPI line.
+#[repr(u16)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IpiKind {
+    /// Make the target run its interrupt-exit path and reschedule.
+    Reschedule = 1,
+    /// Run the functions queued for the target by `smp_call_function`.
+    CallFunction = 2,
+    /// Stop the target for good.
+    Stop = 3,
+}
+
+impl IpiKind {
+    pub fn from_irq(irq: u16) -> Option<Self> {
+        match irq {
+            1 => Some(Self::Reschedule),
+            2 => Some(Self::CallFunction),
+            3 => Some(Self::Stop),
+            _ => None,
+        }
+    }
+}
+
+/// Who an IPI goes to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IpiTarget {
+    /// One CPU by logical index.
+    Cpu(usize),
+    /// Every CPU whose bit is set, by logical index. May include the
+    /// sender.
+    Mask(u64),
+    /// Every CPU except the sender.
+    AllButSelf,
+}
+
+pub trait IpiOps {
+    /// Send `kind` to one CPU. Returns immediately; delivery is
+    /// asynchronous.
+    fn send_one(&self, cpu: usize, kind: IpiKind);
+
+    /// Send `kind` to every CPU but the sender.
+    fn broadcast(&self, kind: IpiKind);
+
+    /// Send `kind` to every CPU in `mask`. The default sends one at a
+    /// time.
+    fn send_mask(&self, mask: u64, kind: IpiKind) {
+        for cpu in 0..MAX_CPUS {
+            if mask & (1 << cpu) != 0 {
+                self.send_one(cpu, kind);
+            }
+        }
+    }
+}
+
+static mut OPS: Option<&'static dyn IpiOps> = None;
+
+/// Install arch's IPI delivery.
+///
+/// # Safety
+///
+/// Call once, during single-threaded boot and before anything uses
+/// the ops.
+pub unsafe fn register_ipi_ops(ops: &'static dyn IpiOps) {
+    unsafe {
+        OPS = Some(ops);
+    }
+}
+
+/// Send `kind` to `target`. A no-op until arch registers its IPI ops.
+pub fn send(target: IpiTarget, kind: IpiKind) {
+    let Some(ops) = (unsafe { OPS }) else {
+        return;
+    };
+    match target {
+        IpiTarget::Cpu(cpu) if cpu < MAX_CPUS => ops.send_one(cpu, kind),
+        IpiTarget::Cpu(_) => {}
+        IpiTarget::Mask(mask) => ops.send_mask(mask, kind),
+        IpiTarget::AllButSelf => ops.broadcast(kind),
+    }
+}
@@ -0,0 +1,165 @@
+//! Per-CPU interrupt accounting.
+//!
+//! Every dispatch through `hal::interrupt` is counted here together with
+//! the time spent in the handler (in `hal::time` ticks). Arch paths that
+//! handle an interrupt without dispatching it (e.g. internal IPIs) call
+//! [`account`] directly so the totals stay complete.
+
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use crate::cpu::MAX_CPUS;
+use crate::interrupt::IrqKind;
+use crate::ipi::IpiKind;
+
+/// Slots in [`IrqStatsSnapshot::kinds`], one per `IrqKind`.
+pub const STAT_KINDS: usize = 7;
+/// External lines tracked individually; higher lines fold into the last slot.
+pub const STAT_LINES: usize = 64;
+/// IPI kinds tracked individually; higher kinds fold into the last slot.
+pub const STAT_IPIS: usize = 8;
+
+/// `Ipi` line used for TLB shootdowns handled entirely inside arch.
+pub const IPI_TLB_SHOOTDOWN: u16 = 0;
+/// `Ipi` line of the scheduler's reschedule kick.
+pub const IPI_RESCHEDULE: u16 = IpiKind::Reschedule as u16;
+/// `Ipi` line of `smp_call_function` requests.
+pub const IPI_CALL_FUNCTION: u16 = IpiKind::CallFunction as u16;
+/// `Ipi` line of stop requests.
+pub const IPI_STOP: u16 = IpiKind::Stop as u16;
+
+/// `IrqKind`s in `kinds` slot order.
+pub const KINDS: [IrqKind; STAT_KINDS] = [
+    IrqKind::Fault,
+    IrqKind::Timer,
+    IrqKind::External,
+    IrqKind::Spurious,
+    IrqKind::Nmi,
+    IrqKind::Ipi,
+    IrqKind::Unknown,
+];
+
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+pub struct IrqCounter {
+    pub count: u64,
+    /// Total handler time in timer ticks.
+    pub ticks: u64,
+    /// Longest single handler run in timer ticks.
+    pub max_ticks: u64,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct IrqStatsSnapshot {
+    pub cpu: u32,
+    pub _pad: u32,
+    pub kinds: [IrqCounter; STAT_KINDS],
+    pub lines: [IrqCounter; STAT_LINES],
+    pub ipis: [IrqCounter; STAT_IPIS],
+}
+
+struct Counter {
+    count: AtomicU64,
+    ticks: AtomicU64,
+    max_ticks: AtomicU64,
+}
+
+impl Counter {
+    const fn new() -> Self {
+        Self {
+            count: AtomicU64::new(0),
+            ticks: AtomicU64::new(0),
+            max_ticks: AtomicU64::new(0),
+        }
+    }
+
+    #[inline(always)]
+    fn add(&self, ticks: u64) {
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.ticks.fetch_add(ticks, Ordering::Relaxed);
+        self.max_ticks.fetch_max(ticks, Ordering::Relaxed);
+    }
+
+    fn read(&self) -> IrqCounter {
+        IrqCounter {
+            count: self.count.load(Ordering::Relaxed),
+            ticks: self.ticks.load(Ordering::Relaxed),
+            max_ticks: self.max_ticks.load(Ordering::Relaxed),
+        }
+    }
+}
+
+struct CpuStats {
+    kinds: [Counter; STAT_KINDS],
+    lines: [Counter; STAT_LINES],
+    ipis: [Counter; STAT_IPIS],
+}
+
+impl CpuStats {
+    const fn new() -> Self {
+        Self {
+            kinds: [const { Counter::new() }; STAT_KINDS],
+            lines: [const { Counter::new() }; STAT_LINES],
+            ipis: [const { Counter::new() }; STAT_IPIS],
+        }
+    }
+}
+
+static STATS: [CpuStats; MAX_CPUS] = [const { CpuStats::new() }; MAX_CPUS];
+
+#[inline(always)]
+pub const fn kind_slot(kind: IrqKind) -> usize {
+    match kind {
+        IrqKind::Fault => 0,
+        IrqKind::Timer => 1,
+        IrqKind::External => 2,
+        IrqKind::Spurious => 3,
+        IrqKind::Nmi => 4,
+        IrqKind::Ipi => 5,
+        IrqKind::Unknown => 6,
+    }
+}
+
+/// Record one handled interrupt of `kind` on `cpu`.
+///
+/// `line` is the external line for `External` and the IPI kind for `Ipi`;
+/// it is ignored otherwise.
+#[inline(always)]
+pub fn account(cpu: u32, kind: IrqKind, line: u16, ticks: u64) {
+    let cpu = cpu as usize;
+    if cpu >= MAX_CPUS {
+        return;
+    }
+    let stats = &STATS[cpu];
+    stats.kinds[kind_slot(kind)].add(ticks);
+    match kind {
+        IrqKind::External => stats.lines[(line as usize).min(STAT_LINES - 1)].add(ticks),
+        IrqKind::Ipi => stats.ipis[(line as usize).min(STAT_IPIS - 1)].add(ticks),
+        _ => {}
+    }
+}
+
+/// Copy out the current counters for `cpu` (zeroed if out of range).
+pub fn snapshot(cpu: usize) -> IrqStatsSnapshot {
+    let mut snap = IrqStatsSnapshot {
+        cpu: cpu as u32,
+        _pad: 0,
+        kinds: [IrqCounter::default(); STAT_KINDS],
+        lines: [IrqCounter::default(); STAT_LINES],
+        ipis: [IrqCounter::default(); STAT_IPIS],
+    };
+    if cpu >= MAX_CPUS {
+        return snap;
+    }
+    let stats = &STATS[cpu];
+    for (dst, src) in snap.kinds.iter_mut().zip(stats.kinds.iter()) {
+        *dst = src.read();
+    }
+    for (dst, src) in snap.lines.iter_mut().zip(stats.lines.iter()) {
+        *dst = src.read();
+    }
+    for (dst, src) in snap.ipis.iter_mut().zip(stats.ipis.iter()) {
+        *dst = src.read();
+    }
+    snap
+}
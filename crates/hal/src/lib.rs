@@ -1,7 +1,12 @@
 #![no_std]
 
+pub mod context;
+pub mod cpu;
 pub mod interrupt;
+pub mod ipi;
+pub mod irqstats;
 pub mod mmu;
+pub mod rtc;
 pub mod serial;
 pub mod time;
 
@@ -0,0 +1,36 @@
+/// Calendar time as read from a hardware real-time clock (UTC).
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RtcTime {
+    pub year: u16,
+    /// 1..=12
+    pub month: u8,
+    /// 1..=31
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+}
+
+pub trait RtcOps {
+    /// Read the current calendar time, or `None` if the clock is unusable.
+    fn read(&self) -> Option<RtcTime>;
+}
+
+static mut RTC: Option<&'static dyn RtcOps> = None;
+
+/// Install the hardware real-time clock.
+///
+/// # Safety
+///
+/// Call once, during single-threaded boot and before anything uses
+/// the ops.
+pub unsafe fn register_rtc(r: &'static dyn RtcOps) {
+    unsafe {
+        RTC = Some(r);
+    }
+}
+
+pub fn read() -> Option<RtcTime> {
+    unsafe { RTC.and_then(|r| r.read()) }
+}
@@ -1,32 +1,127 @@
+use core::sync::atomic::{AtomicUsize, Ordering};
+
 pub trait TimerOps {
+    /// Short name for logs ("tsc-deadline", "hpet", ...).
+    fn name(&self) -> &'static str;
+    /// Quality rating; higher is better. The kernel picks the highest.
+    fn rating(&self) -> u32;
     fn now_ticks(&self) -> u64;
     fn frequency_hz(&self) -> u64;
     fn arm_one_shot(&self, deadline_ticks: u64);
+    /// Called when this timer becomes the active one.
+    fn activate(&self) {}
+    /// Called when another timer replaces this one.
+    fn deactivate(&self) {}
+    /// Called on each CPU that comes up after this timer became active,
+    /// to program its local interrupt source.
+    fn activate_cpu(&self) {}
+}
+
+/// Outcome of checking a newly started CPU's counter against the boot
+/// CPU's.
+#[derive(Clone, Copy, Debug)]
+pub struct ClockSync {
+    /// Measured offset of the new CPU, in counter ticks, before correction.
+    pub offset: i64,
+    /// The offset was corrected on the new CPU.
+    pub adjusted: bool,
+    /// Largest backwards step seen between the two CPUs.
+    pub max_warp: u64,
+    /// False if the counter can no longer be trusted across CPUs.
+    pub stable: bool,
+    /// False if the new CPU never took part.
+    pub responded: bool,
 }
 
-static mut TIMER: Option<&'static dyn TimerOps> = None;
+/// Maximum number of registered timer candidates.
+pub const MAX_TIMERS: usize = 8;
 
-pub unsafe fn register_timer(t: &'static dyn TimerOps) {
+static mut TIMERS: [Option<&'static dyn TimerOps>; MAX_TIMERS] = [None; MAX_TIMERS];
+
+/// Index in `TIMERS` of the active timer, or `MAX_TIMERS` if none. An
+/// index rather than the reference itself, so a CPU reading it while
+/// another switches sees one timer or the other, never half of each.
+static ACTIVE: AtomicUsize = AtomicUsize::new(MAX_TIMERS);
+
+/// Offer a timer as a candidate. Returns false if the table is full.
+///
+/// # Safety
+///
+/// Call during single-threaded boot, before `timers` is used.
+pub unsafe fn register_timer(t: &'static dyn TimerOps) -> bool {
     unsafe {
-        TIMER = Some(t);
+        let timers = &mut *core::ptr::addr_of_mut!(TIMERS);
+        match timers.iter_mut().find(|slot| slot.is_none()) {
+            Some(slot) => {
+                *slot = Some(t);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// Registered candidates, in registration order.
+pub fn timers() -> impl Iterator<Item = &'static dyn TimerOps> {
+    unsafe { TIMERS }.into_iter().flatten()
+}
+
+/// Make `t` the active timer. Does nothing unless `t` came from
+/// `timers()`.
+///
+/// Other CPUs may keep using the timer meanwhile and see either the old
+/// or the new one; anything that turns its ticks into time must notice
+/// the switch itself.
+///
+/// # Safety
+///
+/// Calls must not race each other.
+pub unsafe fn select_timer(t: &'static dyn TimerOps) {
+    let Some(index) = timers().position(|c| core::ptr::addr_eq(c, t)) else {
+        return;
+    };
+    t.activate();
+    let old = ACTIVE.swap(index, Ordering::AcqRel);
+    if let Some(old) = timer_at(old) {
+        old.deactivate();
     }
 }
 
+fn timer_at(index: usize) -> Option<&'static dyn TimerOps> {
+    if index < MAX_TIMERS {
+        unsafe { TIMERS[index] }
+    } else {
+        None
+    }
+}
+
+/// The active timer, if one has been selected.
+#[inline(always)]
+pub fn current() -> Option<&'static dyn TimerOps> {
+    timer_at(ACTIVE.load(Ordering::Acquire))
+}
+
 #[inline(always)]
 pub fn now_ticks() -> u64 {
-    unsafe { TIMER.map(|t| t.now_ticks()).unwrap_or(0) }
+    current().map(|t| t.now_ticks()).unwrap_or(0)
 }
 
 #[inline(always)]
 pub fn frequency_hz() -> u64 {
-    unsafe { TIMER.map(|t| t.frequency_hz()).unwrap_or(0) }
+    current().map(|t| t.frequency_hz()).unwrap_or(0)
 }
 
 #[inline(always)]
 pub fn arm_one_shot(deadline_ticks: u64) {
-    unsafe {
-        if let Some(t) = TIMER {
-            t.arm_one_shot(deadline_ticks);
-        }
+    if let Some(t) = current() {
+        t.arm_one_shot(deadline_ticks);
+    }
+}
+
+/// Program the active timer on the calling CPU; see
+/// `TimerOps::activate_cpu`.
+pub fn activate_cpu() {
+    if let Some(t) = current() {
+        t.activate_cpu();
     }
 }
@@ -1,4 +1,4 @@
-use bootabi::{BootInfo, ByteSpan, MemMapEntry, MemMapFlags, MemType};
+use bootabi::{BootInfo, ByteSpan, CpuEntry, CpuFlags, MemMapEntry, MemMapFlags, MemType};
 
 pub fn print_boot_info(boot: &BootInfo) {
     crate::klogln!(
@@ -32,6 +32,20 @@ pub fn print_boot_info(boot: &BootInfo) {
     print_bytespan("cmdline", boot.cmdline);
     print_bytespan("bootloader", boot.bootloader);
 
+    for (i, cpu) in cpu_entries(boot).iter().enumerate() {
+        crate::klogln!(
+            "[cpu] {:02} hw_id={:#x} acpi_id={}{}",
+            i,
+            cpu.hw_id,
+            cpu.acpi_id,
+            if cpu.flags & (CpuFlags::BSP as u32) != 0 {
+                " (BSP)"
+            } else {
+                ""
+            }
+        );
+    }
+
     if boot.fb.addr.0 != 0 {
         crate::klogln!(
             "[boot] fb addr={:#x} {}x{} pitch={} bpp={} format={:?}",
@@ -85,6 +99,19 @@ pub fn print_boot_info(boot: &BootInfo) {
     }
 }
 
+/// CPUs the bootloader reported, boot CPU included.
+pub fn cpu_entries(boot: &BootInfo) -> &'static [CpuEntry] {
+    if boot.cpus.entries_ptr == 0 {
+        return &[];
+    }
+    unsafe {
+        core::slice::from_raw_parts(
+            boot.cpus.entries_ptr as *const CpuEntry,
+            boot.cpus.entry_count as usize,
+        )
+    }
+}
+
 fn print_bytespan(label: &str, span: ByteSpan) {
     if span.is_empty() {
         crate::klogln!("[boot] {}=<none>", label);
@@ -0,0 +1,77 @@
+use core::sync::atomic::{AtomicU64, Ordering};
+
+pub use hal::cpu::MAX_CPUS;
+
+use crate::svc::sched;
+
+/// Set of CPU indices, one bit each.
+pub type CpuMask = u64;
+
+pub const ALL_CPUS: CpuMask = CpuMask::MAX;
+
+/// CPUs that are up and accept work.
+static ONLINE: AtomicU64 = AtomicU64::new(0);
+
+/// Logical index of the executing CPU. Indices are dense: the boot CPU
+/// is 0 and APs are numbered in the order they come up.
+#[inline(always)]
+pub fn current() -> usize {
+    crate::arch::cpu_index()
+}
+
+/// Hardware ID (LAPIC ID, MPIDR affinity) of CPU `cpu`, once it is up.
+pub fn hw_id(cpu: usize) -> Option<u64> {
+    hal::cpu::hw_id(cpu)
+}
+
+/// Kernel half of a CPU's per-CPU area. The arch half (GDT, TSS, current
+/// address space) sits behind the per-CPU register; both are indexed by
+/// the same logical CPU number.
+pub struct PerCpu {
+    pub(crate) sched: sched::CpuState,
+}
+
+static AREAS: [PerCpu; MAX_CPUS] = [const {
+    PerCpu {
+        sched: sched::CpuState::new(),
+    }
+}; MAX_CPUS];
+
+/// Per-CPU area of the executing CPU. Only meaningful while the caller
+/// cannot migrate.
+#[inline(always)]
+pub fn this() -> &'static PerCpu {
+    &AREAS[current()]
+}
+
+/// Per-CPU area of CPU `cpu`.
+#[inline(always)]
+pub fn of(cpu: usize) -> &'static PerCpu {
+    &AREAS[cpu]
+}
+
+#[inline(always)]
+pub const fn mask_of(cpu: usize) -> CpuMask {
+    1 << cpu
+}
+
+pub fn set_online(cpu: usize, online: bool) {
+    if online {
+        ONLINE.fetch_or(mask_of(cpu), Ordering::AcqRel);
+    } else {
+        ONLINE.fetch_and(!mask_of(cpu), Ordering::AcqRel);
+    }
+}
+
+pub fn online_mask() -> CpuMask {
+    ONLINE.load(Ordering::Acquire)
+}
+
+pub fn is_online(cpu: usize) -> bool {
+    online_mask() & mask_of(cpu) != 0
+}
+
+/// CPU indices set in `mask`, lowest first.
+pub fn iter(mask: CpuMask) -> impl Iterator<Item = usize> {
+    (0..MAX_CPUS).filter(move |&cpu| mask & mask_of(cpu) != 0)
+}
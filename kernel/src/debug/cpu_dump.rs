@@ -0,0 +1,41 @@
+//! Cross-CPU state dumps delivered over NMI.
+//!
+//! `dump_all_cpus` bumps a request generation and NMIs every other CPU.
+//! Each CPU that sees a generation it has not answered yet logs where it
+//! was interrupted; an NMI with no pending request is reported as
+//! unexpected (watchdog, parity, front-panel button...).
+
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use hal::interrupt::IrqFrame;
+
+use crate::cpu::MAX_CPUS;
+
+static REQUEST_GEN: AtomicU32 = AtomicU32::new(0);
+static ANSWERED_GEN: [AtomicU32; MAX_CPUS] = [const { AtomicU32::new(0) }; MAX_CPUS];
+
+/// Ask every CPU to log its interrupted context.
+pub fn dump_all_cpus() {
+    let generation = REQUEST_GEN.fetch_add(1, Ordering::AcqRel) + 1;
+    let cpu = crate::cpu::current();
+    ANSWERED_GEN[cpu].store(generation, Ordering::Release);
+    crate::klogln!("[nmi] cpu{} requested dump of all cpus", cpu);
+    crate::arch::send_nmi_all_others();
+}
+
+pub fn on_nmi(frame: IrqFrame) {
+    let cpu = crate::cpu::current();
+    let requested = REQUEST_GEN.load(Ordering::Acquire);
+    let answered = ANSWERED_GEN[cpu].swap(requested, Ordering::AcqRel);
+
+    if answered != requested {
+        crate::klogln!("[nmi] cpu{} pc={:#x} sp={:#x}", cpu, frame.pc, frame.sp);
+    } else {
+        crate::klogln!(
+            "[nmi] cpu{} unexpected NMI pc={:#x} sp={:#x}",
+            cpu,
+            frame.pc,
+            frame.sp
+        );
+    }
+}
@@ -1 +1,2 @@
+pub mod cpu_dump;
 pub mod early_serial;
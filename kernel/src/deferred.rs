@@ -0,0 +1,191 @@
+//! Deferred interrupt work (bottom halves).
+//!
+//! Hard-IRQ handlers queue a [`WorkItem`] instead of doing long-running
+//! processing inline. Each CPU owns a pending list that is drained on
+//! interrupt exit with interrupts re-enabled; anything left once the exit
+//! budget is spent is picked up by the idle loop.
+
+use core::ptr;
+use core::sync::atomic::{AtomicBool, AtomicPtr, Ordering};
+
+use crate::cpu::{self, MAX_CPUS};
+
+/// Max items run on a single interrupt exit before punting to idle.
+const IRQ_EXIT_BUDGET: usize = 32;
+
+/// A unit of deferred work.
+///
+/// Items are owned by the caller (usually a `static` in the driver) and can
+/// sit on at most one pending list at a time; queueing an item that is
+/// already pending is a no-op.
+pub struct WorkItem {
+    name: &'static str,
+    func: fn(usize),
+    arg: usize,
+    pending: AtomicBool,
+    next: AtomicPtr<WorkItem>,
+}
+
+impl WorkItem {
+    pub const fn new(name: &'static str, func: fn(usize), arg: usize) -> Self {
+        Self {
+            name,
+            func,
+            arg,
+            pending: AtomicBool::new(false),
+            next: AtomicPtr::new(ptr::null_mut()),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    pub fn is_pending(&self) -> bool {
+        self.pending.load(Ordering::Acquire)
+    }
+}
+
+struct CpuQueue {
+    /// LIFO list of pending items, pushed lock-free from any context.
+    head: AtomicPtr<WorkItem>,
+    /// Set while this CPU is draining, so nested interrupt exits back off.
+    draining: AtomicBool,
+}
+
+impl CpuQueue {
+    const fn new() -> Self {
+        Self {
+            head: AtomicPtr::new(ptr::null_mut()),
+            draining: AtomicBool::new(false),
+        }
+    }
+
+    fn push(&self, item: &'static WorkItem) {
+        let raw = item as *const WorkItem as *mut WorkItem;
+        let mut head = self.head.load(Ordering::Relaxed);
+        loop {
+            item.next.store(head, Ordering::Relaxed);
+            match self
+                .head
+                .compare_exchange_weak(head, raw, Ordering::Release, Ordering::Relaxed)
+            {
+                Ok(_) => return,
+                Err(h) => head = h,
+            }
+        }
+    }
+
+    /// Detach the whole pending list and return it in queueing order.
+    fn take_fifo(&self) -> *mut WorkItem {
+        let mut list = self.head.swap(ptr::null_mut(), Ordering::Acquire);
+        let mut fifo: *mut WorkItem = ptr::null_mut();
+        while !list.is_null() {
+            let item = unsafe { &*list };
+            let next = item.next.load(Ordering::Relaxed);
+            item.next.store(fifo, Ordering::Relaxed);
+            fifo = list;
+            list = next;
+        }
+        fifo
+    }
+}
+
+static QUEUES: [CpuQueue; MAX_CPUS] = [const { CpuQueue::new() }; MAX_CPUS];
+
+/// Queue `work` on the current CPU.
+///
+/// Safe to call from hard-IRQ context. Returns `false` if the item was
+/// already pending.
+pub fn queue(work: &'static WorkItem) -> bool {
+    push(cpu::current(), work)
+}
+
+/// Queue `work` on a specific CPU's pending list. Returns `false` if the
+/// item was already pending, or if `cpu` is out of range or offline and
+/// so might never run it.
+pub fn queue_on(cpu: usize, work: &'static WorkItem) -> bool {
+    if cpu >= MAX_CPUS || !cpu::is_online(cpu) {
+        return false;
+    }
+    push(cpu, work)
+}
+
+fn push(cpu: usize, work: &'static WorkItem) -> bool {
+    if work.pending.swap(true, Ordering::AcqRel) {
+        return false;
+    }
+    QUEUES[cpu].push(work);
+    true
+}
+
+/// Returns true if the current CPU has deferred work waiting.
+pub fn has_pending() -> bool {
+    !QUEUES[cpu::current()]
+        .head
+        .load(Ordering::Acquire)
+        .is_null()
+}
+
+/// Drain everything pending on the current CPU.
+///
+/// Called from the idle loop; interrupts must be enabled.
+pub fn run_pending() {
+    drain(cpu::current(), usize::MAX);
+}
+
+/// Interrupt-exit hook: run a bounded batch of pending work with
+/// interrupts enabled, then return to the interrupted context.
+pub fn irq_exit() {
+    let cpu = cpu::current();
+    if QUEUES[cpu].head.load(Ordering::Acquire).is_null() {
+        return;
+    }
+
+    // No preemption mid-drain: a nested exit would switch threads while
+    // this CPU's queue is marked as draining.
+    crate::svc::sched::preempt_disable();
+    crate::arch::enable_interrupts();
+    drain(cpu, IRQ_EXIT_BUDGET);
+    crate::arch::disable_interrupts();
+    crate::svc::sched::preempt_enable_no_resched();
+}
+
+fn drain(cpu: usize, budget: usize) -> usize {
+    let q = &QUEUES[cpu];
+    if q.draining.swap(true, Ordering::Acquire) {
+        // An outer drain on this CPU will pick up whatever we'd run.
+        return 0;
+    }
+
+    let mut ran = 0;
+    'outer: while ran < budget {
+        let mut list = q.take_fifo();
+        if list.is_null() {
+            break;
+        }
+        while !list.is_null() {
+            let item: &'static WorkItem = unsafe { &*list };
+            list = item.next.load(Ordering::Relaxed);
+
+            if ran >= budget {
+                // Out of budget: put the rest back, still marked pending.
+                q.push(item);
+                while !list.is_null() {
+                    let rest: &'static WorkItem = unsafe { &*list };
+                    list = rest.next.load(Ordering::Relaxed);
+                    q.push(rest);
+                }
+                break 'outer;
+            }
+
+            // Clear before running so the handler may requeue itself.
+            item.pending.store(false, Ordering::Release);
+            (item.func)(item.arg);
+            ran += 1;
+        }
+    }
+
+    q.draining.store(false, Ordering::Release);
+    ran
+}
@@ -1,9 +1,53 @@
-use hal::interrupt::{IrqFrame, IrqKind, InterruptHandler};
+use hal::interrupt::{
+    FaultKind, InterruptHandler, IrqFrame, IrqKind, MachineCheckRecord, McSeverity,
+};
+use hal::irqstats::{self, IrqCounter, IrqStatsSnapshot};
+
+use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+
+use crate::cpu::{CpuMask, MAX_CPUS};
+use crate::sync::SpinLock;
 
 struct KernelInterrupts;
 
 static HANDLER: KernelInterrupts = KernelInterrupts;
 
+/// Interrupt handler nesting depth per CPU.
+static IRQ_DEPTH: [AtomicU32; MAX_CPUS] = [const { AtomicU32::new(0) }; MAX_CPUS];
+
+/// Whether the current CPU is running an interrupt handler (including
+/// the timer callbacks it runs).
+pub fn in_interrupt() -> bool {
+    IRQ_DEPTH[crate::cpu::current()].load(Ordering::Relaxed) != 0
+}
+
+/// Whether each CPU is running its NMI or machine-check handler.
+static IN_NMI: [AtomicBool; MAX_CPUS] = [const { AtomicBool::new(false) }; MAX_CPUS];
+
+/// Whether the current CPU is handling an NMI or a machine check. Such
+/// code may have interrupted any lock holder on this CPU, so it must not
+/// spin on locks.
+pub fn in_nmi() -> bool {
+    IN_NMI[crate::cpu::current()].load(Ordering::Relaxed)
+}
+
+/// Run `f` as NMI context (see `in_nmi`). Nests: a machine check can
+/// land inside an NMI handler.
+fn nmi_context(f: impl FnOnce()) {
+    let cpu = crate::cpu::current();
+    let was = IN_NMI[cpu].swap(true, Ordering::Relaxed);
+    f();
+    IN_NMI[cpu].store(was, Ordering::Relaxed);
+}
+
+pub fn irq_enter() {
+    IRQ_DEPTH[crate::cpu::current()].fetch_add(1, Ordering::Relaxed);
+}
+
+pub fn irq_leave() {
+    IRQ_DEPTH[crate::cpu::current()].fetch_sub(1, Ordering::Relaxed);
+}
+
 pub fn init() {
     unsafe {
         hal::interrupt::register_handler(&HANDLER);
@@ -12,22 +56,150 @@ pub fn init() {
 
 impl InterruptHandler for KernelInterrupts {
     fn on_interrupt(&self, frame: IrqFrame) {
+        irq_enter();
         match frame.kind {
             IrqKind::Timer => crate::time::on_timer_tick(),
             IrqKind::Fault => handle_fault(frame),
-            IrqKind::External => {
-                // TODO: route to device drivers
-                let _ = frame;
-            }
+            IrqKind::External => crate::svc::notification::on_irq(frame.irq),
+            IrqKind::Nmi => nmi_context(|| crate::debug::cpu_dump::on_nmi(frame)),
+            IrqKind::Ipi => crate::smp::on_ipi(frame.irq),
             IrqKind::Spurious | IrqKind::Unknown => {}
         }
+        irq_leave();
+    }
+
+    fn on_interrupt_exit(&self) {
+        // Interrupting a preemptible context means no read section is
+        // open on this CPU.
+        if crate::svc::sched::preemptible() {
+            crate::sync::rcu::quiescent();
+        }
+        crate::deferred::irq_exit();
+        crate::svc::sched::irq_exit();
+    }
+
+    fn on_machine_check(&self, rec: &MachineCheckRecord) {
+        // #MC interrupts anything, like an NMI: log without locks or the
+        // clock.
+        nmi_context(|| handle_machine_check(rec));
     }
 }
 
 fn handle_fault(frame: IrqFrame) {
+    if frame.fault_kind == FaultKind::Debug {
+        // Policy: debug traps are informational until a debugger exists.
+        crate::klogln!("[fault] debug trap pc={:#x}", frame.pc);
+        return;
+    }
+
     // Policy: fatal faults abort the current execution context.
     panic!(
-        "fault {:?} err={:#x} addr={:#x}",
-        frame.fault_kind, frame.error_code, frame.fault_addr
+        "fault {:?} err={:#x} addr={:#x} pc={:#x} sp={:#x}",
+        frame.fault_kind, frame.error_code, frame.fault_addr, frame.pc, frame.sp
+    );
+}
+
+fn handle_machine_check(rec: &MachineCheckRecord) {
+    crate::klogln!(
+        "[mce] cpu{} bank={} severity={:?} syndrome={:#x} addr={:#x}{} misc={:#x} pc={:#x}",
+        rec.cpu,
+        rec.bank,
+        rec.severity,
+        rec.syndrome,
+        rec.addr,
+        if rec.addr_valid { "" } else { " (invalid)" },
+        rec.misc,
+        rec.pc
     );
+
+    // Policy: anything the hardware could not correct is fatal for now.
+    if rec.severity >= McSeverity::Recoverable {
+        panic!("uncorrectable machine check on cpu{}", rec.cpu);
+    }
+}
+
+/// One snapshot per CPU, filled once per report. Too big for a thread
+/// stack, and the lock keeps concurrent reports from mixing.
+static SNAPSHOTS: SpinLock<[IrqStatsSnapshot; MAX_CPUS]> =
+    SpinLock::new([EMPTY_SNAPSHOT; MAX_CPUS]);
+
+const EMPTY_COUNTER: IrqCounter = IrqCounter {
+    count: 0,
+    ticks: 0,
+    max_ticks: 0,
+};
+
+const EMPTY_SNAPSHOT: IrqStatsSnapshot = IrqStatsSnapshot {
+    cpu: 0,
+    _pad: 0,
+    kinds: [EMPTY_COUNTER; irqstats::STAT_KINDS],
+    lines: [EMPTY_COUNTER; irqstats::STAT_LINES],
+    ipis: [EMPTY_COUNTER; irqstats::STAT_IPIS],
+};
+
+/// Log a `/proc/interrupts`-style table for the CPUs in `cpus`.
+///
+/// One row per interrupt source with a count column per CPU, followed by
+/// the average and worst-case handler cost in timer ticks.
+pub fn print_stats(cpus: CpuMask) {
+    let mut snaps = SNAPSHOTS.lock();
+    for cpu in crate::cpu::iter(cpus) {
+        snaps[cpu] = irqstats::snapshot(cpu);
+    }
+
+    crate::klog!("[irq] {:>10}", "");
+    for cpu in crate::cpu::iter(cpus) {
+        crate::klog!("      CPU{:<2}", cpu);
+    }
+    crate::klogln!(" {:>10} {:>10}", "avg_ticks", "max_ticks");
+
+    for (slot, kind) in irqstats::KINDS.iter().enumerate() {
+        print_row(kind_name(*kind), None, &snaps, cpus, |s| s.kinds[slot]);
+    }
+    for line in 0..irqstats::STAT_LINES {
+        print_row("line", Some(line), &snaps, cpus, |s| s.lines[line]);
+    }
+    for ipi in 0..irqstats::STAT_IPIS {
+        print_row("ipi", Some(ipi), &snaps, cpus, |s| s.ipis[ipi]);
+    }
+}
+
+fn print_row(
+    name: &str,
+    index: Option<usize>,
+    snaps: &[IrqStatsSnapshot; MAX_CPUS],
+    cpus: CpuMask,
+    pick: impl Fn(&IrqStatsSnapshot) -> IrqCounter,
+) {
+    let mut total = IrqCounter::default();
+    for cpu in crate::cpu::iter(cpus) {
+        let c = pick(&snaps[cpu]);
+        total.count += c.count;
+        total.ticks += c.ticks;
+        total.max_ticks = total.max_ticks.max(c.max_ticks);
+    }
+    if total.count == 0 {
+        return;
+    }
+
+    match index {
+        Some(i) => crate::klog!("[irq] {:>7}{:<3}", name, i),
+        None => crate::klog!("[irq] {:>10}", name),
+    }
+    for cpu in crate::cpu::iter(cpus) {
+        crate::klog!(" {:>10}", pick(&snaps[cpu]).count);
+    }
+    crate::klogln!(" {:>10} {:>10}", total.ticks / total.count, total.max_ticks);
+}
+
+fn kind_name(kind: IrqKind) -> &'static str {
+    match kind {
+        IrqKind::Fault => "fault",
+        IrqKind::Timer => "timer",
+        IrqKind::External => "external",
+        IrqKind::Spurious => "spurious",
+        IrqKind::Nmi => "nmi",
+        IrqKind::Ipi => "ipi",
+        IrqKind::Unknown => "unknown",
+    }
 }
@@ -21,7 +21,7 @@ pub fn kmain(boot: &BootInfo) -> ! {
     crate::svc::vm::init(boot);
 
     crate::klogln!("[init] arch time");
-    let has_time = crate::arch::init_time_source();
+    let has_time = crate::arch::init_time_source(boot);
 
     crate::klogln!("[init] arch irqs");
     let apic_ok = unsafe { crate::arch::init_irqs(boot, has_time) };
@@ -32,10 +32,22 @@ pub fn kmain(boot: &BootInfo) -> ! {
     crate::klogln!("[init] time");
     crate::time::init();
 
+    crate::klogln!("[init] sched");
+    crate::svc::sched::init();
+
     crate::arch::enable_interrupts();
 
+    crate::klogln!("[init] smp");
+    crate::smpboot::boot_aps(boot);
+
+    #[cfg(feature = "selftest")]
+    crate::selftest::run_all();
+
+    let online = crate::cpu::online_mask();
+    crate::interrupts::print_stats(online);
+    crate::svc::sched::print_stats(online);
+    crate::time::print_stats(online);
+
     crate::klogln!("[ok] idle");
-    loop {
-        crate::arch::cpu_relax();
-    }
+    crate::svc::sched::run_idle()
 }
@@ -1,7 +1,24 @@
 use core::fmt;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 
 static mut LOGGER: Option<&'static dyn LogSink> = None;
 
+/// Whether the next character written starts a new line (and so gets a
+/// timestamp prefix). Only the line owner touches it.
+static AT_LINE_START: AtomicBool = AtomicBool::new(true);
+
+const NO_OWNER: usize = usize::MAX;
+
+/// CPU writing the current line. A line may take several `klog!` calls,
+/// so the owner keeps it, with interrupts disabled, until its newline.
+static LINE_OWNER: AtomicUsize = AtomicUsize::new(NO_OWNER);
+
+/// The owner's interrupt state from before it took the line.
+static LINE_IRQ: AtomicUsize = AtomicUsize::new(0);
+
+/// How long an NMI waits for another CPU's line before writing anyway.
+const NMI_SPINS: usize = 1 << 20;
+
 pub trait LogSink {
     fn write_str(&self, s: &str);
 }
@@ -10,21 +27,111 @@ pub fn set_logger(l: &'static dyn LogSink) {
     unsafe { LOGGER = Some(l) }
 }
 
+fn emit(s: &str) {
+    unsafe {
+        if let Some(l) = LOGGER {
+            l.write_str(s);
+        }
+    }
+}
+
+struct Raw;
+
+impl fmt::Write for Raw {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        emit(s);
+        Ok(())
+    }
+}
+
+/// Writes through to the sink, prefixing each line with uptime if
+/// `stamp` is set.
+struct Stamped {
+    stamp: bool,
+}
+
+impl fmt::Write for Stamped {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let mut rest = s;
+        while !rest.is_empty() {
+            if AT_LINE_START.swap(false, Ordering::Relaxed) && self.stamp {
+                let (secs, nanos) = crate::time::uptime();
+                let _ = fmt::Write::write_fmt(
+                    &mut Raw,
+                    format_args!("[{:5}.{:06}] ", secs, nanos / 1000),
+                );
+            }
+            match rest.find('\n') {
+                Some(i) => {
+                    emit(&rest[..=i]);
+                    AT_LINE_START.store(true, Ordering::Relaxed);
+                    rest = &rest[i + 1..];
+                }
+                None => {
+                    emit(rest);
+                    break;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
 pub fn _print(args: fmt::Arguments) {
     use core::fmt::Write;
 
-    struct Adapter;
-    impl Write for Adapter {
-        fn write_str(&mut self, s: &str) -> fmt::Result {
-            unsafe {
-                if let Some(l) = LOGGER {
-                    l.write_str(s);
-                }
+    let cpu = crate::cpu::current();
+    if crate::interrupts::in_nmi() {
+        print_nmi(cpu, args);
+        return;
+    }
+
+    if LINE_OWNER.load(Ordering::Relaxed) != cpu {
+        let irq = crate::arch::irq_save();
+        while LINE_OWNER
+            .compare_exchange_weak(NO_OWNER, cpu, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            crate::arch::cpu_relax();
+        }
+        LINE_IRQ.store(irq, Ordering::Relaxed);
+    }
+
+    let _ = Stamped { stamp: true }.write_fmt(args);
+
+    if AT_LINE_START.load(Ordering::Relaxed) {
+        let irq = LINE_IRQ.load(Ordering::Relaxed);
+        LINE_OWNER.store(NO_OWNER, Ordering::Release);
+        crate::arch::irq_restore(irq);
+    }
+}
+
+/// `_print` from an NMI or machine-check handler. It may have interrupted
+/// anything, including this CPU's own line or the clock's seqlock writer,
+/// so it only tries for the line, never releases one it did not take, and
+/// leaves out the timestamp.
+fn print_nmi(cpu: usize, args: fmt::Arguments) {
+    use core::fmt::Write;
+
+    let mut took = false;
+    if LINE_OWNER.load(Ordering::Relaxed) != cpu {
+        for _ in 0..NMI_SPINS {
+            if LINE_OWNER
+                .compare_exchange_weak(NO_OWNER, cpu, Ordering::Acquire, Ordering::Relaxed)
+                .is_ok()
+            {
+                took = true;
+                break;
             }
-            Ok(())
+            crate::arch::cpu_relax();
         }
     }
-    let _ = Adapter.write_fmt(args);
+
+    let _ = Stamped { stamp: false }.write_fmt(args);
+
+    if took {
+        LINE_OWNER.store(NO_OWNER, Ordering::Release);
+    }
 }
 
 #[macro_export]
@@ -3,15 +3,23 @@
 
 mod arch;
 mod bootinfo;
+mod cpu;
 mod debug;
+mod deferred;
 mod drivers;
 mod hal;
 mod interrupts;
 mod kmain;
 mod log;
+mod obj;
 mod panic;
-mod time;
+#[cfg(feature = "selftest")]
+mod selftest;
+mod smp;
+mod smpboot;
 mod svc;
+mod sync;
+mod time;
 
 use bootabi::BootInfo;
 
@@ -19,7 +27,8 @@ use bootabi::BootInfo;
 pub extern "C" fn kentry() -> ! {
     crate::arch::early_init();
     debug::early_serial::write_str("ENTER kentry\n");
-    let boot: BootInfo = bootloader_limine::gather_bootinfo(1);
+    let boot: BootInfo = bootloader_limine::gather_bootinfo();
+    smpboot::start_aps();
     crate::debug::early_serial::write_str("AFTER bootinfo\n");
 
     drivers::console::init(&boot);
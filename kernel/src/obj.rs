@@ -0,0 +1,680 @@
+//! Kernel objects and handle tables.
+//!
+//! Every kernel object (IPC endpoint, channel, notification, VM region,
+//! thread, interrupt) starts with an `ObjHeader` carrying its type,
+//! reference count and a debug name. There is no heap: each object type
+//! keeps its objects in a fixed `ObjPool`, and a slot goes back to the
+//! pool when its last reference is dropped.
+//!
+//! A handle table maps small integers to an object plus the rights the
+//! holder has over it. Each handle owns one reference. A handle is the
+//! table index plus a generation, so a closed handle never aliases the
+//! next occupant of its slot. Rights only ever shrink: duplicating or
+//! transferring a handle can drop rights but never add them.
+
+use core::cell::UnsafeCell;
+use core::marker::PhantomData;
+use core::ops::Deref;
+use core::sync::atomic::{AtomicBool, AtomicU8, AtomicU32, Ordering};
+
+use bitflags::bitflags;
+
+use crate::sync::SpinLock;
+
+#[repr(u8)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ObjType {
+    Endpoint = 1,
+    Channel = 2,
+    Notification = 3,
+    VmRegion = 4,
+    Thread = 5,
+    Interrupt = 6,
+}
+
+/// `ObjHeader::ty` of a free pool slot.
+const FREE: u8 = 0;
+
+bitflags! {
+    /// What a handle lets its holder do with the object.
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub struct Rights: u32 {
+        /// Receive, or read the object's state.
+        const READ = 1 << 0;
+        /// Send, or modify the object's state.
+        const WRITE = 1 << 1;
+        /// Make another handle to the object.
+        const DUPLICATE = 1 << 2;
+        /// Move the handle to another table or through IPC.
+        const TRANSFER = 1 << 3;
+        /// Raise signals on the object.
+        const SIGNAL = 1 << 4;
+        /// Block on the object.
+        const WAIT = 1 << 5;
+        /// Map the object into an address space.
+        const MAP = 1 << 6;
+        /// Bind, reconfigure or tear down the object.
+        const MANAGE = 1 << 7;
+    }
+}
+
+/// Common header; must be the first field of every kernel object.
+#[repr(C)]
+pub struct ObjHeader {
+    ty: AtomicU8,
+    refs: AtomicU32,
+    name: UnsafeCell<&'static str>,
+    /// Hands the object back to its pool; set by `ObjPool::alloc`.
+    release: UnsafeCell<fn(&'static ObjHeader)>,
+}
+
+// `name` and `release` are written only while the slot is being claimed,
+// before any reference to it exists.
+unsafe impl Sync for ObjHeader {}
+
+fn no_release(_: &'static ObjHeader) {}
+
+impl ObjHeader {
+    pub const fn new() -> Self {
+        Self {
+            ty: AtomicU8::new(FREE),
+            refs: AtomicU32::new(0),
+            name: UnsafeCell::new(""),
+            release: UnsafeCell::new(no_release),
+        }
+    }
+
+    pub fn ty(&self) -> Option<ObjType> {
+        match self.ty.load(Ordering::Acquire) {
+            1 => Some(ObjType::Endpoint),
+            2 => Some(ObjType::Channel),
+            3 => Some(ObjType::Notification),
+            4 => Some(ObjType::VmRegion),
+            5 => Some(ObjType::Thread),
+            6 => Some(ObjType::Interrupt),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        unsafe { *self.name.get() }
+    }
+
+    /// Live references, handles included.
+    pub fn ref_count(&self) -> u32 {
+        self.refs.load(Ordering::Acquire)
+    }
+
+    /// A new reference to the object, unless its last one is already
+    /// gone. For pointers that hold no reference of their own: the slot
+    /// may have been reused since, so the caller checks that it still
+    /// holds the object it meant.
+    pub fn try_ref(&'static self) -> Option<ObjRef> {
+        let mut refs = self.refs.load(Ordering::Relaxed);
+        loop {
+            if refs == 0 {
+                return None;
+            }
+            match self.refs.compare_exchange_weak(
+                refs,
+                refs + 1,
+                Ordering::Acquire,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => return Some(ObjRef(self)),
+                Err(now) => refs = now,
+            }
+        }
+    }
+}
+
+impl Default for ObjHeader {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A type of kernel object.
+///
+/// # Safety
+///
+/// Implementors are `#[repr(C)]` with the `ObjHeader` returned by
+/// `header` as their first field, so a header of type `TYPE` can be cast
+/// back to the object.
+pub unsafe trait KernelObject: Sync + 'static {
+    const TYPE: ObjType;
+
+    fn header(&self) -> &ObjHeader;
+
+    /// Runs when the last reference is dropped, before the slot is
+    /// reused. Drop whatever the object holds here.
+    fn on_release(&'static self) {}
+}
+
+/// A counted reference to a kernel object of any type.
+pub struct ObjRef(&'static ObjHeader);
+
+impl ObjRef {
+    pub fn header(&self) -> &'static ObjHeader {
+        self.0
+    }
+
+    pub fn ty(&self) -> Option<ObjType> {
+        self.0.ty()
+    }
+
+    /// The object as a `T`, if it is one.
+    pub fn get<T: KernelObject>(&self) -> Option<&'static T> {
+        if self.0.ty() != Some(T::TYPE) {
+            return None;
+        }
+        Some(unsafe { &*(self.0 as *const ObjHeader as *const T) })
+    }
+
+    /// Turn this into a typed reference, or give it back if the type is
+    /// wrong.
+    pub fn downcast<T: KernelObject>(self) -> Result<Obj<T>, ObjRef> {
+        if self.0.ty() != Some(T::TYPE) {
+            return Err(self);
+        }
+        Ok(Obj {
+            obj: self,
+            _ty: PhantomData,
+        })
+    }
+
+    pub fn ptr_eq(&self, other: &ObjRef) -> bool {
+        core::ptr::eq(self.0, other.0)
+    }
+}
+
+impl Clone for ObjRef {
+    fn clone(&self) -> Self {
+        self.0.refs.fetch_add(1, Ordering::Relaxed);
+        ObjRef(self.0)
+    }
+}
+
+impl Drop for ObjRef {
+    fn drop(&mut self) {
+        if self.0.refs.fetch_sub(1, Ordering::AcqRel) == 1 {
+            let release = unsafe { *self.0.release.get() };
+            release(self.0);
+        }
+    }
+}
+
+/// A counted reference to a kernel object known to be a `T`.
+pub struct Obj<T: KernelObject> {
+    obj: ObjRef,
+    _ty: PhantomData<&'static T>,
+}
+
+impl<T: KernelObject> Obj<T> {
+    pub fn as_ref(&self) -> &ObjRef {
+        &self.obj
+    }
+
+    pub fn into_ref(self) -> ObjRef {
+        self.obj
+    }
+}
+
+impl<T: KernelObject> Clone for Obj<T> {
+    fn clone(&self) -> Self {
+        Self {
+            obj: self.obj.clone(),
+            _ty: PhantomData,
+        }
+    }
+}
+
+impl<T: KernelObject> Deref for Obj<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*(self.obj.0 as *const ObjHeader as *const T) }
+    }
+}
+
+/// Fixed storage for the objects of one type.
+pub struct ObjPool<T: KernelObject, const N: usize> {
+    objs: [T; N],
+}
+
+impl<T: KernelObject, const N: usize> ObjPool<T, N> {
+    pub const fn new(objs: [T; N]) -> Self {
+        Self { objs }
+    }
+
+    /// Claim a free object, let `init` set up its type-specific state and
+    /// return the first reference to it. `None` if the pool is exhausted.
+    pub fn alloc(
+        &'static self,
+        name: &'static str,
+        init: impl FnOnce(&'static T),
+    ) -> Option<Obj<T>> {
+        for obj in &self.objs {
+            let hdr = obj.header();
+            if hdr
+                .ty
+                .compare_exchange(FREE, T::TYPE as u8, Ordering::AcqRel, Ordering::Relaxed)
+                .is_err()
+            {
+                continue;
+            }
+            unsafe {
+                *hdr.name.get() = name;
+                *hdr.release.get() = release::<T>;
+            }
+            init(obj);
+            hdr.refs.store(1, Ordering::Release);
+            return Some(Obj {
+                obj: ObjRef(hdr),
+                _ty: PhantomData,
+            });
+        }
+        None
+    }
+
+    /// Objects currently allocated.
+    pub fn live(&self) -> usize {
+        self.objs
+            .iter()
+            .filter(|o| o.header().ty.load(Ordering::Relaxed) != FREE)
+            .count()
+    }
+}
+
+fn release<T: KernelObject>(hdr: &'static ObjHeader) {
+    let obj = unsafe { &*(hdr as *const ObjHeader as *const T) };
+    obj.on_release();
+    hdr.ty.store(FREE, Ordering::Release);
+}
+
+/// An object and rights outside any table: a handle taken out for
+/// transfer, e.g. in an IPC message.
+pub struct Capability {
+    pub obj: ObjRef,
+    pub rights: Rights,
+}
+
+pub const HANDLES_PER_TABLE: usize = 64;
+pub const MAX_HANDLE_TABLES: usize = 16;
+
+const INDEX_BITS: u32 = 8;
+const INDEX_MASK: u32 = (1 << INDEX_BITS) - 1;
+
+/// Index of an object in a handle table, with its slot's generation.
+/// Zero is never a valid handle.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Handle(u32);
+
+impl Handle {
+    pub const INVALID: Handle = Handle(0);
+
+    pub const fn from_raw(raw: u32) -> Self {
+        Handle(raw)
+    }
+
+    pub const fn raw(self) -> u32 {
+        self.0
+    }
+
+    fn index(self) -> usize {
+        (self.0 & INDEX_MASK) as usize
+    }
+
+    fn generation(self) -> u32 {
+        self.0 >> INDEX_BITS
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HandleError {
+    /// Not a live handle in this table.
+    BadHandle,
+    /// The object is not of the type the operation needs.
+    WrongType,
+    /// The handle lacks a right the operation needs.
+    AccessDenied,
+    /// Every slot in the table is in use.
+    TableFull,
+}
+
+struct Entry {
+    obj: Option<ObjRef>,
+    rights: Rights,
+    /// Bumped on every close; starts at 1 so no handle is zero.
+    generation: u32,
+}
+
+impl Entry {
+    const fn new() -> Self {
+        Self {
+            obj: None,
+            rights: Rights::empty(),
+            generation: 1,
+        }
+    }
+
+    fn handle(&self, index: usize) -> Handle {
+        Handle((self.generation << INDEX_BITS) | index as u32)
+    }
+}
+
+struct Entries {
+    slots: [Entry; HANDLES_PER_TABLE],
+}
+
+impl Entries {
+    fn lookup(&mut self, h: Handle) -> Result<&mut Entry, HandleError> {
+        let e = self
+            .slots
+            .get_mut(h.index())
+            .ok_or(HandleError::BadHandle)?;
+        if e.obj.is_none() || e.generation != h.generation() {
+            return Err(HandleError::BadHandle);
+        }
+        Ok(e)
+    }
+
+    fn insert(&mut self, cap: Capability) -> Result<Handle, Capability> {
+        let Some(index) = self.slots.iter().position(|e| e.obj.is_none()) else {
+            return Err(cap);
+        };
+        let e = &mut self.slots[index];
+        e.obj = Some(cap.obj);
+        e.rights = cap.rights;
+        Ok(e.handle(index))
+    }
+
+    /// `remove` for a handle with `TRANSFER`.
+    fn take(&mut self, h: Handle) -> Result<Capability, HandleError> {
+        if !self.lookup(h)?.rights.contains(Rights::TRANSFER) {
+            return Err(HandleError::AccessDenied);
+        }
+        self.remove(h)
+    }
+
+    fn remove(&mut self, h: Handle) -> Result<Capability, HandleError> {
+        let e = self.lookup(h)?;
+        let obj = e.obj.take().ok_or(HandleError::BadHandle)?;
+        e.generation = (e.generation + 1) & (u32::MAX >> INDEX_BITS);
+        if e.generation == 0 {
+            e.generation = 1;
+        }
+        Ok(Capability {
+            obj,
+            rights: e.rights,
+        })
+    }
+}
+
+/// Handles of one process.
+///
+/// References are dropped only after the table lock is released, so an
+/// object's `on_release` may take other locks.
+pub struct HandleTable {
+    entries: SpinLock<Entries>,
+    in_use: AtomicBool,
+}
+
+impl HandleTable {
+    const fn new() -> Self {
+        Self {
+            entries: SpinLock::new(Entries {
+                slots: [const { Entry::new() }; HANDLES_PER_TABLE],
+            }),
+            in_use: AtomicBool::new(false),
+        }
+    }
+
+    /// Add a handle to `obj`, consuming the reference.
+    pub fn insert(&self, obj: ObjRef, rights: Rights) -> Result<Handle, HandleError> {
+        self.insert_cap(Capability { obj, rights })
+    }
+
+    /// Add a handle for a capability taken from elsewhere.
+    pub fn insert_cap(&self, cap: Capability) -> Result<Handle, HandleError> {
+        let r = self.entries.lock().insert(cap);
+        // A refused capability is dropped here, outside the lock.
+        r.map_err(|_| HandleError::TableFull)
+    }
+
+    /// A new reference to `h`'s object, if the handle has every right in
+    /// `required`.
+    pub fn get(&self, h: Handle, required: Rights) -> Result<ObjRef, HandleError> {
+        let mut entries = self.entries.lock();
+        let e = entries.lookup(h)?;
+        if !e.rights.contains(required) {
+            return Err(HandleError::AccessDenied);
+        }
+        e.obj.clone().ok_or(HandleError::BadHandle)
+    }
+
+    /// Like `get`, for an object that must be a `T`.
+    pub fn get_as<T: KernelObject>(
+        &self,
+        h: Handle,
+        required: Rights,
+    ) -> Result<Obj<T>, HandleError> {
+        self.get(h, required)?
+            .downcast::<T>()
+            .map_err(|_| HandleError::WrongType)
+    }
+
+    pub fn rights(&self, h: Handle) -> Result<Rights, HandleError> {
+        Ok(self.entries.lock().lookup(h)?.rights)
+    }
+
+    /// A second handle to `h`'s object with `h`'s rights masked by
+    /// `mask`. Needs `DUPLICATE`.
+    pub fn duplicate(&self, h: Handle, mask: Rights) -> Result<Handle, HandleError> {
+        let mut entries = self.entries.lock();
+        let e = entries.lookup(h)?;
+        if !e.rights.contains(Rights::DUPLICATE) {
+            return Err(HandleError::AccessDenied);
+        }
+        let cap = Capability {
+            obj: e.obj.clone().ok_or(HandleError::BadHandle)?,
+            rights: e.rights & mask,
+        };
+        let r = entries.insert(cap);
+        drop(entries);
+        r.map_err(|_| HandleError::TableFull)
+    }
+
+    /// Drop rights from `h` in place: it is closed and a handle with the
+    /// remaining rights is returned in its stead.
+    pub fn restrict(&self, h: Handle, mask: Rights) -> Result<Handle, HandleError> {
+        let mut entries = self.entries.lock();
+        let mut cap = entries.remove(h)?;
+        cap.rights &= mask;
+        // Cannot fail: a slot was just freed.
+        let r = entries.insert(cap);
+        drop(entries);
+        r.map_err(|_| HandleError::TableFull)
+    }
+
+    /// Remove `h` for sending elsewhere, keeping its rights masked by
+    /// `mask`. Needs `TRANSFER`; the handle is closed on success.
+    pub fn take(&self, h: Handle, mask: Rights) -> Result<Capability, HandleError> {
+        let mut cap = self.entries.lock().take(h)?;
+        cap.rights &= mask;
+        Ok(cap)
+    }
+
+    /// `take` for several handles at once: either all of `hs` are
+    /// removed, their capabilities written to the front of `out`, or
+    /// none are.
+    pub fn take_all(
+        &self,
+        hs: &[Handle],
+        mask: Rights,
+        out: &mut [Option<Capability>],
+    ) -> Result<(), HandleError> {
+        if hs.len() > out.len() {
+            return Err(HandleError::TableFull);
+        }
+        let mut entries = self.entries.lock();
+        for (i, &h) in hs.iter().enumerate() {
+            if hs[..i].contains(&h) {
+                return Err(HandleError::BadHandle);
+            }
+            if !entries.lookup(h)?.rights.contains(Rights::TRANSFER) {
+                return Err(HandleError::AccessDenied);
+            }
+        }
+        for (slot, &h) in out.iter_mut().zip(hs) {
+            let mut cap = entries.remove(h)?;
+            cap.rights &= mask;
+            *slot = Some(cap);
+        }
+        Ok(())
+    }
+
+    /// Move `h` into `to` with its rights masked by `mask`. Needs
+    /// `TRANSFER`. On failure nothing moves and `h` stays valid.
+    pub fn transfer(
+        &self,
+        h: Handle,
+        to: &HandleTable,
+        mask: Rights,
+    ) -> Result<Handle, HandleError> {
+        if core::ptr::eq(self, to) {
+            let mut entries = self.entries.lock();
+            let mut cap = entries.take(h)?;
+            cap.rights &= mask;
+            // Cannot fail: a slot was just freed.
+            let r = entries.insert(cap);
+            drop(entries);
+            return r.map_err(|_| HandleError::TableFull);
+        }
+        // Lock in address order so opposite transfers cannot deadlock.
+        let (mut from, mut dest) = if (self as *const Self) < (to as *const Self) {
+            let from = self.entries.lock();
+            (from, to.entries.lock())
+        } else {
+            let dest = to.entries.lock();
+            (self.entries.lock(), dest)
+        };
+        if !from.lookup(h)?.rights.contains(Rights::TRANSFER) {
+            return Err(HandleError::AccessDenied);
+        }
+        if dest.slots.iter().all(|e| e.obj.is_some()) {
+            return Err(HandleError::TableFull);
+        }
+        let mut cap = from.take(h)?;
+        cap.rights &= mask;
+        // Cannot fail: `dest` has a free slot and stays locked.
+        let r = dest.insert(cap);
+        drop(dest);
+        drop(from);
+        r.map_err(|_| HandleError::TableFull)
+    }
+
+    /// Close `h`, dropping its reference.
+    pub fn close(&self, h: Handle) -> Result<(), HandleError> {
+        let cap = self.entries.lock().remove(h)?;
+        drop(cap);
+        Ok(())
+    }
+
+    /// Close every handle.
+    pub fn close_all(&self) {
+        for index in 0..HANDLES_PER_TABLE {
+            let cap = {
+                let mut entries = self.entries.lock();
+                let h = entries.slots[index].handle(index);
+                entries.remove(h).ok()
+            };
+            drop(cap);
+        }
+    }
+
+    /// Handles in use.
+    pub fn count(&self) -> usize {
+        self.entries
+            .lock()
+            .slots
+            .iter()
+            .filter(|e| e.obj.is_some())
+            .count()
+    }
+}
+
+static TABLES: [HandleTable; MAX_HANDLE_TABLES] = [const { HandleTable::new() }; MAX_HANDLE_TABLES];
+
+/// An empty handle table, e.g. for a new process.
+pub fn alloc_table() -> Option<&'static HandleTable> {
+    TABLES.iter().find(|t| {
+        t.in_use
+            .compare_exchange(false, true, Ordering::AcqRel, Ordering::Relaxed)
+            .is_ok()
+    })
+}
+
+/// Close every handle in `table` and return it to the pool.
+pub fn free_table(table: &'static HandleTable) {
+    table.close_all();
+    table.in_use.store(false, Ordering::Release);
+}
+
+/// Exercise handles on thread objects: rights checks, duplicate with
+/// reduced rights, transfer between tables, stale handles, and release
+/// of the object once the last handle closes.
+#[cfg(feature = "selftest")]
+pub fn self_test() -> bool {
+    use crate::svc::sched::{self, ThreadObject};
+
+    let (Some(a), Some(b)) = (alloc_table(), alloc_table()) else {
+        return false;
+    };
+    let Some(obj) = ThreadObject::create(sched::current()) else {
+        return false;
+    };
+    let hdr = obj.as_ref().header();
+    let all = Rights::READ | Rights::DUPLICATE | Rights::TRANSFER;
+
+    let mut ok = true;
+    let h = a.insert(obj.into_ref(), all).unwrap_or(Handle::INVALID);
+    ok &= a
+        .get_as::<ThreadObject>(h, Rights::READ)
+        .is_ok_and(|t| t.id() == sched::current());
+    ok &= a.get(h, Rights::WRITE).err() == Some(HandleError::AccessDenied);
+
+    // Duplicate without TRANSFER, then try to move the copy.
+    let dup = a.duplicate(h, !Rights::TRANSFER);
+    ok &= dup.is_ok_and(|d| a.rights(d) == Ok(Rights::READ | Rights::DUPLICATE));
+    if let Ok(d) = dup {
+        ok &= a.transfer(d, b, Rights::all()).err() == Some(HandleError::AccessDenied);
+        ok &= a.close(d).is_ok();
+        ok &= a.close(d).err() == Some(HandleError::BadHandle);
+    }
+
+    // A full destination refuses the move and `h` stays as it was.
+    while let Ok(r) = a.get(h, Rights::empty()) {
+        if b.insert(r, Rights::READ).is_err() {
+            break;
+        }
+    }
+    ok &= a.transfer(h, b, Rights::all()).err() == Some(HandleError::TableFull);
+    ok &= a.rights(h) == Ok(all);
+    b.close_all();
+
+    // Move the original, dropping DUPLICATE on the way.
+    let moved = a.transfer(h, b, !Rights::DUPLICATE);
+    ok &= a.get(h, Rights::empty()).err() == Some(HandleError::BadHandle);
+    ok &= moved.is_ok_and(|m| b.rights(m) == Ok(Rights::READ | Rights::TRANSFER));
+    ok &=
+        moved.is_ok_and(|m| b.duplicate(m, Rights::all()).err() == Some(HandleError::AccessDenied));
+    ok &= hdr.ref_count() == 1;
+
+    free_table(b);
+    ok &= hdr.ty().is_none() && hdr.ref_count() == 0;
+    ok &= a.count() == 0;
+    free_table(a);
+
+    crate::selftest::report(format_args!("[obj] handles"), ok)
+}
@@ -14,5 +14,8 @@ fn panic(info: &PanicInfo) -> ! {
     }
 
     let _ = write!(&mut W, "{}\n", info);
+
+    crate::debug::cpu_dump::dump_all_cpus();
+    crate::smp::stop_others();
     loop {}
 }
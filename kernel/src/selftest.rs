@@ -0,0 +1,93 @@
+//! Boot-time self-tests, built with the `selftest` feature.
+//!
+//! Each subsystem has a `self_test` that returns whether it passed.
+//! `run_all` runs them in order once every CPU is up and panics on the
+//! first failure. The helpers below are what the tests share: handing an
+//! object to a test thread, running threads to completion and logging
+//! the outcome.
+
+use crate::cpu::CpuMask;
+use crate::svc::sched::{self, SchedAttr};
+
+/// A subsystem's test; true if it passed.
+type Test = fn() -> bool;
+
+/// Subsystem tests in the order they run; each may rely on the ones
+/// before it.
+const TESTS: &[(&str, Test)] = &[
+    ("threads", crate::svc::sched::self_test),
+    ("sync", crate::sync::self_test),
+    ("smp", crate::smp::self_test),
+    ("objects", crate::obj::self_test),
+    ("ipc", crate::svc::ipc::self_test),
+    ("channels", crate::svc::channel::self_test),
+    ("notifications", crate::svc::notification::self_test),
+];
+
+pub fn run_all() {
+    for &(name, test) in TESTS {
+        if !test() {
+            panic!("{} self-test failed", name);
+        }
+        crate::klogln!("[ok] {}", name);
+    }
+}
+
+/// `obj` as a test thread's argument; the thread gets it back with
+/// `obj_arg`.
+pub fn arg<T>(obj: &T) -> usize {
+    obj as *const T as usize
+}
+
+/// The object behind a thread argument made by `arg`.
+///
+/// # Safety
+///
+/// `arg` came from `arg::<T>`, and the object outlives the thread: the
+/// test joins the thread before dropping it.
+pub unsafe fn obj_arg<T>(arg: usize) -> &'static T {
+    unsafe { &*(arg as *const T) }
+}
+
+/// A test thread and the exit code it must return.
+pub struct TestThread {
+    pub name: &'static str,
+    pub entry: fn(usize) -> usize,
+    pub arg: usize,
+    pub expect: usize,
+}
+
+impl TestThread {
+    pub const fn new(
+        name: &'static str,
+        entry: fn(usize) -> usize,
+        arg: usize,
+        expect: usize,
+    ) -> Self {
+        Self {
+            name,
+            entry,
+            arg,
+            expect,
+        }
+    }
+}
+
+/// Start all of `threads` on the CPUs in `cpus`, then join them all.
+/// True if every one started and exited with its `expect`.
+pub fn run_threads<const N: usize>(cpus: CpuMask, threads: [TestThread; N]) -> bool {
+    let ids = threads
+        .each_ref()
+        .map(|t| sched::spawn_on_cpus(t.name, t.entry, t.arg, SchedAttr::default(), cpus).ok());
+    let mut ok = true;
+    for (t, id) in threads.iter().zip(ids) {
+        ok &= id.is_some_and(|id| sched::join(id) == Some(t.expect));
+    }
+    ok
+}
+
+/// Log `what` with the outcome and pass `ok` through, to end a test.
+pub fn report(what: core::fmt::Arguments<'_>, ok: bool) -> bool {
+    crate::klogln!("{}: {}", what, if ok { "ok" } else { "FAILED" });
+    ok
+}
@@ -0,0 +1,198 @@
+//! Cross-CPU calls, reschedule kicks and stopping CPUs, over `hal::ipi`.
+//!
+//! `smp_call_function` runs a function on other CPUs from their
+//! function-call IPI. Each CPU owns one call slot it fills as a caller;
+//! a target finds the slots aimed at it through a per-CPU mask of
+//! callers, so callers on different CPUs never wait on each other. A
+//! caller spinning for its targets keeps running calls aimed at itself,
+//! so two CPUs calling each other cannot deadlock, even with interrupts
+//! disabled.
+
+use core::cell::UnsafeCell;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use hal::ipi::{IpiKind, IpiTarget};
+
+use crate::cpu::{self, ALL_CPUS, CpuMask, MAX_CPUS};
+use crate::svc::sched;
+
+/// A caller's outstanding call.
+struct CallSlot {
+    /// Written by the owning CPU only while `pending` is empty.
+    call: UnsafeCell<(fn(usize), usize)>,
+    /// Targets that have not finished the call.
+    pending: AtomicU64,
+}
+
+// `call` is only written while no target may read it.
+unsafe impl Sync for CallSlot {}
+
+fn no_call(_: usize) {}
+
+static SLOTS: [CallSlot; MAX_CPUS] = [const {
+    CallSlot {
+        call: UnsafeCell::new((no_call, 0)),
+        pending: AtomicU64::new(0),
+    }
+}; MAX_CPUS];
+
+/// Per target CPU: callers whose slot holds a call for it.
+static QUEUED: [AtomicU64; MAX_CPUS] = [const { AtomicU64::new(0) }; MAX_CPUS];
+
+/// CPUs that took a stop IPI.
+static STOPPED: AtomicU64 = AtomicU64::new(0);
+
+/// Bound on the wait for other CPUs to stop; time may not be usable.
+const STOP_SPINS: u64 = 10_000_000;
+
+/// Interrupt `cpu` so it runs the interrupt-exit path and notices a
+/// pending reschedule.
+pub fn send_reschedule(cpu: usize) {
+    hal::ipi::send(IpiTarget::Cpu(cpu), IpiKind::Reschedule);
+}
+
+/// Spin until every target of `slot` has finished, serving calls aimed
+/// at this CPU meanwhile.
+fn wait_done(slot: &CallSlot) {
+    while slot.pending.load(Ordering::Acquire) != 0 {
+        run_queued_calls();
+        crate::arch::cpu_relax();
+    }
+}
+
+/// Run `func(arg)` on every online CPU in `mask` except the caller, from
+/// their function-call IPI (interrupt context, interrupts disabled).
+///
+/// With `wait`, returns once every target has finished. Without, returns
+/// once the call is sent; the caller's next call waits for it, so `arg`
+/// must stay valid until then. Returns the CPUs called.
+pub fn smp_call_function(mask: CpuMask, func: fn(usize), arg: usize, wait: bool) -> CpuMask {
+    sched::preempt_disable();
+    let me = cpu::current();
+    let targets = mask & cpu::online_mask() & !cpu::mask_of(me);
+    if targets != 0 {
+        let slot = &SLOTS[me];
+        wait_done(slot);
+        unsafe {
+            *slot.call.get() = (func, arg);
+        }
+        slot.pending.store(targets, Ordering::Release);
+        for target in cpu::iter(targets) {
+            QUEUED[target].fetch_or(cpu::mask_of(me), Ordering::AcqRel);
+        }
+        hal::ipi::send(IpiTarget::Mask(targets), IpiKind::CallFunction);
+        if wait {
+            wait_done(slot);
+        }
+    }
+    sched::preempt_enable();
+    targets
+}
+
+/// Run `func(arg)` on every online CPU, the caller included, and wait
+/// for all of them. Locally it runs with interrupts disabled, as it
+/// does remotely.
+pub fn on_each_cpu(func: fn(usize), arg: usize) {
+    sched::preempt_disable();
+    smp_call_function(ALL_CPUS, func, arg, true);
+    let irq = crate::arch::irq_save();
+    func(arg);
+    crate::arch::irq_restore(irq);
+    sched::preempt_enable();
+}
+
+/// Run every call queued for this CPU.
+fn run_queued_calls() {
+    let me = cpu::current();
+    let callers = QUEUED[me].swap(0, Ordering::AcqRel);
+    for caller in cpu::iter(callers) {
+        let slot = &SLOTS[caller];
+        let (func, arg) = unsafe { *slot.call.get() };
+        func(arg);
+        slot.pending.fetch_and(!cpu::mask_of(me), Ordering::Release);
+    }
+}
+
+/// Stop every other CPU: each disables interrupts and spins for good
+/// (NMIs still get through). Returns the CPUs that stopped in time.
+pub fn stop_others() -> CpuMask {
+    let others = cpu::online_mask() & !cpu::mask_of(cpu::current());
+    hal::ipi::send(IpiTarget::AllButSelf, IpiKind::Stop);
+    for _ in 0..STOP_SPINS {
+        if STOPPED.load(Ordering::Acquire) & others == others {
+            break;
+        }
+        crate::arch::cpu_relax();
+    }
+    STOPPED.load(Ordering::Acquire) & others
+}
+
+fn stop_this_cpu() -> ! {
+    crate::arch::disable_interrupts();
+    STOPPED.fetch_or(cpu::mask_of(cpu::current()), Ordering::AcqRel);
+    loop {
+        crate::arch::cpu_relax();
+    }
+}
+
+/// IPI dispatch from `interrupts`. Reschedule needs nothing here: the
+/// interrupt-exit hook does the work.
+pub fn on_ipi(irq: u16) {
+    match IpiKind::from_irq(irq) {
+        Some(IpiKind::CallFunction) => run_queued_calls(),
+        Some(IpiKind::Stop) => stop_this_cpu(),
+        Some(IpiKind::Reschedule) | None => {}
+    }
+}
+
+/// Call every other CPU synchronously, then a run of asynchronous calls,
+/// then every CPU including this one; check each call ran exactly once
+/// per target, with interrupts disabled.
+#[cfg(feature = "selftest")]
+pub fn self_test() -> bool {
+    use core::sync::atomic::AtomicUsize;
+
+    static HITS: AtomicUsize = AtomicUsize::new(0);
+    static RAN_ON: AtomicU64 = AtomicU64::new(0);
+    /// Calls that found interrupts enabled.
+    static IRQS_ON: AtomicUsize = AtomicUsize::new(0);
+
+    fn record_hit(weight: usize) {
+        HITS.fetch_add(weight, Ordering::AcqRel);
+        RAN_ON.fetch_or(cpu::mask_of(cpu::current()), Ordering::AcqRel);
+        if crate::arch::interrupts_enabled() {
+            IRQS_ON.fetch_add(1, Ordering::AcqRel);
+        }
+    }
+
+    const ASYNC_ROUNDS: usize = 64;
+
+    HITS.store(0, Ordering::Release);
+    RAN_ON.store(0, Ordering::Release);
+    let targets = smp_call_function(ALL_CPUS, record_hit, 1, true);
+    let n = cpu::iter(targets).count();
+    let sync_ok = HITS.load(Ordering::Acquire) == n && RAN_ON.load(Ordering::Acquire) == targets;
+
+    HITS.store(0, Ordering::Release);
+    let mut expected = 0;
+    for _ in 0..ASYNC_ROUNDS {
+        expected += cpu::iter(smp_call_function(ALL_CPUS, record_hit, 1, false)).count();
+    }
+    // The next call from this CPU waits for the last asynchronous one.
+    expected += cpu::iter(smp_call_function(ALL_CPUS, record_hit, 1, true)).count();
+    let async_ok = HITS.load(Ordering::Acquire) == expected;
+
+    HITS.store(0, Ordering::Release);
+    RAN_ON.store(0, Ordering::Release);
+    on_each_cpu(record_hit, 1);
+    let online = cpu::online_mask();
+    let each_ok = HITS.load(Ordering::Acquire) == cpu::iter(online).count()
+        && RAN_ON.load(Ordering::Acquire) == online;
+
+    crate::klogln!(
+        "[smp] call_function: {} targets, {} async calls",
+        n,
+        expected
+    );
+    sync_ok && async_ok && each_ok && IRQS_ON.load(Ordering::Acquire) == 0
+}
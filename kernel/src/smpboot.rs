@@ -0,0 +1,171 @@
+//! Application processor bring-up.
+//!
+//! Limine holds every AP until it is given a start address, on a stack in
+//! bootloader-reclaimable memory that the frame allocator later hands
+//! out. So `start_aps` sends them off from `kentry`, before anything is
+//! allocated: each AP moves to a stack of its own and spins there until
+//! released.
+//!
+//! Once the BSP has interrupts, timers and the scheduler up, `boot_aps`
+//! releases the APs one at a time. Each sets up its CPU state, takes part
+//! in the TSC check against the BSP, starts its tick and joins the
+//! scheduler as that CPU's idle thread. Each AP is given the next logical
+//! CPU index as it is released (the BSP is 0), so the first `MAX_CPUS`
+//! CPUs come up whatever their hardware IDs.
+
+use core::sync::atomic::{AtomicU8, AtomicUsize, Ordering};
+
+use bootabi::{BootInfo, CpuFlags};
+
+use crate::cpu::{self, MAX_CPUS};
+use crate::svc::sched;
+
+const AP_STACK_SIZE: usize = 64 * 1024;
+
+/// How long to wait for an AP to arrive or come online.
+const AP_TIMEOUT_NS: u64 = 1_000_000_000;
+
+const AP_ABSENT: u8 = 0;
+/// Spinning on its own stack, waiting for `boot_aps`.
+const AP_PARKED: u8 = 1;
+const AP_RELEASED: u8 = 2;
+const AP_ONLINE: u8 = 3;
+
+#[repr(align(16))]
+struct ApStack([u8; AP_STACK_SIZE]);
+
+/// Boot stacks by index in `BootInfo::cpus`; each becomes that CPU's idle
+/// thread stack.
+static mut AP_STACKS: [ApStack; MAX_CPUS] = [const { ApStack([0; AP_STACK_SIZE]) }; MAX_CPUS];
+
+static AP_STATE: [AtomicU8; MAX_CPUS] = [const { AtomicU8::new(AP_ABSENT) }; MAX_CPUS];
+
+/// Logical CPU index handed to each AP before it is released.
+static AP_CPU: [AtomicUsize; MAX_CPUS] = [const { AtomicUsize::new(0) }; MAX_CPUS];
+
+/// Bound on the spin while APs leave bootloader memory; there is no
+/// clock yet.
+const PARK_SPINS: u64 = 100_000_000;
+
+/// Send the APs to their holding loop and wait until they are off the
+/// bootloader's stacks. Call first thing in `kentry`. Returns how many
+/// were started.
+pub fn start_aps() -> u32 {
+    let started = bootloader_limine::start_aps(ap_entry);
+    for _ in 0..PARK_SPINS {
+        let parked = AP_STATE
+            .iter()
+            .filter(|s| s.load(Ordering::Acquire) == AP_PARKED)
+            .count();
+        if parked >= started as usize {
+            break;
+        }
+        crate::arch::cpu_relax();
+    }
+    started
+}
+
+extern "C" fn ap_entry(index: u32) -> ! {
+    let index = index as usize;
+    if index >= MAX_CPUS {
+        // No stack for it; leave it spinning where the bootloader put it.
+        loop {
+            crate::arch::cpu_relax();
+        }
+    }
+    let top = unsafe { core::ptr::addr_of!(AP_STACKS[index]) as u64 + AP_STACK_SIZE as u64 };
+    unsafe { crate::arch::call_on_stack(top, ap_park, index) }
+}
+
+extern "C" fn ap_park(index: usize) -> ! {
+    AP_STATE[index].store(AP_PARKED, Ordering::Release);
+    while AP_STATE[index].load(Ordering::Acquire) != AP_RELEASED {
+        crate::arch::cpu_relax();
+    }
+    ap_main(index)
+}
+
+fn ap_main(index: usize) -> ! {
+    unsafe {
+        crate::arch::init_ap(AP_CPU[index].load(Ordering::Acquire));
+    }
+    crate::arch::ap_clock_sync();
+    hal::time::activate_cpu();
+    crate::time::tick::start_cpu();
+    sched::init_cpu();
+    AP_STATE[index].store(AP_ONLINE, Ordering::Release);
+    crate::arch::enable_interrupts();
+    sched::run_idle()
+}
+
+/// Spin until `cond` holds or `AP_TIMEOUT_NS` passes.
+fn wait_for(cond: impl Fn() -> bool) -> bool {
+    let deadline = crate::time::monotonic_ns().saturating_add(AP_TIMEOUT_NS);
+    while !cond() {
+        if crate::time::monotonic_ns() >= deadline {
+            return false;
+        }
+        crate::arch::cpu_relax();
+    }
+    true
+}
+
+/// Bring the parked APs online one at a time, then re-pick the
+/// clocksource in case the TSC check found it unreliable. Returns how
+/// many came up.
+pub fn boot_aps(boot: &BootInfo) -> usize {
+    let mut online = 0;
+    let mut next_cpu = 1;
+    for (index, entry) in crate::bootinfo::cpu_entries(boot).iter().enumerate() {
+        if entry.flags & (CpuFlags::BSP as u32) != 0 {
+            continue;
+        }
+        if index >= MAX_CPUS || next_cpu >= MAX_CPUS {
+            crate::klogln!(
+                "[smp] cpu {:#x} is beyond MAX_CPUS={}; left parked",
+                entry.hw_id,
+                MAX_CPUS
+            );
+            continue;
+        }
+        let state = &AP_STATE[index];
+        if !wait_for(|| state.load(Ordering::Acquire) == AP_PARKED) {
+            crate::klogln!("[smp] cpu {:#x} never arrived", entry.hw_id);
+            continue;
+        }
+
+        let cpu = next_cpu;
+        next_cpu += 1;
+        AP_CPU[index].store(cpu, Ordering::Release);
+        state.store(AP_RELEASED, Ordering::Release);
+        let sync = crate::arch::bsp_clock_sync();
+        if !wait_for(|| state.load(Ordering::Acquire) == AP_ONLINE) {
+            crate::klogln!("[smp] cpu{} did not come online", cpu);
+            continue;
+        }
+        online += 1;
+        crate::klogln!(
+            "[smp] cpu{} (hw {:#x}) online: clock offset={} adjusted={} max_warp={}{}",
+            cpu,
+            entry.hw_id,
+            sync.offset,
+            sync.adjusted,
+            sync.max_warp,
+            if !sync.responded {
+                " (no clock check)"
+            } else if !sync.stable {
+                " (unstable)"
+            } else {
+                ""
+            }
+        );
+    }
+
+    crate::time::select_clocksource();
+    crate::klogln!(
+        "[smp] {} of {} CPUs online",
+        cpu::iter(cpu::online_mask()).count(),
+        boot.cpu_count
+    );
+    online
+}
@@ -0,0 +1,567 @@
+//! Ring-buffer channels for bulk, asynchronous IPC.
+//!
+//! A channel is a one-way ring of fixed-size records on a page that is
+//! mapped into the address spaces of both the producer and the
+//! consumer. Each side moves only its own index (`head` for the
+//! producer, `tail` for the consumer), so once the page is mapped, data
+//! flows without a syscall per message. The kernel reads and writes the
+//! same page through the HHDM for kernel threads and for messages that
+//! carry handles.
+//!
+//! A write that makes the ring non-empty wakes sleeping readers, and a
+//! read that makes a full ring non-full wakes sleeping writers. A
+//! producer that writes the page directly calls `notify` after such a
+//! transition. When the ring is full, `try_write` fails with `Full` and
+//! `write` waits for space.
+//!
+//! Handles cannot travel through memory that user space can write, so
+//! the capabilities sent with a record are held by the kernel and indexed
+//! by ring slot. The reader receives them in its own table.
+//!
+//! Everything on the ring page may be scribbled on by either side. The
+//! kernel clamps what it reads from the page and never trusts it for
+//! anything beyond the bytes of a message.
+//!
+//! A mapping keeps the channel alive: the first `map` pins it and the
+//! last `unmap` lets it go, so the page is never freed while an address
+//! space can still reach it.
+
+use core::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+
+use hal::mmu::{AddressSpace, MapError, MapFlags, PhysAddr, VirtAddr};
+
+use crate::obj::{
+    Capability, Handle, HandleError, HandleTable, KernelObject, Obj, ObjHeader, ObjPool, ObjRef,
+    ObjType, Rights,
+};
+use crate::svc::vm;
+use crate::sync::{SpinLock, WaitQueue};
+
+/// Records in a ring; a power of two so free-running indices wrap
+/// cleanly.
+pub const RING_SLOTS: usize = 32;
+/// Payload bytes in one record.
+pub const RECORD_BYTES: usize = 112;
+/// Handles that can travel with one record.
+pub const MAX_MSG_HANDLES: usize = 4;
+
+/// Start of the ring page. `head` and `tail` count records ever written
+/// and read; the slot of record `n` is `n % RING_SLOTS`.
+#[repr(C, align(64))]
+pub struct RingHeader {
+    pub head: AtomicU32,
+    pub tail: AtomicU32,
+}
+
+#[repr(C)]
+pub struct Record {
+    /// Payload bytes used.
+    pub len: u32,
+    /// Handles sent with the record; informational, the kernel keeps
+    /// the capabilities.
+    pub handles: u32,
+    pub data: [u8; RECORD_BYTES],
+}
+
+const PAGE_SIZE: usize = 4096;
+const RECORDS_OFFSET: usize = core::mem::size_of::<RingHeader>();
+
+const _: () = assert!(RING_SLOTS.is_power_of_two());
+const _: () = assert!(RECORDS_OFFSET + RING_SLOTS * core::mem::size_of::<Record>() <= PAGE_SIZE);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChannelError {
+    /// No room for another record.
+    Full,
+    /// No record to read.
+    Empty,
+    /// The payload exceeds `RECORD_BYTES` or the handles exceed
+    /// `MAX_MSG_HANDLES`.
+    TooLarge,
+    /// The read buffer is shorter than the record; it stays queued.
+    BufferTooSmall,
+    Handle(HandleError),
+    Map(MapError),
+}
+
+impl From<HandleError> for ChannelError {
+    fn from(e: HandleError) -> Self {
+        Self::Handle(e)
+    }
+}
+
+/// What `read` delivered.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Received {
+    pub len: usize,
+    /// Handles added to the reader's table, written to the front of its
+    /// handle buffer.
+    pub handles: usize,
+}
+
+type SlotCaps = [Option<Capability>; MAX_MSG_HANDLES];
+
+/// Live mappings of the ring page, and the reference they hold while
+/// there are any.
+struct Mappings {
+    count: u32,
+    pin: Option<ObjRef>,
+}
+
+const NO_CAPS: SlotCaps = [const { None }; MAX_MSG_HANDLES];
+
+#[repr(C)]
+pub struct Channel {
+    hdr: ObjHeader,
+    /// Physical address of the ring page.
+    frame: AtomicU64,
+    /// Capabilities in flight, by ring slot. Also serializes the
+    /// kernel's own reads and writes.
+    caps: SpinLock<[SlotCaps; RING_SLOTS]>,
+    maps: SpinLock<Mappings>,
+    readable: WaitQueue,
+    writable: WaitQueue,
+}
+
+unsafe impl KernelObject for Channel {
+    const TYPE: ObjType = ObjType::Channel;
+
+    fn header(&self) -> &ObjHeader {
+        &self.hdr
+    }
+
+    fn on_release(&'static self) {
+        // Mappings pin the channel, so none are left to reach the frame.
+        // Released references may be channels too; drop them unlocked.
+        let caps = core::mem::replace(&mut *self.caps.lock(), [NO_CAPS; RING_SLOTS]);
+        drop(caps);
+        vm::free_frame(PhysAddr(self.frame.swap(0, Ordering::AcqRel)));
+    }
+}
+
+/// Channels in the system at once.
+pub const MAX_CHANNELS: usize = 16;
+
+static CHANNELS: ObjPool<Channel, MAX_CHANNELS> = ObjPool::new(
+    [const {
+        Channel {
+            hdr: ObjHeader::new(),
+            frame: AtomicU64::new(0),
+            caps: SpinLock::new([NO_CAPS; RING_SLOTS]),
+            maps: SpinLock::new(Mappings {
+                count: 0,
+                pin: None,
+            }),
+            readable: WaitQueue::new(),
+            writable: WaitQueue::new(),
+        }
+    }; MAX_CHANNELS],
+);
+
+impl Channel {
+    /// A new channel with an empty ring, or `None` if the pool or
+    /// memory is exhausted.
+    pub fn create(name: &'static str) -> Option<Obj<Channel>> {
+        let frame = vm::alloc_frame()?;
+        let ch = CHANNELS.alloc(name, |ch| ch.frame.store(frame.0, Ordering::Release));
+        if ch.is_none() {
+            vm::free_frame(frame);
+        }
+        ch
+    }
+
+    pub fn frame(&self) -> PhysAddr {
+        PhysAddr(self.frame.load(Ordering::Acquire))
+    }
+
+    /// Map the ring page of `ch` at `vaddr` in `aspace`, read-write and,
+    /// with `user`, accessible from user mode. The channel stays alive
+    /// until the mapping is removed with `unmap`, which must happen
+    /// before `aspace` is destroyed.
+    pub fn map(
+        ch: &Obj<Channel>,
+        aspace: &mut AddressSpace,
+        vaddr: VirtAddr,
+        user: bool,
+    ) -> Result<(), MapError> {
+        let mut flags = MapFlags::READ | MapFlags::WRITE;
+        if user {
+            flags |= MapFlags::USER;
+        }
+        let mut maps = ch.maps.lock();
+        vm::map_4k(aspace, vaddr, ch.frame(), flags)?;
+        maps.count += 1;
+        if maps.pin.is_none() {
+            maps.pin = Some(ch.as_ref().clone());
+        }
+        Ok(())
+    }
+
+    /// Remove a mapping made by `map`. Fails with `NotMapped` if the
+    /// ring page is not mapped at `vaddr`.
+    pub fn unmap(&self, aspace: &mut AddressSpace, vaddr: VirtAddr) -> Result<(), MapError> {
+        let mut maps = self.maps.lock();
+        if maps.count == 0 || vm::translate(aspace, vaddr) != Ok(self.frame()) {
+            return Err(MapError::NotMapped);
+        }
+        vm::unmap_4k(aspace, vaddr)?;
+        maps.count -= 1;
+        let pin = if maps.count == 0 {
+            maps.pin.take()
+        } else {
+            None
+        };
+        // The last reference may go with the pin; release outside the lock.
+        drop(maps);
+        drop(pin);
+        Ok(())
+    }
+
+    fn ring(&self) -> &RingHeader {
+        unsafe { &*(vm::phys_to_virt(self.frame()) as *const RingHeader) }
+    }
+
+    fn record(&self, n: u32) -> *mut Record {
+        let slot = n as usize % RING_SLOTS;
+        unsafe {
+            vm::phys_to_virt(self.frame())
+                .add(RECORDS_OFFSET)
+                .cast::<Record>()
+                .add(slot)
+        }
+    }
+
+    /// Records queued. Clamped: the indices live in shared memory.
+    pub fn len(&self) -> usize {
+        let ring = self.ring();
+        let queued = ring
+            .head
+            .load(Ordering::Acquire)
+            .wrapping_sub(ring.tail.load(Ordering::Acquire));
+        (queued as usize).min(RING_SLOTS)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.len() == RING_SLOTS
+    }
+
+    /// Append a record of `data`, moving `handles` out of `from` to go
+    /// with it. Needs `TRANSFER` on each handle. Nothing is sent or
+    /// taken on failure.
+    pub fn try_write(
+        &self,
+        from: &HandleTable,
+        data: &[u8],
+        handles: &[Handle],
+    ) -> Result<(), ChannelError> {
+        if data.len() > RECORD_BYTES || handles.len() > MAX_MSG_HANDLES {
+            return Err(ChannelError::TooLarge);
+        }
+        let mut caps = self.caps.lock();
+        if self.is_full() {
+            return Err(ChannelError::Full);
+        }
+        let mut taken = NO_CAPS;
+        from.take_all(handles, Rights::all(), &mut taken)?;
+
+        let ring = self.ring();
+        let head = ring.head.load(Ordering::Relaxed);
+        let was_empty = head == ring.tail.load(Ordering::Acquire);
+        let rec = self.record(head);
+        unsafe {
+            (*rec).len = data.len() as u32;
+            (*rec).handles = handles.len() as u32;
+            core::ptr::copy_nonoverlapping(data.as_ptr(), (*rec).data.as_mut_ptr(), data.len());
+        }
+        // Anything left in the slot was never claimed by a reader.
+        let stale = core::mem::replace(&mut caps[head as usize % RING_SLOTS], taken);
+        ring.head.store(head.wrapping_add(1), Ordering::Release);
+        drop(caps);
+        drop(stale);
+        if was_empty {
+            self.readable.wake_all();
+        }
+        Ok(())
+    }
+
+    /// `try_write`, waiting for room while the ring is full.
+    pub fn write(
+        &self,
+        from: &HandleTable,
+        data: &[u8],
+        handles: &[Handle],
+    ) -> Result<(), ChannelError> {
+        loop {
+            match self.try_write(from, data, handles) {
+                Err(ChannelError::Full) => self.writable.wait_until(|| !self.is_full()),
+                r => return r,
+            }
+        }
+    }
+
+    /// Take the oldest record: copy its payload into `buf` and add its
+    /// handles to `to`, writing them to the front of `handles_out`.
+    /// Handles that do not fit in `to` or `handles_out` are closed.
+    pub fn try_read(
+        &self,
+        to: &HandleTable,
+        buf: &mut [u8],
+        handles_out: &mut [Handle],
+    ) -> Result<Received, ChannelError> {
+        let mut caps = self.caps.lock();
+        let ring = self.ring();
+        let tail = ring.tail.load(Ordering::Relaxed);
+        let head = ring.head.load(Ordering::Acquire);
+        if head == tail {
+            return Err(ChannelError::Empty);
+        }
+        let was_full = head.wrapping_sub(tail) as usize >= RING_SLOTS;
+        let rec = self.record(tail);
+        let len = (unsafe { (*rec).len } as usize).min(RECORD_BYTES);
+        if len > buf.len() {
+            return Err(ChannelError::BufferTooSmall);
+        }
+        unsafe {
+            core::ptr::copy_nonoverlapping((*rec).data.as_ptr(), buf.as_mut_ptr(), len);
+        }
+        let taken = core::mem::replace(&mut caps[tail as usize % RING_SLOTS], NO_CAPS);
+        ring.tail.store(tail.wrapping_add(1), Ordering::Release);
+        drop(caps);
+
+        let mut n = 0;
+        for cap in taken.into_iter().flatten() {
+            if n == handles_out.len() {
+                continue;
+            }
+            if let Ok(h) = to.insert_cap(cap) {
+                handles_out[n] = h;
+                n += 1;
+            }
+        }
+        if was_full {
+            self.writable.wake_all();
+        }
+        Ok(Received { len, handles: n })
+    }
+
+    /// `try_read`, waiting for a record while the ring is empty.
+    pub fn read(
+        &self,
+        to: &HandleTable,
+        buf: &mut [u8],
+        handles_out: &mut [Handle],
+    ) -> Result<Received, ChannelError> {
+        loop {
+            match self.try_read(to, buf, handles_out) {
+                Err(ChannelError::Empty) => self.readable.wait_until(|| !self.is_empty()),
+                r => return r,
+            }
+        }
+    }
+
+    /// Wake both sides to re-check the ring, after user space moved an
+    /// index itself.
+    pub fn notify(&self) {
+        self.readable.wake_all();
+        self.writable.wake_all();
+    }
+}
+
+/// `Channel::write` through a handle with `Rights::WRITE`.
+pub fn write(
+    table: &HandleTable,
+    h: Handle,
+    data: &[u8],
+    handles: &[Handle],
+) -> Result<(), ChannelError> {
+    table
+        .get_as::<Channel>(h, Rights::WRITE)?
+        .write(table, data, handles)
+}
+
+/// `Channel::read` through a handle with `Rights::READ`.
+pub fn read(
+    table: &HandleTable,
+    h: Handle,
+    buf: &mut [u8],
+    handles_out: &mut [Handle],
+) -> Result<Received, ChannelError> {
+    table
+        .get_as::<Channel>(h, Rights::READ)?
+        .read(table, buf, handles_out)
+}
+
+/// `Channel::map` through a handle with `Rights::MAP`, for user mode.
+pub fn map(
+    table: &HandleTable,
+    h: Handle,
+    aspace: &mut AddressSpace,
+    vaddr: VirtAddr,
+) -> Result<(), ChannelError> {
+    let ch = table.get_as::<Channel>(h, Rights::MAP)?;
+    Channel::map(&ch, aspace, vaddr, true).map_err(ChannelError::Map)
+}
+
+/// `Channel::unmap` through a handle with `Rights::MAP`.
+pub fn unmap(
+    table: &HandleTable,
+    h: Handle,
+    aspace: &mut AddressSpace,
+    vaddr: VirtAddr,
+) -> Result<(), ChannelError> {
+    let ch = table.get_as::<Channel>(h, Rights::MAP)?;
+    ch.unmap(aspace, vaddr).map_err(ChannelError::Map)
+}
+
+/// Shared mapping, a handle sent with a record, backpressure on a full
+/// ring, then a stream between two threads that must block on each
+/// other both ways.
+#[cfg(feature = "selftest")]
+pub fn self_test() -> bool {
+    use crate::selftest::{self, TestThread};
+    use crate::svc::ipc::Endpoint;
+
+    /// User address the self-test maps rings at.
+    const TEST_VADDR: VirtAddr = VirtAddr(0x4000_0000);
+
+    /// Records streamed through the ring by the test threads: enough to
+    /// fill it several times over.
+    const STREAM: usize = RING_SLOTS * 4;
+
+    fn producer(arg: usize) -> usize {
+        let ch = unsafe { selftest::obj_arg::<Channel>(arg) };
+        let Some(table) = crate::obj::alloc_table() else {
+            return 0;
+        };
+        let mut sent = 0;
+        for i in 0..STREAM {
+            if ch.write(table, &(i as u32).to_le_bytes(), &[]).is_ok() {
+                sent += 1;
+            }
+        }
+        crate::obj::free_table(table);
+        sent
+    }
+
+    /// Read the stream back; returns how many records arrived in order.
+    fn consumer(arg: usize) -> usize {
+        let ch = unsafe { selftest::obj_arg::<Channel>(arg) };
+        let Some(table) = crate::obj::alloc_table() else {
+            return 0;
+        };
+        let mut in_order = 0;
+        let mut buf = [0u8; RECORD_BYTES];
+        for i in 0..STREAM {
+            let got = ch.read(table, &mut buf, &mut []);
+            if got.is_ok_and(|r| r.len == 4) && buf[..4] == (i as u32).to_le_bytes() {
+                in_order += 1;
+            }
+        }
+        crate::obj::free_table(table);
+        in_order
+    }
+
+    /// Map the ring into two address spaces, one of them through a
+    /// handle, and check both reach the same frame, and that the mappings
+    /// pin the channel until the last unmap.
+    fn map_test(table: &HandleTable, ch: &Obj<Channel>) -> bool {
+        let (Ok(a), Ok(b)) = (vm::new_address_space(), vm::new_address_space()) else {
+            return false;
+        };
+        let Ok(h) = table.insert(ch.as_ref().clone(), Rights::MAP) else {
+            return false;
+        };
+        let refs = ch.header().ref_count();
+        let mut ok = map(table, h, a, TEST_VADDR).is_ok()
+            && Channel::map(ch, b, TEST_VADDR, true).is_ok()
+            && vm::translate(a, TEST_VADDR) == Ok(ch.frame())
+            && vm::translate(b, TEST_VADDR) == Ok(ch.frame())
+            && ch.header().ref_count() == refs + 1;
+        ok &= unmap(table, h, a, TEST_VADDR).is_ok() && ch.header().ref_count() == refs + 1;
+        ok &= unmap(table, h, a, TEST_VADDR) == Err(ChannelError::Map(MapError::NotMapped));
+        ok &= ch.unmap(b, TEST_VADDR).is_ok() && ch.header().ref_count() == refs;
+        ok &= table.close(h).is_ok();
+        vm::destroy_address_space(a);
+        vm::destroy_address_space(b);
+        ok
+    }
+
+    let (Some(a), Some(b)) = (crate::obj::alloc_table(), crate::obj::alloc_table()) else {
+        return false;
+    };
+    let (Some(ch), Some(ep)) = (
+        Channel::create("chan-test"),
+        Endpoint::create("chan-test-ep"),
+    ) else {
+        return false;
+    };
+    let mut ok = map_test(a, &ch);
+
+    // A handle rides along with a record and lands in the reader's table,
+    // both ends going through their own handle to the channel.
+    let ep_ref = ep.as_ref().clone();
+    let (Ok(wh), Ok(rh)) = (
+        a.insert(ch.as_ref().clone(), Rights::WRITE),
+        b.insert(ch.as_ref().clone(), Rights::READ),
+    ) else {
+        return false;
+    };
+    let h = a
+        .insert(ep.into_ref(), Rights::READ | Rights::TRANSFER)
+        .unwrap_or(Handle::INVALID);
+    ok &= write(b, rh, b"hello", &[]) == Err(ChannelError::Handle(HandleError::AccessDenied));
+    ok &= write(a, wh, b"hello", &[h]).is_ok() && a.count() == 1;
+    let mut buf = [0u8; RECORD_BYTES];
+    let mut got = [Handle::INVALID; MAX_MSG_HANDLES];
+    ok &= ch.try_read(b, &mut buf[..2], &mut got) == Err(ChannelError::BufferTooSmall);
+    let r = read(b, rh, &mut buf, &mut got);
+    ok &= r == Ok(Received { len: 5, handles: 1 }) && &buf[..5] == b"hello";
+    ok &= b.get(got[0], Rights::READ).is_ok_and(|o| o.ptr_eq(&ep_ref));
+    ok &= ch.try_read(b, &mut buf, &mut got) == Err(ChannelError::Empty);
+
+    // One bad handle in the list: nothing is sent and nothing is taken.
+    let h = a
+        .insert(ep_ref.clone(), Rights::TRANSFER)
+        .unwrap_or(Handle::INVALID);
+    let bad = Err(ChannelError::Handle(HandleError::BadHandle));
+    ok &= ch.try_write(a, b"x", &[h, Handle::INVALID]) == bad;
+    ok &= ch.try_write(a, b"x", &[h, h]) == bad;
+    ok &= a.rights(h).is_ok() && ch.is_empty();
+    ok &= a.close(h).is_ok();
+
+    // Fill the ring, get pushed back, drain it in order.
+    for i in 0..RING_SLOTS {
+        ok &= ch.try_write(a, &[i as u8], &[]).is_ok();
+    }
+    ok &= ch.try_write(a, &[0], &[]) == Err(ChannelError::Full);
+    for i in 0..RING_SLOTS {
+        ok &= ch.try_read(b, &mut buf, &mut []).is_ok() && buf[0] == i as u8;
+    }
+    ok &= ch.is_empty();
+
+    let arg = selftest::arg(&*ch);
+    ok &= selftest::run_threads(
+        crate::cpu::ALL_CPUS,
+        [
+            TestThread::new("chan-producer", producer, arg, STREAM),
+            TestThread::new("chan-consumer", consumer, arg, STREAM),
+        ],
+    );
+
+    crate::obj::free_table(a);
+    crate::obj::free_table(b);
+    drop(ep_ref);
+    drop(ch);
+    ok &= CHANNELS.live() == 0;
+    selftest::report(
+        format_args!(
+            "[chan] {} records streamed through {} slots",
+            STREAM, RING_SLOTS
+        ),
+        ok,
+    )
+}
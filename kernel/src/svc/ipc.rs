@@ -1,4 +1,700 @@
-pub fn init<_T>(_boot: &_T) {
-    // MVP: ring-buffer channels or mailbox objects
-    // Later: capability-guarded endpoints + fastpath
+//! Synchronous IPC endpoints.
+//!
+//! An endpoint is a rendezvous point with no buffer: `send` waits for a
+//! receiver and `recv` for a sender, and a message passes straight from
+//! one thread to the other. Messages are small, a tag and `MSG_REGS`
+//! words, so the syscall layer carries them in registers and nothing is
+//! copied through memory the threads share.
+//!
+//! `call` sends and then waits for an answer. The receiver gets an
+//! implicit reply right to the caller, used by `reply` or by
+//! `reply_recv`, which answers and waits for the next message in one
+//! step. The right lasts until the receiver's next receive: receiving
+//! again without replying fails the caller with `IpcError::Closed`. When the other side is already waiting, `call` and `reply_recv`
+//! switch straight to it on the current CPU (`sched::block_handoff`)
+//! instead of waking it through the run queues: the fastpath a
+//! client/server round trip takes.
+//!
+//! `recv_or_signal` also returns when a notification is signalled, so a
+//! server can take requests and driver interrupts in one loop.
+//!
+//! Endpoints are guarded by handles: sending and calling need
+//! `Rights::WRITE`, receiving needs `Rights::READ`.
+//!
+//! Releasing an endpoint fails every thread still queued on it with
+//! `IpcError::Closed`. A thread that already handed its message to a
+//! receiver keeps waiting for that receiver's reply.
+
+use core::cell::UnsafeCell;
+use core::sync::atomic::{AtomicBool, AtomicU8, AtomicUsize, Ordering};
+
+use crate::obj::{
+    Handle, HandleError, HandleTable, KernelObject, Obj, ObjHeader, ObjPool, ObjType, Rights,
+};
+use crate::svc::notification::Notification;
+use crate::svc::sched::{self, thread::MAX_THREADS};
+use crate::sync::SpinLockIrq;
+
+const NO_SLOT: usize = usize::MAX;
+
+/// Message words besides the tag.
+pub const MSG_REGS: usize = 4;
+
+/// An IPC message: what fits in the registers of one syscall.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Msg {
+    /// Meaning is up to the protocol; typically an operation code.
+    pub tag: u64,
+    pub regs: [u64; MSG_REGS],
+}
+
+impl Msg {
+    pub const fn new(tag: u64, regs: [u64; MSG_REGS]) -> Self {
+        Self { tag, regs }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IpcError {
+    /// The endpoint was released before the message got through, or the
+    /// receiver of a call moved on without replying.
+    Closed,
+    Handle(HandleError),
+}
+
+impl From<HandleError> for IpcError {
+    fn from(e: HandleError) -> Self {
+        Self::Handle(e)
+    }
+}
+
+/// Where a thread is in an IPC operation.
+#[repr(u8)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum IpcState {
+    /// Not waiting; its message buffer holds what it last received.
+    Idle = 0,
+    /// Queued on an endpoint with a message to send.
+    Sending = 1,
+    /// Queued on an endpoint with a message to send, then a reply to
+    /// wait for.
+    Calling = 2,
+    /// Queued on an endpoint, waiting for a message.
+    Receiving = 3,
+    /// Message taken; waiting for the reply.
+    AwaitingReply = 4,
+}
+
+/// Per-thread IPC state, indexed by thread slot.
+struct IpcThread {
+    /// Outgoing message while Sending or Calling, incoming once Idle.
+    /// Written by the thread itself while it is Idle, otherwise by
+    /// whoever moves it back to Idle.
+    msg: UnsafeCell<Msg>,
+    state: AtomicU8,
+    /// Caller owed a reply, or `NO_SLOT`.
+    reply_to: AtomicUsize,
+    /// Next thread on the same endpoint queue; guarded by its lock.
+    next: AtomicUsize,
+    /// The endpoint was released while this thread was queued on it.
+    /// Set before the thread is moved back to Idle.
+    closed: AtomicBool,
+}
+
+// `msg` has one writer at a time, handed over through `state`.
+unsafe impl Sync for IpcThread {}
+
+static THREADS: [IpcThread; MAX_THREADS] = [const {
+    IpcThread {
+        msg: UnsafeCell::new(Msg::new(0, [0; MSG_REGS])),
+        state: AtomicU8::new(IpcState::Idle as u8),
+        reply_to: AtomicUsize::new(NO_SLOT),
+        next: AtomicUsize::new(NO_SLOT),
+        closed: AtomicBool::new(false),
+    }
+}; MAX_THREADS];
+
+impl IpcThread {
+    fn state(&self) -> IpcState {
+        match self.state.load(Ordering::Acquire) {
+            1 => IpcState::Sending,
+            2 => IpcState::Calling,
+            3 => IpcState::Receiving,
+            4 => IpcState::AwaitingReply,
+            _ => IpcState::Idle,
+        }
+    }
+
+    fn set_state(&self, s: IpcState) {
+        self.state.store(s as u8, Ordering::Release);
+    }
+
+    /// Hand `msg` to this thread, which is waiting for one, and mark it
+    /// Idle. The caller wakes or switches to it next.
+    fn deliver(&self, msg: Msg) {
+        unsafe {
+            *self.msg.get() = msg;
+        }
+        self.set_state(IpcState::Idle);
+    }
+
+    fn take_msg(&self) -> Msg {
+        unsafe { *self.msg.get() }
+    }
+}
+
+/// Threads queued on one side of an endpoint, in arrival order, linked
+/// through `IpcThread::next`.
+struct List {
+    head: usize,
+    tail: usize,
+}
+
+impl List {
+    const fn new() -> Self {
+        Self {
+            head: NO_SLOT,
+            tail: NO_SLOT,
+        }
+    }
+
+    fn push(&mut self, slot: usize) {
+        THREADS[slot].next.store(NO_SLOT, Ordering::Relaxed);
+        if self.tail == NO_SLOT {
+            self.head = slot;
+        } else {
+            THREADS[self.tail].next.store(slot, Ordering::Relaxed);
+        }
+        self.tail = slot;
+    }
+
+    fn pop(&mut self) -> Option<usize> {
+        let slot = self.head;
+        if slot == NO_SLOT {
+            return None;
+        }
+        self.head = THREADS[slot].next.load(Ordering::Relaxed);
+        if self.head == NO_SLOT {
+            self.tail = NO_SLOT;
+        }
+        Some(slot)
+    }
+
+    fn remove(&mut self, slot: usize) -> bool {
+        let mut prev = NO_SLOT;
+        let mut cur = self.head;
+        while cur != NO_SLOT {
+            let next = THREADS[cur].next.load(Ordering::Relaxed);
+            if cur == slot {
+                if prev == NO_SLOT {
+                    self.head = next;
+                } else {
+                    THREADS[prev].next.store(next, Ordering::Relaxed);
+                }
+                if self.tail == slot {
+                    self.tail = prev;
+                }
+                return true;
+            }
+            prev = cur;
+            cur = next;
+        }
+        false
+    }
+}
+
+/// Senders and receivers waiting on an endpoint. At most one side is
+/// non-empty.
+struct Queues {
+    senders: List,
+    receivers: List,
+    /// Released; nothing may queue any more.
+    closed: bool,
+}
+
+#[repr(C)]
+pub struct Endpoint {
+    hdr: ObjHeader,
+    q: SpinLockIrq<Queues>,
+}
+
+unsafe impl KernelObject for Endpoint {
+    const TYPE: ObjType = ObjType::Endpoint;
+
+    fn header(&self) -> &ObjHeader {
+        &self.hdr
+    }
+
+    fn on_release(&'static self) {
+        // Fail the waiters one at a time, waking each outside the lock;
+        // `closed` keeps new ones from queueing meanwhile.
+        loop {
+            let mut q = self.q.lock();
+            q.closed = true;
+            let Some(slot) = q.senders.pop().or_else(|| q.receivers.pop()) else {
+                return;
+            };
+            THREADS[slot].closed.store(true, Ordering::Relaxed);
+            THREADS[slot].set_state(IpcState::Idle);
+            drop(q);
+            sched::wake_slot(slot);
+        }
+    }
+}
+
+/// Endpoints in the system at once.
+pub const MAX_ENDPOINTS: usize = 32;
+
+static ENDPOINTS: ObjPool<Endpoint, MAX_ENDPOINTS> = ObjPool::new(
+    [const {
+        Endpoint {
+            hdr: ObjHeader::new(),
+            q: SpinLockIrq::new(Queues {
+                senders: List::new(),
+                receivers: List::new(),
+                closed: false,
+            }),
+        }
+    }; MAX_ENDPOINTS],
+);
+
+/// Block until the current thread's IPC state is back to Idle.
+fn wait_idle(me: &IpcThread) {
+    loop {
+        sched::prepare_block();
+        if me.state() == IpcState::Idle {
+            sched::cancel_block();
+            return;
+        }
+        sched::block();
+    }
+}
+
+/// The message a waiter ended up with once Idle again, or `Closed` if
+/// the endpoint went away while it was queued.
+fn finish(me: &IpcThread) -> Result<Msg, IpcError> {
+    if me.closed.swap(false, Ordering::Relaxed) {
+        return Err(IpcError::Closed);
+    }
+    Ok(me.take_msg())
+}
+
+/// Fail the caller `me` still owes a reply, if any, with `Closed`: a
+/// receive ends the reply right to the previous message.
+fn drop_reply(me: &IpcThread) {
+    let caller = me.reply_to.swap(NO_SLOT, Ordering::Relaxed);
+    if caller == NO_SLOT {
+        return;
+    }
+    THREADS[caller].closed.store(true, Ordering::Relaxed);
+    THREADS[caller].set_state(IpcState::Idle);
+    sched::wake_slot(caller);
+}
+
+/// Take the oldest queued sender's message for receiver `slot`. A caller
+/// stays blocked for its reply; a plain sender is returned to be woken
+/// once the queue lock is dropped.
+fn take_sender(q: &mut Queues, slot: usize) -> Option<(Msg, usize)> {
+    let s = q.senders.pop()?;
+    let sender = &THREADS[s];
+    let msg = sender.take_msg();
+    if sender.state() == IpcState::Calling {
+        sender.set_state(IpcState::AwaitingReply);
+        THREADS[slot].reply_to.store(s, Ordering::Relaxed);
+        return Some((msg, NO_SLOT));
+    }
+    THREADS[slot].reply_to.store(NO_SLOT, Ordering::Relaxed);
+    sender.set_state(IpcState::Idle);
+    Some((msg, s))
+}
+
+fn wake_sender(slot: usize) {
+    if slot != NO_SLOT {
+        sched::wake_slot(slot);
+    }
+}
+
+impl Endpoint {
+    /// A new endpoint, or `None` if the pool is exhausted.
+    pub fn create(name: &'static str) -> Option<Obj<Endpoint>> {
+        ENDPOINTS.alloc(name, |ep| ep.q.lock().closed = false)
+    }
+
+    /// Pass `msg` to a receiver, waiting for one if none is queued.
+    pub fn send(&self, msg: Msg) -> Result<(), IpcError> {
+        let slot = sched::current_slot();
+        let me = &THREADS[slot];
+        let mut q = self.q.lock();
+        if q.closed {
+            return Err(IpcError::Closed);
+        }
+        if let Some(r) = q.receivers.pop() {
+            drop(q);
+            THREADS[r].reply_to.store(NO_SLOT, Ordering::Relaxed);
+            THREADS[r].deliver(msg);
+            sched::wake_slot(r);
+            return Ok(());
+        }
+        unsafe {
+            *me.msg.get() = msg;
+        }
+        me.set_state(IpcState::Sending);
+        q.senders.push(slot);
+        drop(q);
+        wait_idle(me);
+        finish(me).map(drop)
+    }
+
+    /// Wait for a message. If it came from `call`, the caller waits for
+    /// `reply` or `reply_recv` from this thread. A caller still owed a
+    /// reply from before gets `Closed` instead.
+    pub fn recv(&self) -> Result<Msg, IpcError> {
+        let slot = sched::current_slot();
+        let me = &THREADS[slot];
+        drop_reply(me);
+        let mut q = self.q.lock();
+        if q.closed {
+            return Err(IpcError::Closed);
+        }
+        if let Some((msg, wake)) = take_sender(&mut q, slot) {
+            drop(q);
+            wake_sender(wake);
+            return Ok(msg);
+        }
+        me.set_state(IpcState::Receiving);
+        q.receivers.push(slot);
+        drop(q);
+        wait_idle(me);
+        finish(me)
+    }
+
+    /// `recv`, but also return when `n` has signals pending. Signals are
+    /// taken first; a message that arrives meanwhile stays queued.
+    pub fn recv_or_signal(&self, n: &Notification) -> Result<RecvEvent, IpcError> {
+        let slot = sched::current_slot();
+        let me = &THREADS[slot];
+        drop_reply(me);
+        loop {
+            let bits = n.poll();
+            if bits != 0 {
+                return Ok(RecvEvent::Signals(bits));
+            }
+            let mut q = self.q.lock();
+            if q.closed {
+                return Err(IpcError::Closed);
+            }
+            if let Some((msg, wake)) = take_sender(&mut q, slot) {
+                drop(q);
+                wake_sender(wake);
+                return Ok(RecvEvent::Msg(msg));
+            }
+            me.set_state(IpcState::Receiving);
+            q.receivers.push(slot);
+            drop(q);
+
+            // Senders wake us directly; signals through the notification.
+            loop {
+                n.waiters.prepare_to_wait();
+                if me.state() == IpcState::Idle || n.pending() != 0 {
+                    n.waiters.finish_wait();
+                    break;
+                }
+                sched::block();
+            }
+
+            let mut q = self.q.lock();
+            if me.state() == IpcState::Receiving && q.receivers.remove(slot) {
+                // Woken by a signal; take it, or start over if another
+                // waiter did.
+                me.set_state(IpcState::Idle);
+                continue;
+            }
+            // A sender or the release took us off the queue.
+            drop(q);
+            wait_idle(me);
+            return finish(me).map(RecvEvent::Msg);
+        }
+    }
+
+    /// Send `msg` and wait for the reply. With a receiver already waiting,
+    /// switch straight to it.
+    pub fn call(&self, msg: Msg) -> Result<Msg, IpcError> {
+        let slot = sched::current_slot();
+        let me = &THREADS[slot];
+        let mut q = self.q.lock();
+        if q.closed {
+            return Err(IpcError::Closed);
+        }
+        if let Some(r) = q.receivers.pop() {
+            drop(q);
+            me.set_state(IpcState::AwaitingReply);
+            THREADS[r].reply_to.store(slot, Ordering::Relaxed);
+            THREADS[r].deliver(msg);
+            // `r` cannot reply before it runs, so nothing can wake us
+            // before we are Blocked.
+            sched::prepare_block();
+            sched::block_handoff(r);
+        } else {
+            unsafe {
+                *me.msg.get() = msg;
+            }
+            me.set_state(IpcState::Calling);
+            q.senders.push(slot);
+            drop(q);
+        }
+        wait_idle(me);
+        finish(me)
+    }
+
+    /// Reply to the current caller, if any, then wait for the next
+    /// message. When no sender is queued, switch straight to the caller.
+    /// The reply goes out even if the endpoint is closed.
+    pub fn reply_recv(&self, reply_msg: Msg) -> Result<Msg, IpcError> {
+        let slot = sched::current_slot();
+        let me = &THREADS[slot];
+        let caller = me.reply_to.swap(NO_SLOT, Ordering::Relaxed);
+        let mut q = self.q.lock();
+        if caller == NO_SLOT || q.closed || q.senders.head != NO_SLOT {
+            drop(q);
+            if caller != NO_SLOT {
+                THREADS[caller].deliver(reply_msg);
+                sched::wake_slot(caller);
+            }
+            return self.recv();
+        }
+        me.set_state(IpcState::Receiving);
+        q.receivers.push(slot);
+        // Blocked before a sender can find us on the queue and wake us.
+        sched::prepare_block();
+        drop(q);
+        THREADS[caller].deliver(reply_msg);
+        sched::block_handoff(caller);
+        wait_idle(me);
+        finish(me)
+    }
+}
+
+/// Answer the caller whose message the current thread last received.
+/// Returns false if it owes no reply.
+pub fn reply(msg: Msg) -> bool {
+    let me = &THREADS[sched::current_slot()];
+    let caller = me.reply_to.swap(NO_SLOT, Ordering::Relaxed);
+    if caller == NO_SLOT {
+        return false;
+    }
+    THREADS[caller].deliver(msg);
+    sched::wake_slot(caller);
+    true
+}
+
+/// What `recv_or_signal` returned for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RecvEvent {
+    Msg(Msg),
+    /// Bits taken from the notification.
+    Signals(u64),
+}
+
+/// `Endpoint::send` through a handle with `Rights::WRITE`.
+pub fn send(table: &HandleTable, h: Handle, msg: Msg) -> Result<(), IpcError> {
+    table.get_as::<Endpoint>(h, Rights::WRITE)?.send(msg)
+}
+
+/// `Endpoint::recv` through a handle with `Rights::READ`.
+pub fn recv(table: &HandleTable, h: Handle) -> Result<Msg, IpcError> {
+    table.get_as::<Endpoint>(h, Rights::READ)?.recv()
+}
+
+/// `Endpoint::call` through a handle with `Rights::WRITE`.
+pub fn call(table: &HandleTable, h: Handle, msg: Msg) -> Result<Msg, IpcError> {
+    table.get_as::<Endpoint>(h, Rights::WRITE)?.call(msg)
+}
+
+/// `Endpoint::recv_or_signal` through an endpoint handle with
+/// `Rights::READ` and a notification handle with `Rights::WAIT`.
+pub fn recv_or_signal(table: &HandleTable, h: Handle, n: Handle) -> Result<RecvEvent, IpcError> {
+    let n = table.get_as::<Notification>(n, Rights::WAIT)?;
+    table
+        .get_as::<Endpoint>(h, Rights::READ)?
+        .recv_or_signal(&n)
+}
+
+/// `Endpoint::reply_recv` through a handle with `Rights::READ`.
+pub fn reply_recv(table: &HandleTable, h: Handle, msg: Msg) -> Result<Msg, IpcError> {
+    table.get_as::<Endpoint>(h, Rights::READ)?.reply_recv(msg)
+}
+
+/// Receive after the sender queued, then run an echo server against a
+/// client on one CPU and report the round-trip time, fail a caller whose
+/// receiver moves on without replying, fail a receiver whose endpoint is
+/// released, and check rights on endpoint handles.
+#[cfg(feature = "selftest")]
+pub fn self_test() -> bool {
+    use core::sync::atomic::AtomicU64;
+
+    use crate::selftest::{self, TestThread};
+
+    const OP_ECHO: u64 = 1;
+    const OP_NOTE: u64 = 2;
+    const OP_STOP: u64 = 3;
+
+    const ROUND_TRIPS: u64 = 1000;
+    const NOTES: u64 = 8;
+
+    static BENCH_MIN: AtomicU64 = AtomicU64::new(u64::MAX);
+    static BENCH_TOTAL: AtomicU64 = AtomicU64::new(0);
+
+    /// Echo server: answers each call with its words incremented, counts
+    /// one-way notes, and exits with the note count on `OP_STOP`.
+    fn server(arg: usize) -> usize {
+        let ep = unsafe { selftest::obj_arg::<Endpoint>(arg) };
+        let mut notes = 0;
+        let Ok(mut msg) = ep.recv() else {
+            return notes;
+        };
+        loop {
+            let out = match msg.tag {
+                OP_ECHO => Msg::new(OP_ECHO, msg.regs.map(|r| r + 1)),
+                OP_NOTE => {
+                    notes += 1;
+                    Msg::default()
+                }
+                _ => {
+                    reply(Msg::new(OP_STOP, [0; MSG_REGS]));
+                    return notes;
+                }
+            };
+            match ep.reply_recv(out) {
+                Ok(next) => msg = next,
+                Err(_) => return notes,
+            }
+        }
+    }
+
+    /// Check echoes and time each round trip, then send notes and stop
+    /// the server.
+    fn client(arg: usize) -> usize {
+        let ep = unsafe { selftest::obj_arg::<Endpoint>(arg) };
+        let mut ok = true;
+        for i in 0..ROUND_TRIPS {
+            let start = hal::time::now_ticks();
+            let r = ep.call(Msg::new(OP_ECHO, [i, i + 1, i + 2, i + 3]));
+            let ticks = hal::time::now_ticks().wrapping_sub(start);
+            ok &= r == Ok(Msg::new(OP_ECHO, [i + 1, i + 2, i + 3, i + 4]));
+            BENCH_MIN.fetch_min(ticks, Ordering::Relaxed);
+            BENCH_TOTAL.fetch_add(ticks, Ordering::Relaxed);
+        }
+        for i in 0..NOTES {
+            ok &= ep.send(Msg::new(OP_NOTE, [i; MSG_REGS])).is_ok();
+        }
+        ok &= ep
+            .call(Msg::new(OP_STOP, [0; MSG_REGS]))
+            .is_ok_and(|r| r.tag == OP_STOP);
+        ok as usize
+    }
+
+    fn lone_sender(arg: usize) -> usize {
+        let ep = unsafe { selftest::obj_arg::<Endpoint>(arg) };
+        ep.send(Msg::new(OP_NOTE, [7; MSG_REGS])).is_ok() as usize
+    }
+
+    fn lone_receiver(arg: usize) -> usize {
+        let ep = unsafe { selftest::obj_arg::<Endpoint>(arg) };
+        (ep.recv() == Ok(Msg::new(OP_NOTE, [7; MSG_REGS]))) as usize
+    }
+
+    /// Call a receiver that never replies.
+    fn abandoned_caller(arg: usize) -> usize {
+        let ep = unsafe { selftest::obj_arg::<Endpoint>(arg) };
+        (ep.call(Msg::new(OP_ECHO, [0; MSG_REGS])) == Err(IpcError::Closed)) as usize
+    }
+
+    /// Wait on an endpoint that is released under us.
+    fn orphan_receiver(arg: usize) -> usize {
+        let ep = unsafe { selftest::obj_arg::<Endpoint>(arg) };
+        (ep.recv() == Err(IpcError::Closed)) as usize
+    }
+
+    /// Start `entry` and wait until it is queued on its endpoint.
+    fn spawn_queued(
+        name: &'static str,
+        entry: fn(usize) -> usize,
+        arg: usize,
+        state: IpcState,
+    ) -> Option<sched::ThreadId> {
+        let t = sched::spawn(name, entry, arg).ok()?;
+        while THREADS[t.slot()].state() != state {
+            sched::yield_now();
+        }
+        Some(t)
+    }
+
+    let Some(ep) = Endpoint::create("ipc-test") else {
+        return false;
+    };
+    let arg = selftest::arg(&*ep);
+    let here = crate::cpu::mask_of(crate::cpu::current());
+
+    // Sender first: it must be queued by the time the receiver arrives.
+    let Some(s) = spawn_queued("ipc-sender", lone_sender, arg, IpcState::Sending) else {
+        return false;
+    };
+    let queued_ok = selftest::run_threads(
+        crate::cpu::ALL_CPUS,
+        [TestThread::new("ipc-receiver", lone_receiver, arg, 1)],
+    ) && sched::join(s) == Some(1);
+
+    let echo_ok = selftest::run_threads(
+        here,
+        [
+            TestThread::new("ipc-server", server, arg, NOTES as usize),
+            TestThread::new("ipc-client", client, arg, 1),
+        ],
+    );
+
+    let min = BENCH_MIN.load(Ordering::Relaxed);
+    let avg = BENCH_TOTAL.load(Ordering::Relaxed) / ROUND_TRIPS;
+    crate::klogln!(
+        "[ipc] call round trip: min {} ticks ({} ns), avg {} ticks ({} ns)",
+        min,
+        crate::time::ticks_to_ns(min),
+        avg,
+        crate::time::ticks_to_ns(avg)
+    );
+
+    // Receiving again without replying fails the first caller.
+    let Some(c) = spawn_queued("ipc-caller", abandoned_caller, arg, IpcState::Calling) else {
+        return false;
+    };
+    let Some(s) = spawn_queued("ipc-sender", lone_sender, arg, IpcState::Sending) else {
+        return false;
+    };
+    let abandoned_ok = ep.recv().is_ok_and(|m| m.tag == OP_ECHO)
+        && ep.recv() == Ok(Msg::new(OP_NOTE, [7; MSG_REGS]))
+        && sched::join(c) == Some(1)
+        && sched::join(s) == Some(1);
+
+    let Some(table) = crate::obj::alloc_table() else {
+        return false;
+    };
+    let h = table
+        .insert(ep.into_ref(), Rights::READ)
+        .unwrap_or(Handle::INVALID);
+    let denied = Some(IpcError::Handle(HandleError::AccessDenied));
+    let rights_ok = send(table, h, Msg::default()).err() == denied
+        && call(table, h, Msg::default()).err() == denied;
+    crate::obj::free_table(table);
+
+    // Releasing the endpoint takes the queued receiver off it.
+    let Some(orphan) = Endpoint::create("ipc-orphan") else {
+        return false;
+    };
+    let arg = selftest::arg(&*orphan);
+    let Some(r) = spawn_queued("ipc-orphan", orphan_receiver, arg, IpcState::Receiving) else {
+        return false;
+    };
+    drop(orphan);
+    let closed_ok = sched::join(r) == Some(1);
+
+    queued_ok && echo_ok && abandoned_ok && rights_ok && closed_ok
 }
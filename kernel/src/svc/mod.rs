@@ -1,3 +1,5 @@
+pub mod channel;
 pub mod ipc;
+pub mod notification;
 pub mod sched;
 pub mod vm;
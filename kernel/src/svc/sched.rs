@@ -1,4 +0,0 @@
-pub fn init<_T>(_boot: &_T) {
-    // MVP: single-core idle, then add runqueue
-    // Later: SMP, priorities, time-accounting
-}
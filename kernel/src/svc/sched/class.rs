@@ -0,0 +1,348 @@
+//! Scheduling classes and the per-CPU queues that order them.
+//!
+//! Deadline threads (see `deadline`) run first, earliest deadline first.
+//! Real-time threads (FIFO and round-robin) come next and run strictly by
+//! priority, 1..=`MAX_RT_PRIO`, higher first. Fair threads only run when
+//! no other class is ready and share the CPU by weighted virtual runtime;
+//! the weight comes from the nice value.
+
+use core::sync::atomic::Ordering;
+
+use super::thread::{self, NO_SLOT};
+
+pub const MAX_RT_PRIO: u8 = 99;
+
+/// Effective priority of deadline threads: above every real-time one.
+pub const DL_PRIO: u8 = MAX_RT_PRIO + 1;
+
+/// `Thread::rq_key` while the thread is in no queue.
+pub(super) const NOT_QUEUED: u8 = u8::MAX;
+/// `Thread::rq_key` of the fair queue; real-time queues use the priority
+/// and the deadline queue `DL_PRIO`.
+pub(super) const FAIR_KEY: u8 = 0;
+
+pub const NICE_MIN: i8 = -20;
+pub const NICE_MAX: i8 = 19;
+
+/// Weight of a nice-0 thread; virtual runtime advances at wall speed.
+pub(super) const NICE_0_WEIGHT: u64 = 1024;
+
+/// Each nice step is roughly a 10% CPU share change.
+const NICE_TO_WEIGHT: [u32; 40] = [
+    88761, 71755, 56483, 46273, 36291, 29154, 23254, 18705, 14949, 11916, 9548, 7620, 6100, 4904,
+    3906, 3121, 2501, 1991, 1586, 1277, 1024, 820, 655, 526, 423, 335, 272, 215, 172, 137, 110, 87,
+    70, 56, 45, 36, 29, 23, 18, 15,
+];
+
+#[repr(u8)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Policy {
+    /// Fair share by nice value.
+    Fair = 0,
+    /// Real-time, runs until it blocks or yields.
+    Fifo = 1,
+    /// Real-time, time-sliced among equal priorities.
+    RoundRobin = 2,
+    /// Earliest deadline first, with a reserved runtime per period.
+    Deadline = 3,
+}
+
+impl Policy {
+    pub(super) fn from_u8(v: u8) -> Self {
+        match v {
+            1 => Policy::Fifo,
+            2 => Policy::RoundRobin,
+            3 => Policy::Deadline,
+            _ => Policy::Fair,
+        }
+    }
+
+    /// FIFO or round-robin.
+    pub fn is_realtime(self) -> bool {
+        matches!(self, Policy::Fifo | Policy::RoundRobin)
+    }
+
+    pub fn is_deadline(self) -> bool {
+        self == Policy::Deadline
+    }
+}
+
+/// Scheduling attributes of a thread.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SchedAttr {
+    pub policy: Policy,
+    /// Real-time priority, 1..=`MAX_RT_PRIO`; ignored for `Fair`.
+    pub priority: u8,
+    /// `NICE_MIN..=NICE_MAX`; ignored for other policies.
+    pub nice: i8,
+    /// CPU time reserved per period (`Deadline` only).
+    pub runtime_ns: u64,
+    /// Relative deadline within each period (`Deadline` only).
+    pub deadline_ns: u64,
+    /// Activation period (`Deadline` only).
+    pub period_ns: u64,
+}
+
+impl SchedAttr {
+    pub const fn fair(nice: i8) -> Self {
+        Self {
+            policy: Policy::Fair,
+            priority: 0,
+            nice,
+            runtime_ns: 0,
+            deadline_ns: 0,
+            period_ns: 0,
+        }
+    }
+
+    pub const fn fifo(priority: u8) -> Self {
+        Self {
+            policy: Policy::Fifo,
+            priority,
+            nice: 0,
+            runtime_ns: 0,
+            deadline_ns: 0,
+            period_ns: 0,
+        }
+    }
+
+    pub const fn round_robin(priority: u8) -> Self {
+        Self {
+            policy: Policy::RoundRobin,
+            priority,
+            nice: 0,
+            runtime_ns: 0,
+            deadline_ns: 0,
+            period_ns: 0,
+        }
+    }
+
+    /// Reserve `runtime_ns` of every `period_ns`, to be used within
+    /// `deadline_ns` of the period start.
+    pub const fn deadline(runtime_ns: u64, deadline_ns: u64, period_ns: u64) -> Self {
+        Self {
+            policy: Policy::Deadline,
+            priority: 0,
+            nice: 0,
+            runtime_ns,
+            deadline_ns,
+            period_ns,
+        }
+    }
+
+    pub fn is_valid(&self) -> bool {
+        match self.policy {
+            Policy::Fair => (NICE_MIN..=NICE_MAX).contains(&self.nice),
+            Policy::Fifo | Policy::RoundRobin => (1..=MAX_RT_PRIO).contains(&self.priority),
+            Policy::Deadline => {
+                self.runtime_ns >= super::deadline::MIN_RUNTIME_NS
+                    && self.runtime_ns <= self.deadline_ns
+                    && self.deadline_ns <= self.period_ns
+            }
+        }
+    }
+}
+
+impl Default for SchedAttr {
+    fn default() -> Self {
+        Self::fair(0)
+    }
+}
+
+pub(super) fn weight(nice: i8) -> u64 {
+    let nice = nice.clamp(NICE_MIN, NICE_MAX);
+    NICE_TO_WEIGHT[(nice - NICE_MIN) as usize] as u64
+}
+
+/// FIFO of ready threads, linked through `Thread::next`.
+pub(super) struct RunQueue {
+    head: usize,
+    tail: usize,
+}
+
+impl RunQueue {
+    const fn new() -> Self {
+        Self {
+            head: NO_SLOT,
+            tail: NO_SLOT,
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.head == NO_SLOT
+    }
+
+    fn push_back(&mut self, slot: usize) {
+        thread::get(slot).next.store(NO_SLOT, Ordering::Relaxed);
+        if self.tail == NO_SLOT {
+            self.head = slot;
+        } else {
+            thread::get(self.tail).next.store(slot, Ordering::Relaxed);
+        }
+        self.tail = slot;
+    }
+
+    fn push_front(&mut self, slot: usize) {
+        thread::get(slot).next.store(self.head, Ordering::Relaxed);
+        if self.tail == NO_SLOT {
+            self.tail = slot;
+        }
+        self.head = slot;
+    }
+
+    fn remove(&mut self, slot: usize) -> bool {
+        let mut prev = NO_SLOT;
+        let mut cur = self.head;
+        while cur != NO_SLOT {
+            let next = thread::get(cur).next.load(Ordering::Relaxed);
+            if cur == slot {
+                if prev == NO_SLOT {
+                    self.head = next;
+                } else {
+                    thread::get(prev).next.store(next, Ordering::Relaxed);
+                }
+                if self.tail == slot {
+                    self.tail = prev;
+                }
+                return true;
+            }
+            prev = cur;
+            cur = next;
+        }
+        false
+    }
+
+    fn iter(&self) -> impl Iterator<Item = usize> {
+        let mut cur = self.head;
+        core::iter::from_fn(move || {
+            if cur == NO_SLOT {
+                return None;
+            }
+            let slot = cur;
+            cur = thread::get(slot).next.load(Ordering::Relaxed);
+            Some(slot)
+        })
+    }
+}
+
+/// All ready threads of one CPU, by class.
+pub(super) struct ClassQueues {
+    /// Deadline threads; picked by earliest absolute deadline.
+    dl: RunQueue,
+    /// Index = real-time priority; index 0 is unused.
+    rt: [RunQueue; MAX_RT_PRIO as usize + 1],
+    /// Bit `p` set while `rt[p]` is non-empty.
+    rt_bitmap: u128,
+    /// Fair threads; picked by lowest virtual runtime.
+    fair: RunQueue,
+    /// Floor for the virtual runtime of threads joining the fair queue.
+    pub(super) min_vruntime: u64,
+    pub(super) len: usize,
+}
+
+impl ClassQueues {
+    pub(super) const fn new() -> Self {
+        Self {
+            dl: RunQueue::new(),
+            rt: [const { RunQueue::new() }; MAX_RT_PRIO as usize + 1],
+            rt_bitmap: 0,
+            fair: RunQueue::new(),
+            min_vruntime: 0,
+            len: 0,
+        }
+    }
+
+    /// Queue `slot` under its current effective priority. `front` puts it
+    /// ahead of its equals (a preempted FIFO thread keeps its place).
+    pub(super) fn enqueue(&mut self, slot: usize, front: bool) {
+        let t = thread::get(slot);
+        if t.rq_key.load(Ordering::Relaxed) != NOT_QUEUED {
+            return;
+        }
+        // A thread boosted to `DL_PRIO` by inheritance has no deadline of
+        // its own; it runs as the highest real-time priority.
+        let key = if t.policy().is_deadline() {
+            DL_PRIO
+        } else {
+            t.effective_prio().min(MAX_RT_PRIO)
+        };
+        let q = match key {
+            DL_PRIO => &mut self.dl,
+            FAIR_KEY => &mut self.fair,
+            _ => {
+                self.rt_bitmap |= 1 << key;
+                &mut self.rt[key as usize]
+            }
+        };
+        if front {
+            q.push_front(slot);
+        } else {
+            q.push_back(slot);
+        }
+        t.rq_key.store(key, Ordering::Relaxed);
+        self.len += 1;
+    }
+
+    pub(super) fn dequeue(&mut self, slot: usize) -> bool {
+        let t = thread::get(slot);
+        let key = t.rq_key.load(Ordering::Relaxed);
+        if key == NOT_QUEUED {
+            return false;
+        }
+        match key {
+            DL_PRIO => {
+                self.dl.remove(slot);
+            }
+            FAIR_KEY => {
+                self.fair.remove(slot);
+            }
+            _ => {
+                self.rt[key as usize].remove(slot);
+                if self.rt[key as usize].is_empty() {
+                    self.rt_bitmap &= !(1 << key);
+                }
+            }
+        }
+        t.rq_key.store(NOT_QUEUED, Ordering::Relaxed);
+        self.len -= 1;
+        true
+    }
+
+    /// The thread that should run next, left queued.
+    pub(super) fn peek(&self) -> Option<usize> {
+        if !self.dl.is_empty() {
+            self.dl
+                .iter()
+                .min_by_key(|&s| thread::get(s).dl.abs_deadline.load(Ordering::Relaxed))
+        } else if self.rt_bitmap != 0 {
+            let prio = 127 - self.rt_bitmap.leading_zeros() as usize;
+            self.rt[prio].iter().next()
+        } else {
+            self.fair
+                .iter()
+                .min_by_key(|&s| thread::get(s).vruntime.load(Ordering::Relaxed))
+        }
+    }
+
+    /// Every queued thread: fair first, then real-time from the highest
+    /// priority, then deadline.
+    pub(super) fn iter(&self) -> impl Iterator<Item = usize> + '_ {
+        let rt = (1..=MAX_RT_PRIO as usize)
+            .rev()
+            .filter(move |&p| self.rt_bitmap & (1 << p) != 0)
+            .flat_map(move |p| self.rt[p].iter());
+        self.fair.iter().chain(rt).chain(self.dl.iter())
+    }
+
+    /// Remove and return the thread that should run next.
+    pub(super) fn pick(&mut self) -> Option<usize> {
+        let slot = self.peek()?;
+        let t = thread::get(slot);
+        if t.rq_key.load(Ordering::Relaxed) == FAIR_KEY {
+            let v = t.vruntime.load(Ordering::Relaxed);
+            self.min_vruntime = self.min_vruntime.max(v);
+        }
+        self.dequeue(slot);
+        Some(slot)
+    }
+}
@@ -0,0 +1,230 @@
+//! Earliest-deadline-first class with admission control.
+//!
+//! A deadline thread declares (runtime, deadline, period): within every
+//! period it needs up to `runtime` of CPU, finished within `deadline` of
+//! the period start. Ready deadline threads run before every other class,
+//! earliest absolute deadline first.
+//!
+//! Each thread is a constant bandwidth server: its budget is charged as
+//! it runs, and a thread that uses it up before its period ends is
+//! throttled until the next period and counted as an overrun. Admission
+//! keeps the summed density (runtime / deadline) of a CPU's deadline
+//! threads under `BW_LIMIT`, which makes every admitted set schedulable
+//! on that CPU with room left for the other classes.
+
+use core::sync::atomic::{AtomicBool, AtomicI64, AtomicU64, Ordering};
+
+use super::class::SchedAttr;
+use super::thread::{self, MAX_THREADS, Thread, ThreadId};
+use crate::cpu::MAX_CPUS;
+use crate::deferred::WorkItem;
+use crate::sync::SpinLock;
+use crate::time::timer::{self, Timer};
+
+/// Bandwidth fixed point: `BW_ONE` is one whole CPU.
+const BW_SHIFT: u32 = 20;
+const BW_ONE: u64 = 1 << BW_SHIFT;
+
+/// Share of each CPU deadline threads may reserve.
+const BW_LIMIT: u64 = BW_ONE * 95 / 100;
+
+/// Smallest runtime worth scheduling; shorter budgets are mostly overhead.
+pub const MIN_RUNTIME_NS: u64 = 10_000;
+
+/// Reserved bandwidth per CPU.
+static BW: [AtomicU64; MAX_CPUS] = [const { AtomicU64::new(0) }; MAX_CPUS];
+
+/// Per-thread server state.
+pub(super) struct DlState {
+    pub(super) runtime_ns: AtomicU64,
+    pub(super) deadline_ns: AtomicU64,
+    pub(super) period_ns: AtomicU64,
+    /// Start of the current period, `monotonic_ns` time.
+    pub(super) period_start: AtomicU64,
+    /// Absolute deadline of the current period.
+    pub(super) abs_deadline: AtomicU64,
+    /// Budget left in this period; negative after an overrun.
+    pub(super) remaining: AtomicI64,
+    /// Off the run queue until the replenishment timer fires.
+    pub(super) throttled: AtomicBool,
+    /// Blocked in `wait_next_period` rather than throttled.
+    pub(super) period_wait: AtomicBool,
+    pub(super) overruns: AtomicU64,
+    /// An overrun the handler has not been told about yet.
+    notify: AtomicBool,
+}
+
+impl DlState {
+    pub(super) const fn new() -> Self {
+        Self {
+            runtime_ns: AtomicU64::new(0),
+            deadline_ns: AtomicU64::new(0),
+            period_ns: AtomicU64::new(0),
+            period_start: AtomicU64::new(0),
+            abs_deadline: AtomicU64::new(0),
+            remaining: AtomicI64::new(0),
+            throttled: AtomicBool::new(false),
+            period_wait: AtomicBool::new(false),
+            overruns: AtomicU64::new(0),
+            notify: AtomicBool::new(false),
+        }
+    }
+
+    pub(super) fn reset(&self, attr: &SchedAttr) {
+        self.runtime_ns.store(attr.runtime_ns, Ordering::Relaxed);
+        self.deadline_ns.store(attr.deadline_ns, Ordering::Relaxed);
+        self.period_ns.store(attr.period_ns, Ordering::Relaxed);
+        self.period_start.store(0, Ordering::Relaxed);
+        self.abs_deadline.store(0, Ordering::Relaxed);
+        self.remaining.store(0, Ordering::Relaxed);
+        self.throttled.store(false, Ordering::Relaxed);
+        self.period_wait.store(false, Ordering::Relaxed);
+    }
+
+    /// Begin a fresh period at `start` with a full budget.
+    pub(super) fn new_period(&self, start: u64) {
+        let runtime = self.runtime_ns.load(Ordering::Relaxed);
+        self.period_start.store(start, Ordering::Relaxed);
+        self.abs_deadline.store(
+            start.saturating_add(self.deadline_ns.load(Ordering::Relaxed)),
+            Ordering::Relaxed,
+        );
+        self.remaining.store(runtime as i64, Ordering::Relaxed);
+    }
+
+    /// Start of the period after the current one.
+    pub(super) fn next_period_start(&self) -> u64 {
+        self.period_start
+            .load(Ordering::Relaxed)
+            .saturating_add(self.period_ns.load(Ordering::Relaxed))
+    }
+
+    /// Budget left, clamped at zero.
+    pub(super) fn budget_ns(&self) -> u64 {
+        self.remaining.load(Ordering::Relaxed).max(0) as u64
+    }
+}
+
+/// Density of `attr` in `BW_ONE` units.
+fn bandwidth(attr: &SchedAttr) -> u64 {
+    if !attr.policy.is_deadline() {
+        return 0;
+    }
+    (((attr.runtime_ns as u128) << BW_SHIFT) / attr.deadline_ns as u128) as u64
+}
+
+/// Swap the bandwidth `cpu` reserves for a thread from `old` to `new`
+/// attributes. Fails, changing nothing, if the CPU would go over
+/// `BW_LIMIT`.
+pub(super) fn admit(cpu: usize, old: &SchedAttr, new: &SchedAttr) -> bool {
+    let (old_bw, new_bw) = (bandwidth(old), bandwidth(new));
+    let mut bw = BW[cpu].load(Ordering::Relaxed);
+    loop {
+        let total = bw - old_bw + new_bw;
+        if new_bw > old_bw && total > BW_LIMIT {
+            return false;
+        }
+        match BW[cpu].compare_exchange_weak(bw, total, Ordering::AcqRel, Ordering::Relaxed) {
+            Ok(_) => return true,
+            Err(cur) => bw = cur,
+        }
+    }
+}
+
+/// Give back the bandwidth of a thread that is leaving the class.
+pub(super) fn release(cpu: usize, attr: &SchedAttr) {
+    BW[cpu].fetch_sub(bandwidth(attr), Ordering::AcqRel);
+}
+
+/// Carry a thread's reservation along when it moves CPU. This may take
+/// `to` past `BW_LIMIT`; moves only happen when affinity or offlining
+/// leaves no choice.
+pub(super) fn transfer(from: usize, to: usize, attr: &SchedAttr) {
+    let bw = bandwidth(attr);
+    BW[from].fetch_sub(bw, Ordering::AcqRel);
+    BW[to].fetch_add(bw, Ordering::AcqRel);
+}
+
+/// Reserved share of `cpu`, in percent.
+pub fn utilization_percent(cpu: usize) -> u64 {
+    BW[cpu].load(Ordering::Relaxed) * 100 / BW_ONE
+}
+
+/// Constant-bandwidth-server wakeup rule: keep the current period only if
+/// the leftover budget fits before its deadline at the reserved rate;
+/// otherwise start a new period now.
+pub(super) fn on_wakeup(t: &Thread, now: u64) {
+    let dl = &t.dl;
+    if dl.throttled.load(Ordering::Relaxed) {
+        return;
+    }
+    let abs = dl.abs_deadline.load(Ordering::Relaxed);
+    let remaining = dl.budget_ns() as u128;
+    let fits = abs > now
+        && remaining * dl.deadline_ns.load(Ordering::Relaxed) as u128
+            <= (abs - now) as u128 * dl.runtime_ns.load(Ordering::Relaxed) as u128;
+    if !fits {
+        dl.new_period(now);
+    }
+}
+
+/// Replenishment timers, one per thread slot.
+static REPLENISH: [Timer; MAX_THREADS] = replenish_timers();
+
+const fn replenish_timers() -> [Timer; MAX_THREADS] {
+    let mut timers = [const { Timer::new("dl-replenish", super::replenish, 0) }; MAX_THREADS];
+    let mut i = 0;
+    while i < MAX_THREADS {
+        timers[i] = Timer::new("dl-replenish", super::replenish, i);
+        i += 1;
+    }
+    timers
+}
+
+/// Arm `slot`'s replenishment for its next period.
+pub(super) fn arm_replenish(slot: usize) {
+    let t = thread::get(slot);
+    timer::start_at(&REPLENISH[slot], t.dl.next_period_start(), 0);
+}
+
+pub(super) fn cancel_replenish(slot: usize) {
+    timer::cancel(&REPLENISH[slot]);
+}
+
+static OVERRUN_HANDLER: SpinLock<fn(ThreadId)> = SpinLock::new(log_overrun);
+
+static OVERRUN_WORK: [WorkItem; MAX_CPUS] =
+    [const { WorkItem::new("dl-overrun", notify_overruns, 0) }; MAX_CPUS];
+
+fn log_overrun(id: ThreadId) {
+    if let Some(t) = thread::lookup(id) {
+        crate::klogln!(
+            "[sched] {} overran its runtime ({} total)",
+            t.name(),
+            t.dl.overruns.load(Ordering::Relaxed)
+        );
+    }
+}
+
+/// Install the function told about budget overruns and return the one it
+/// replaces. It runs from deferred work, never from the scheduler itself.
+pub fn set_overrun_handler(f: fn(ThreadId)) -> fn(ThreadId) {
+    core::mem::replace(&mut *OVERRUN_HANDLER.lock(), f)
+}
+
+/// Record that `slot` exhausted its budget and signal the handler.
+pub(super) fn overrun(slot: usize) {
+    let dl = &thread::get(slot).dl;
+    dl.overruns.fetch_add(1, Ordering::Relaxed);
+    dl.notify.store(true, Ordering::Release);
+    crate::deferred::queue(&OVERRUN_WORK[crate::cpu::current()]);
+}
+
+fn notify_overruns(_: usize) {
+    let handler = *OVERRUN_HANDLER.lock();
+    for slot in 0..MAX_THREADS {
+        if thread::get(slot).dl.notify.swap(false, Ordering::AcqRel) {
+            handler(thread::id_of(slot));
+        }
+    }
+}
@@ -0,0 +1,1311 @@
+//! Kernel threads and the scheduler.
+//!
+//! Preemptive and class-based: each CPU owns a set of run queues (see
+//! `class`) and an idle thread that is never queued and runs only when
+//! nothing else is ready. Deadline threads run before real-time ones, and
+//! real-time threads before fair ones. A per-CPU slice timer sets
+//! `need_resched` when a round-robin or fair thread's quantum, or a
+//! deadline thread's budget, is used up, and waking a thread that
+//! outranks the running one sets it too; the flag is honoured on
+//! interrupt return unless preemption is disabled. Every switch charges
+//! the outgoing thread's CPU time, read from `hal::time::now_ticks`.
+//!
+//! Threads are placed on a CPU when they are spawned or woken, within
+//! their affinity mask, preferring an idle CPU; a remote CPU that needs
+//! to reschedule is sent a reschedule IPI. Idle CPUs and a periodic
+//! balancer pull ready threads from the busiest CPU (see `smp`).
+
+use core::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicUsize, Ordering};
+
+use crate::sync::SpinLock;
+
+use crate::cpu::{self, ALL_CPUS, CpuMask};
+use crate::time::timer::{self, Timer};
+
+pub mod class;
+pub mod deadline;
+pub mod smp;
+pub mod thread;
+
+pub use class::{MAX_RT_PRIO, Policy, SchedAttr};
+pub use deadline::set_overrun_handler;
+pub use smp::{affinity, cpu_offline, cpu_online, set_affinity};
+pub use thread::{ThreadId, ThreadObject, ThreadState};
+
+use class::{ClassQueues, DL_PRIO, NICE_0_WEIGHT, NOT_QUEUED};
+use thread::{BOOT_SLOT, MAX_THREADS, NO_SLOT};
+
+/// `Thread::joiner` once the thread has fully exited.
+const JOIN_CLOSED: usize = usize::MAX - 1;
+
+/// Why the current thread is giving up the CPU.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Switch {
+    /// Still runnable: goes to the back of its queue.
+    Yield,
+    /// Still runnable, but forced off the CPU by `need_resched`.
+    Preempt,
+    /// Already marked Blocked by `prepare_block`; a waker requeues it.
+    Block,
+    /// Never runs again.
+    Exit,
+}
+
+/// Default time slice.
+const DEFAULT_QUANTUM_NS: u64 = 10_000_000;
+
+static QUANTUM_NS: AtomicU64 = AtomicU64::new(DEFAULT_QUANTUM_NS);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SpawnError {
+    /// Every thread slot is in use.
+    NoSlots,
+    /// Priority, nice value or deadline parameters out of range.
+    InvalidAttr,
+    /// Admitting the deadline thread would overcommit the CPU.
+    Admission,
+    /// The affinity mask has no online CPU.
+    NoCpu,
+}
+
+struct CpuRq {
+    rq: ClassQueues,
+    current: usize,
+    idle: usize,
+}
+
+/// Per-CPU scheduler flags and counters, readable without the rq lock.
+struct CpuSched {
+    need_resched: AtomicBool,
+    /// Slot of the running thread (mirror of `CpuRq::current`).
+    current: AtomicUsize,
+    /// Slot of this CPU's idle thread.
+    idle: AtomicUsize,
+    /// Thread that exited in the switch now completing; finished by
+    /// whoever runs next, once its stack is no longer in use.
+    exited: AtomicUsize,
+    /// Thread switched away from in the switch now completing; its
+    /// `on_cpu` is cleared by whoever runs next.
+    prev: AtomicUsize,
+    /// `prev` may no longer run here and must be queued elsewhere.
+    push_prev: AtomicBool,
+    /// Queued threads (mirror of `ClassQueues::len`), for placement.
+    nr_ready: AtomicUsize,
+    slice: Timer,
+    /// The current slice ran out (as opposed to a higher-priority wakeup).
+    slice_expired: AtomicBool,
+    /// `now_ticks` when the current thread was switched in.
+    switched_in: AtomicU64,
+    switches: AtomicU64,
+    preemptions: AtomicU64,
+    migrations_in: AtomicU64,
+    migrations_out: AtomicU64,
+    balance_pulls: AtomicU64,
+    balance: Timer,
+}
+
+impl CpuSched {
+    const fn new() -> Self {
+        Self {
+            need_resched: AtomicBool::new(false),
+            current: AtomicUsize::new(NO_SLOT),
+            idle: AtomicUsize::new(NO_SLOT),
+            exited: AtomicUsize::new(NO_SLOT),
+            prev: AtomicUsize::new(NO_SLOT),
+            push_prev: AtomicBool::new(false),
+            nr_ready: AtomicUsize::new(0),
+            slice: Timer::new("sched-slice", on_slice_expired, 0),
+            slice_expired: AtomicBool::new(false),
+            switched_in: AtomicU64::new(0),
+            switches: AtomicU64::new(0),
+            preemptions: AtomicU64::new(0),
+            migrations_in: AtomicU64::new(0),
+            migrations_out: AtomicU64::new(0),
+            balance_pulls: AtomicU64::new(0),
+            balance: Timer::new("sched-balance", on_balance, 0),
+        }
+    }
+}
+
+/// The scheduler's share of a CPU's per-CPU area (`cpu::PerCpu`).
+pub(crate) struct CpuState {
+    rq: SpinLock<CpuRq>,
+    sched: CpuSched,
+}
+
+impl CpuState {
+    pub(crate) const fn new() -> Self {
+        Self {
+            rq: SpinLock::new(CpuRq {
+                rq: ClassQueues::new(),
+                current: NO_SLOT,
+                idle: NO_SLOT,
+            }),
+            sched: CpuSched::new(),
+        }
+    }
+}
+
+#[inline(always)]
+fn runqueue(cpu: usize) -> &'static SpinLock<CpuRq> {
+    &cpu::of(cpu).sched.rq
+}
+
+#[inline(always)]
+fn cpu_sched(cpu: usize) -> &'static CpuSched {
+    &cpu::of(cpu).sched.sched
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SchedStats {
+    /// Context switches on this CPU.
+    pub switches: u64,
+    /// Switches forced by slice expiry.
+    pub preemptions: u64,
+    /// Threads waiting in the run queues.
+    pub ready: usize,
+    /// CPU time spent in the idle thread, in ns.
+    pub idle_ns: u64,
+    /// Threads moved onto this CPU.
+    pub migrations_in: u64,
+    /// Threads moved off this CPU.
+    pub migrations_out: u64,
+    /// Balancing passes that pulled work onto this CPU.
+    pub balance_pulls: u64,
+}
+
+/// Adopt the boot context as CPU 0's idle thread.
+pub fn init() {
+    init_cpu();
+}
+
+/// Adopt the calling context as the current CPU's idle thread.
+pub fn init_cpu() {
+    let cpu = cpu::current();
+    let slot = if cpu == 0 {
+        Some(BOOT_SLOT)
+    } else {
+        (0..MAX_THREADS).find(|&i| {
+            i != BOOT_SLOT
+                && thread::get(i)
+                    .state
+                    .compare_exchange(
+                        ThreadState::Free as u8,
+                        ThreadState::Running as u8,
+                        Ordering::AcqRel,
+                        Ordering::Relaxed,
+                    )
+                    .is_ok()
+        })
+    };
+    let Some(slot) = slot else {
+        panic!("no thread slot for cpu{} idle", cpu);
+    };
+
+    let t = thread::get(slot);
+    unsafe {
+        *t.name.get() = "idle";
+    }
+    t.cpu.store(cpu, Ordering::Relaxed);
+    t.affinity.store(cpu::mask_of(cpu), Ordering::Relaxed);
+    t.on_cpu.store(true, Ordering::Relaxed);
+    t.generation.fetch_add(1, Ordering::AcqRel);
+    t.set_state(ThreadState::Running);
+
+    let mut rq = runqueue(cpu).lock();
+    rq.idle = slot;
+    rq.current = slot;
+    cpu_sched(cpu)
+        .switched_in
+        .store(hal::time::now_ticks(), Ordering::Relaxed);
+    cpu_sched(cpu).current.store(slot, Ordering::Relaxed);
+    cpu_sched(cpu).idle.store(slot, Ordering::Relaxed);
+    drop(rq);
+    cpu::set_online(cpu, true);
+    timer::start_periodic(&cpu_sched(cpu).balance, smp::BALANCE_INTERVAL_NS);
+}
+
+/// Set the round-robin and fair time slice.
+pub fn set_quantum_ns(ns: u64) {
+    QUANTUM_NS.store(ns.max(1), Ordering::Relaxed);
+}
+
+pub fn quantum_ns() -> u64 {
+    QUANTUM_NS.load(Ordering::Relaxed)
+}
+
+/// Start a fair nice-0 kernel thread running `entry(arg)`. Its return
+/// value becomes the exit code reported by `join`.
+pub fn spawn(
+    name: &'static str,
+    entry: fn(usize) -> usize,
+    arg: usize,
+) -> Result<ThreadId, SpawnError> {
+    spawn_with(name, entry, arg, SchedAttr::default())
+}
+
+/// `spawn` with explicit scheduling attributes.
+pub fn spawn_with(
+    name: &'static str,
+    entry: fn(usize) -> usize,
+    arg: usize,
+    attr: SchedAttr,
+) -> Result<ThreadId, SpawnError> {
+    spawn_on_cpus(name, entry, arg, attr, ALL_CPUS)
+}
+
+/// `spawn_with`, limited to the CPUs in `affinity`. A deadline thread
+/// starts on the calling CPU when allowed, and is admitted against the
+/// CPU it starts on.
+pub fn spawn_on_cpus(
+    name: &'static str,
+    entry: fn(usize) -> usize,
+    arg: usize,
+    attr: SchedAttr,
+    affinity: CpuMask,
+) -> Result<ThreadId, SpawnError> {
+    if !attr.is_valid() {
+        return Err(SpawnError::InvalidAttr);
+    }
+    if affinity & cpu::online_mask() == 0 {
+        return Err(SpawnError::NoCpu);
+    }
+    let slot = (0..MAX_THREADS)
+        .find(|&i| {
+            thread::get(i)
+                .state
+                .compare_exchange(
+                    ThreadState::Free as u8,
+                    ThreadState::Blocked as u8,
+                    Ordering::AcqRel,
+                    Ordering::Relaxed,
+                )
+                .is_ok()
+        })
+        .ok_or(SpawnError::NoSlots)?;
+
+    let t = thread::get(slot);
+    unsafe {
+        *t.name.get() = name;
+        *t.entry.get() = entry;
+        hal::context::init(
+            &mut *t.ctx.get(),
+            thread::stack_top(slot),
+            thread_start,
+            slot,
+        );
+    }
+    t.arg.store(arg, Ordering::Relaxed);
+    t.exit_code.store(0, Ordering::Relaxed);
+    t.joiner.store(NO_SLOT, Ordering::Relaxed);
+    t.set_attr(attr);
+    t.pi_prio.store(0, Ordering::Relaxed);
+    t.rq_key.store(NOT_QUEUED, Ordering::Relaxed);
+    t.cpu_ns.store(0, Ordering::Relaxed);
+    t.preempt_count.store(0, Ordering::Relaxed);
+    t.dl.overruns.store(0, Ordering::Relaxed);
+    t.affinity.store(affinity, Ordering::Relaxed);
+    t.on_cpu.store(false, Ordering::Relaxed);
+    let cpu = smp::select_cpu(slot, cpu::current());
+    if !deadline::admit(cpu, &SchedAttr::default(), &attr) {
+        t.set_state(ThreadState::Free);
+        return Err(SpawnError::Admission);
+    }
+    t.cpu.store(cpu, Ordering::Relaxed);
+    with_rq(cpu, |rq| {
+        t.vruntime.store(rq.rq.min_vruntime, Ordering::Relaxed)
+    });
+    t.generation.fetch_add(1, Ordering::AcqRel);
+    let id = thread::id_of(slot);
+    wake_slot(slot);
+    Ok(id)
+}
+
+extern "C" fn thread_start(slot: usize) -> ! {
+    // Switched to with interrupts disabled by `schedule`.
+    finish_switch();
+    crate::arch::enable_interrupts();
+    let t = thread::get(slot);
+    let entry = unsafe { *t.entry.get() };
+    let code = entry(t.arg.load(Ordering::Relaxed));
+    exit(code)
+}
+
+#[inline(always)]
+pub(crate) fn current_slot() -> usize {
+    cpu_sched(cpu::current()).current.load(Ordering::Relaxed)
+}
+
+#[inline(always)]
+fn is_idle(slot: usize) -> bool {
+    slot == cpu_sched(cpu::current()).idle.load(Ordering::Relaxed)
+}
+
+/// Id of the running thread.
+pub fn current() -> ThreadId {
+    thread::id_of(current_slot())
+}
+
+/// Give up the CPU to the next ready thread, if any.
+pub fn yield_now() {
+    schedule(Switch::Yield);
+}
+
+/// First half of blocking: mark the current thread Blocked. Publish it to
+/// whoever will `wake` it, then call `block`. A wake that lands in between
+/// makes `block` return straight away. Preemption waits until `block` or
+/// `cancel_block`.
+pub fn prepare_block() {
+    let me = current_slot();
+    assert!(!is_idle(me), "idle thread cannot block");
+    thread::get(me).set_state(ThreadState::Blocked);
+}
+
+/// Undo `prepare_block` when the caller decides not to sleep after all.
+pub fn cancel_block() {
+    let _ = thread::get(current_slot()).state.compare_exchange(
+        ThreadState::Blocked as u8,
+        ThreadState::Running as u8,
+        Ordering::AcqRel,
+        Ordering::Relaxed,
+    );
+    // Take a preemption that `preempt` held off while we were Blocked.
+    if cpu_sched(cpu::current())
+        .need_resched
+        .load(Ordering::Relaxed)
+        && preemptible()
+        && crate::arch::interrupts_enabled()
+    {
+        preempt();
+    }
+}
+
+/// Second half of blocking: sleep until woken.
+pub fn block() {
+    schedule(Switch::Block);
+}
+
+/// Timers ending `block_until`, one per thread slot.
+static TIMEOUTS: [Timer; MAX_THREADS] = timeout_timers();
+
+const fn timeout_timers() -> [Timer; MAX_THREADS] {
+    let mut timers = [const { Timer::new("sched-timeout", on_timeout, 0) }; MAX_THREADS];
+    let mut i = 0;
+    while i < MAX_THREADS {
+        timers[i] = Timer::new("sched-timeout", on_timeout, i);
+        i += 1;
+    }
+    timers
+}
+
+fn on_timeout(slot: usize) {
+    let t = thread::get(slot);
+    if t.state() == ThreadState::Blocked {
+        t.timed_out.store(true, Ordering::Relaxed);
+        wake_slot(slot);
+    }
+}
+
+/// `block`, but wake by `deadline_ns` on `monotonic_ns` at the latest.
+/// Returns false if the deadline rather than a wake ended the sleep.
+/// Without a running clock only a wake ends it.
+pub fn block_until(deadline_ns: u64) -> bool {
+    let me = current_slot();
+    let t = thread::get(me);
+    t.timed_out.store(false, Ordering::Relaxed);
+    timer::start_at(&TIMEOUTS[me], deadline_ns, 0);
+    block();
+    timer::cancel(&TIMEOUTS[me]);
+    !t.timed_out.swap(false, Ordering::Relaxed)
+}
+
+/// Terminate the calling thread with `code`.
+pub fn exit(code: usize) -> ! {
+    crate::arch::disable_interrupts();
+    let me = current_slot();
+    assert!(!is_idle(me), "idle thread cannot exit");
+    thread::get(me).exit_code.store(code, Ordering::Relaxed);
+    schedule(Switch::Exit);
+    unreachable!("exited thread resumed");
+}
+
+/// Wait for `id` to exit and return its exit code. Returns `None` if `id`
+/// is stale, is the caller, or is joined by someone else.
+pub fn join(id: ThreadId) -> Option<usize> {
+    let me = current_slot();
+    if id.slot() == me {
+        return None;
+    }
+    loop {
+        let t = thread::lookup(id)?;
+        if t.state() == ThreadState::Exited {
+            // Reap: the slot can be reused by the next spawn.
+            let code = t.exit_code.load(Ordering::Relaxed);
+            return t
+                .state
+                .compare_exchange(
+                    ThreadState::Exited as u8,
+                    ThreadState::Free as u8,
+                    Ordering::AcqRel,
+                    Ordering::Relaxed,
+                )
+                .ok()
+                .map(|_| code);
+        }
+        if is_idle(me) {
+            // Idle must stay runnable: poll instead of blocking.
+            yield_now();
+            continue;
+        }
+
+        prepare_block();
+        match t
+            .joiner
+            .compare_exchange(NO_SLOT, me, Ordering::AcqRel, Ordering::Acquire)
+        {
+            Ok(_) => block(),
+            Err(j) if j == me => block(),
+            Err(JOIN_CLOSED) => cancel_block(),
+            Err(_) => {
+                cancel_block();
+                return None;
+            }
+        }
+    }
+}
+
+/// Scheduling attributes of `id`.
+pub fn attr(id: ThreadId) -> Option<SchedAttr> {
+    thread::lookup(id).map(|t| t.attr())
+}
+
+/// Change the class and priority of `id`. Takes effect immediately; a
+/// thread that now outranks the running one preempts it. Returns false if
+/// `id` is stale, `attr` is out of range or a deadline thread fails
+/// admission.
+pub fn set_attr(id: ThreadId, attr: SchedAttr) -> bool {
+    if !attr.is_valid() {
+        return false;
+    }
+    let Some(t) = thread::lookup(id) else {
+        return false;
+    };
+    let slot = id.slot();
+    let (admitted, cpu) = with_thread_rq(slot, |cpu, rq| {
+        if !deadline::admit(cpu, &t.attr(), &attr) {
+            return (false, cpu);
+        }
+        let throttled = t.dl.throttled.load(Ordering::Relaxed);
+        let period_wait = t.dl.period_wait.load(Ordering::Relaxed);
+        let queued = rq.rq.dequeue(slot) || (throttled && t.state() == ThreadState::Ready);
+        t.set_attr(attr);
+        if attr.policy.is_deadline() {
+            t.dl.new_period(crate::time::monotonic_ns());
+        }
+        if period_wait {
+            // Its replenishment is about to be cancelled.
+            make_ready(cpu, rq, slot, false);
+        } else if queued {
+            rq.rq.enqueue(slot, false);
+        }
+        check_preempt(cpu, rq);
+        (true, cpu)
+    });
+    if !admitted {
+        return false;
+    }
+    deadline::cancel_replenish(slot);
+    kick(cpu);
+    preempt_disable();
+    preempt_enable();
+    true
+}
+
+/// Budget overruns `id` has had as a deadline thread.
+pub fn overruns(id: ThreadId) -> Option<u64> {
+    thread::lookup(id).map(|t| t.dl.overruns.load(Ordering::Relaxed))
+}
+
+/// Give up the rest of this period's budget and sleep until the next
+/// period starts. Only meaningful for deadline threads; others yield.
+pub fn wait_next_period() {
+    let me = current_slot();
+    let t = thread::get(me);
+    if !t.policy().is_deadline() {
+        yield_now();
+        return;
+    }
+    prepare_block();
+    t.dl.period_wait.store(true, Ordering::Relaxed);
+    deadline::arm_replenish(me);
+    block();
+}
+
+/// Replenishment timer: start `slot`'s next period and let it run again.
+fn replenish(slot: usize) {
+    let t = thread::get(slot);
+    if !t.policy().is_deadline() {
+        return;
+    }
+    let cpu = with_thread_rq(slot, |cpu, rq| {
+        let dl = &t.dl;
+        let now = crate::time::monotonic_ns();
+        // Keep the cadence unless the next period's deadline already
+        // passed.
+        let next = dl.next_period_start();
+        let deadline = next.saturating_add(dl.deadline_ns.load(Ordering::Relaxed));
+        dl.new_period(if deadline > now { next } else { now });
+        if dl.throttled.swap(false, Ordering::Relaxed) {
+            if t.state() == ThreadState::Ready {
+                rq.rq.enqueue(slot, false);
+                check_preempt(cpu, rq);
+            }
+        } else if dl.period_wait.swap(false, Ordering::Relaxed) {
+            make_ready(cpu, rq, slot, false);
+        }
+        cpu
+    });
+    kick(cpu);
+}
+
+/// CPU time `id` has consumed so far, in ns.
+pub fn cpu_time_ns(id: ThreadId) -> Option<u64> {
+    let t = thread::lookup(id)?;
+    let cpu = t.cpu.load(Ordering::Relaxed);
+    let c = cpu_sched(cpu);
+    let irq = crate::arch::irq_save();
+    let mut ns = t.cpu_ns.load(Ordering::Relaxed);
+    if cpu == cpu::current() && c.current.load(Ordering::Relaxed) == id.slot() {
+        let since = hal::time::now_ticks().saturating_sub(c.switched_in.load(Ordering::Relaxed));
+        ns += crate::time::ticks_to_ns(since);
+    }
+    crate::arch::irq_restore(irq);
+    Some(ns)
+}
+
+/// Effective priority of `slot`: 0 for fair, else the real-time priority
+/// including any inherited one.
+pub(crate) fn prio_of(slot: usize) -> u8 {
+    thread::get(slot).effective_prio()
+}
+
+/// Priority `slot` currently inherits through held mutexes.
+pub(crate) fn inherited_prio(slot: usize) -> u8 {
+    thread::get(slot).pi_prio.load(Ordering::Relaxed)
+}
+
+/// Set the priority `slot` inherits from mutex waiters (0 for none) and
+/// move it to the matching queue.
+pub(crate) fn set_inherited_prio(slot: usize, prio: u8) {
+    let t = thread::get(slot);
+    if t.pi_prio.load(Ordering::Relaxed) != prio {
+        requeue(slot, || t.pi_prio.store(prio, Ordering::Relaxed));
+    }
+}
+
+/// Apply `update` to `slot`'s priority under its rq lock, re-queueing it
+/// if it is ready and flagging a reschedule if the order changed.
+fn requeue(slot: usize, update: impl FnOnce()) {
+    let cpu = with_thread_rq(slot, |cpu, rq| {
+        let queued = rq.rq.dequeue(slot);
+        update();
+        if queued {
+            rq.rq.enqueue(slot, false);
+        }
+        check_preempt(cpu, rq);
+        cpu
+    });
+    kick(cpu);
+}
+
+/// Whether `a` should run instead of `b`.
+fn outranks(a: usize, b: usize) -> bool {
+    let (ta, tb) = (thread::get(a), thread::get(b));
+    let (pa, pb) = (ta.effective_prio(), tb.effective_prio());
+    if pa == DL_PRIO && pb == DL_PRIO && ta.policy().is_deadline() && tb.policy().is_deadline() {
+        return ta.dl.abs_deadline.load(Ordering::Relaxed)
+            < tb.dl.abs_deadline.load(Ordering::Relaxed);
+    }
+    pa > pb
+}
+
+/// Flag a reschedule on `cpu` if its best queued thread outranks the
+/// running one. Caller holds the rq lock.
+fn check_preempt(cpu: usize, rq: &CpuRq) {
+    let current = rq.current;
+    if rq
+        .rq
+        .peek()
+        .is_some_and(|next| current == rq.idle || outranks(next, current))
+    {
+        cpu_sched(cpu).need_resched.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Send `cpu` a reschedule IPI if it has been flagged to reschedule. The
+/// current CPU notices `need_resched` itself on interrupt return.
+fn kick(cpu: usize) {
+    if cpu != cpu::current() && cpu_sched(cpu).need_resched.load(Ordering::Relaxed) {
+        crate::smp::send_reschedule(cpu);
+    }
+}
+
+/// Mirror the queue length for lock-free placement decisions. Caller
+/// holds the rq lock.
+fn publish_load(cpu: usize, rq: &CpuRq) {
+    cpu_sched(cpu).nr_ready.store(rq.rq.len, Ordering::Relaxed);
+}
+
+fn with_rq<R>(cpu: usize, f: impl FnOnce(&mut CpuRq) -> R) -> R {
+    let irq = crate::arch::irq_save();
+    let mut rq = runqueue(cpu).lock();
+    let r = f(&mut rq);
+    publish_load(cpu, &rq);
+    drop(rq);
+    crate::arch::irq_restore(irq);
+    r
+}
+
+/// `with_rq` on the CPU `slot` belongs to, which cannot change while its
+/// lock is held.
+fn with_thread_rq<R>(slot: usize, f: impl FnOnce(usize, &mut CpuRq) -> R) -> R {
+    let t = thread::get(slot);
+    let irq = crate::arch::irq_save();
+    let r = loop {
+        let cpu = t.cpu.load(Ordering::Acquire);
+        let mut rq = runqueue(cpu).lock();
+        if t.cpu.load(Ordering::Relaxed) == cpu {
+            let r = f(cpu, &mut rq);
+            publish_load(cpu, &rq);
+            break r;
+        }
+    };
+    crate::arch::irq_restore(irq);
+    r
+}
+
+/// Make a blocked thread runnable, on the CPU `smp::select_cpu` picks.
+pub fn wake(id: ThreadId) -> bool {
+    match thread::lookup(id) {
+        Some(_) => wake_slot(id.slot()),
+        None => false,
+    }
+}
+
+pub(crate) fn wake_slot(slot: usize) -> bool {
+    let t = thread::get(slot);
+    let irq = crate::arch::irq_save();
+    let (woke, cpu) = loop {
+        let from = t.cpu.load(Ordering::Acquire);
+        // A thread still on its CPU (woken between `prepare_block` and
+        // `block`) stays there. One that is not cannot get back on
+        // without being woken, so the check below is stable.
+        let to = if t.on_cpu.load(Ordering::Acquire) {
+            from
+        } else {
+            smp::select_cpu(slot, from)
+        };
+        let mut pair = smp::lock_pair(from, to);
+        if t.cpu.load(Ordering::Relaxed) != from {
+            continue;
+        }
+        if t.state() != ThreadState::Blocked {
+            break (false, from);
+        }
+        let to = if t.on_cpu.load(Ordering::Acquire) {
+            from
+        } else {
+            to
+        };
+        smp::set_cpu(slot, from, to);
+        break (make_ready(to, pair.get(to), slot, true), to);
+    };
+    crate::arch::irq_restore(irq);
+    if woke {
+        kick(cpu);
+    }
+    woke
+}
+
+/// Blocked -> Ready on `cpu`, under its rq lock. `refresh` applies the deadline
+/// wakeup rule; the replenishment path has just started a period itself.
+fn make_ready(cpu: usize, rq: &mut CpuRq, slot: usize, refresh: bool) -> bool {
+    let t = thread::get(slot);
+    let woke = t
+        .state
+        .compare_exchange(
+            ThreadState::Blocked as u8,
+            ThreadState::Ready as u8,
+            Ordering::AcqRel,
+            Ordering::Relaxed,
+        )
+        .is_ok();
+    if woke {
+        // May be the current thread between `prepare_block` and `block`;
+        // `schedule` then simply picks it again.
+        on_wake(rq, t, refresh);
+        if !t.dl.throttled.load(Ordering::Relaxed) {
+            rq.rq.enqueue(slot, false);
+            let current = rq.current;
+            if current == rq.idle || outranks(slot, current) {
+                cpu_sched(cpu).need_resched.store(true, Ordering::Relaxed);
+            }
+        }
+    }
+    woke
+}
+
+/// Class bookkeeping for `t` becoming runnable on `rq`'s CPU.
+fn on_wake(rq: &CpuRq, t: &thread::Thread, refresh: bool) {
+    match t.policy() {
+        Policy::Fair => {
+            // Sleepers get at most one slice of credit.
+            let floor = rq.rq.min_vruntime.saturating_sub(quantum_ns());
+            t.vruntime.fetch_max(floor, Ordering::Relaxed);
+        }
+        Policy::Deadline if refresh => {
+            deadline::on_wakeup(t, crate::time::monotonic_ns());
+        }
+        _ => {}
+    }
+}
+
+/// Block the current thread and run `slot` in its place, skipping the
+/// run queues: the IPC fastpath, where `slot` is the blocked partner of
+/// a rendezvous the caller just completed. Falls back to waking `slot`
+/// and blocking when it cannot run here now (still switching out on
+/// another CPU, outside its affinity, or throttled). Call after
+/// `prepare_block`.
+pub(crate) fn block_handoff(slot: usize) {
+    let irq = crate::arch::irq_save();
+    let claimed = claim_handoff(slot);
+    if claimed {
+        schedule_to(Switch::Block, slot);
+    }
+    crate::arch::irq_restore(irq);
+    if !claimed {
+        wake_slot(slot);
+        block();
+    }
+}
+
+/// Take blocked `slot` for `block_handoff`: mark it Ready and move it to
+/// this CPU without queueing it. Interrupts are disabled.
+fn claim_handoff(slot: usize) -> bool {
+    let me = cpu::current();
+    let t = thread::get(slot);
+    loop {
+        let from = t.cpu.load(Ordering::Acquire);
+        // Off its CPU and Blocked, it cannot get back on without a wake,
+        // so these checks hold until the state changes below.
+        if t.on_cpu.load(Ordering::Acquire)
+            || !smp::runnable_on(slot, me)
+            || t.dl.throttled.load(Ordering::Relaxed)
+        {
+            return false;
+        }
+        let _pair = smp::lock_pair(from, me);
+        if t.cpu.load(Ordering::Relaxed) != from {
+            continue;
+        }
+        if t.state
+            .compare_exchange(
+                ThreadState::Blocked as u8,
+                ThreadState::Ready as u8,
+                Ordering::AcqRel,
+                Ordering::Relaxed,
+            )
+            .is_err()
+        {
+            return false;
+        }
+        smp::set_cpu(slot, from, me);
+        return true;
+    }
+}
+
+/// Whether any thread besides idle is waiting to run on this CPU.
+pub fn has_ready() -> bool {
+    with_rq(cpu::current(), |rq| rq.rq.len != 0)
+}
+
+/// The running thread's `preempt_count`, or `None` before the scheduler
+/// runs on this CPU. The count belongs to the thread, not the CPU, so it
+/// stays right if the thread migrates while it is enabled; interrupts are
+/// off only while the slot is read.
+#[inline(always)]
+fn preempt_count() -> Option<&'static AtomicU32> {
+    let irq = crate::arch::irq_save();
+    let slot = current_slot();
+    crate::arch::irq_restore(irq);
+    (slot != NO_SLOT).then(|| &thread::get(slot).preempt_count)
+}
+
+/// Enter a section that must not be preempted. Nests.
+#[inline(always)]
+pub fn preempt_disable() {
+    if let Some(count) = preempt_count() {
+        count.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Leave a `preempt_disable` section, rescheduling if a slice expired
+/// meanwhile and interrupts are on.
+#[inline(always)]
+pub fn preempt_enable() {
+    if preempt_count().is_some_and(|count| count.fetch_sub(1, Ordering::Relaxed) == 1)
+        && cpu_sched(cpu::current())
+            .need_resched
+            .load(Ordering::Relaxed)
+        && crate::arch::interrupts_enabled()
+    {
+        preempt();
+    }
+}
+
+/// Leave a `preempt_disable` section without checking for a reschedule.
+#[inline(always)]
+pub fn preempt_enable_no_resched() {
+    if let Some(count) = preempt_count() {
+        count.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+pub fn preemptible() -> bool {
+    preempt_count().is_none_or(|count| count.load(Ordering::Relaxed) == 0)
+}
+
+fn on_balance(_: usize) {
+    smp::balance(cpu::current());
+}
+
+fn on_slice_expired(_: usize) {
+    let c = cpu_sched(cpu::current());
+    c.slice_expired.store(true, Ordering::Relaxed);
+    c.need_resched.store(true, Ordering::Relaxed);
+}
+
+/// Interrupt-return hook: switch away if the slice expired or a
+/// higher-priority thread woke up.
+pub fn irq_exit() {
+    let cpu = cpu::current();
+    let c = cpu_sched(cpu);
+    if !c.need_resched.load(Ordering::Relaxed) || !preemptible() {
+        return;
+    }
+    // Idle restarts its tick and yields on its own once it wakes.
+    if c.current.load(Ordering::Relaxed) == c.idle.load(Ordering::Relaxed) {
+        return;
+    }
+    preempt();
+}
+
+fn preempt() {
+    // Between `prepare_block` and `block` the thread is Blocked but still
+    // running. Switching it out as preempted would requeue it as Ready,
+    // and the `block` it then returns to would leave it unqueued and
+    // unwakeable. Leave `need_resched` set: `block` switches away anyway
+    // and `cancel_block` preempts.
+    if thread::get(current_slot()).state() == ThreadState::Blocked {
+        return;
+    }
+    cpu_sched(cpu::current())
+        .preemptions
+        .fetch_add(1, Ordering::Relaxed);
+    schedule(Switch::Preempt);
+}
+
+/// Switch away from the current thread.
+fn schedule(why: Switch) {
+    schedule_to(why, NO_SLOT);
+}
+
+/// `schedule`, running `handoff` next if it is a slot taken by
+/// `claim_handoff`, unless a queued thread outranks it.
+fn schedule_to(why: Switch, handoff: usize) {
+    // A context switch is a quiescent state. Note it before taking the
+    // run queue lock; ending a grace period wakes its waiters.
+    crate::sync::rcu::quiescent();
+    let irq = crate::arch::irq_save();
+    let cpu = cpu::current();
+    let c = cpu_sched(cpu);
+    c.need_resched.store(false, Ordering::Relaxed);
+
+    let mut rq = runqueue(cpu).lock();
+    let prev = rq.current;
+    let idle = rq.idle;
+    let prev_t = thread::get(prev);
+    account(c, prev_t);
+    let slice_expired = c.slice_expired.swap(false, Ordering::Relaxed);
+
+    // A deadline thread that used up its budget sits out the rest of the
+    // period if it is still runnable.
+    let overran = why != Switch::Exit
+        && prev_t.policy().is_deadline()
+        && prev_t.dl.remaining.load(Ordering::Relaxed) <= 0;
+    let throttle = overran && why != Switch::Block;
+    if overran {
+        deadline::overrun(prev);
+    }
+    if throttle {
+        prev_t.dl.throttled.store(true, Ordering::Relaxed);
+        prev_t.set_state(ThreadState::Ready);
+    }
+
+    match why {
+        _ if throttle => {}
+        Switch::Yield | Switch::Preempt if prev != idle => {
+            // A real-time thread that lost the CPU to a higher priority
+            // keeps its place; one whose slice ran out goes to the back.
+            let front = why == Switch::Preempt
+                && match prev_t.policy() {
+                    Policy::Fifo => true,
+                    Policy::RoundRobin => !slice_expired,
+                    Policy::Fair | Policy::Deadline => false,
+                };
+            prev_t.set_state(ThreadState::Ready);
+            if smp::runnable_on(prev, cpu) {
+                rq.rq.enqueue(prev, front);
+            } else {
+                // Affinity changed or the CPU went offline: queue it
+                // elsewhere once it is off this CPU.
+                c.push_prev.store(true, Ordering::Relaxed);
+            }
+        }
+        Switch::Yield | Switch::Preempt => {}
+        // Left Blocked, or already Ready if a wake beat us here.
+        Switch::Block => {}
+        Switch::Exit => c.exited.store(prev, Ordering::Relaxed),
+    }
+    let next = if handoff == NO_SLOT {
+        rq.rq.pick().unwrap_or(idle)
+    } else {
+        on_wake(&rq, thread::get(handoff), true);
+        if rq.rq.peek().is_some_and(|q| outranks(q, handoff)) {
+            rq.rq.enqueue(handoff, false);
+            rq.rq.pick().unwrap_or(idle)
+        } else {
+            handoff
+        }
+    };
+    publish_load(cpu, &rq);
+    let next_t = thread::get(next);
+    next_t.set_state(ThreadState::Running);
+    next_t.on_cpu.store(true, Ordering::Relaxed);
+    let slice_ns = match next_t.policy() {
+        _ if next == idle => None,
+        Policy::Fifo => None,
+        Policy::RoundRobin | Policy::Fair => Some(quantum_ns()),
+        Policy::Deadline => Some(next_t.dl.budget_ns().max(1)),
+    };
+    if next == prev {
+        // Not reachable for a throttled thread: it was not queued.
+        drop(rq);
+        restart_slice(c, slice_ns);
+        crate::arch::irq_restore(irq);
+        return;
+    }
+
+    rq.current = next;
+    c.current.store(next, Ordering::Relaxed);
+    c.prev.store(prev, Ordering::Relaxed);
+    c.switches.fetch_add(1, Ordering::Relaxed);
+    drop(rq);
+    restart_slice(c, slice_ns);
+    if throttle {
+        deadline::arm_replenish(prev);
+    }
+
+    unsafe {
+        hal::context::switch(prev_t.ctx.get(), next_t.ctx.get());
+    }
+    finish_switch();
+    crate::arch::irq_restore(irq);
+}
+
+/// Runs on the incoming thread: release the outgoing one to other CPUs,
+/// retire a thread that exited in the switch and wake whoever is joining
+/// it.
+fn finish_switch() {
+    let cpu = cpu::current();
+    let c = cpu_sched(cpu);
+    let prev = c.prev.swap(NO_SLOT, Ordering::Relaxed);
+    if prev != NO_SLOT {
+        // Its context is saved; another CPU may resume it from here on.
+        thread::get(prev).on_cpu.store(false, Ordering::Release);
+        if c.push_prev.swap(false, Ordering::Relaxed) {
+            smp::push_away(prev, cpu);
+        }
+    }
+    let dead = c.exited.swap(NO_SLOT, Ordering::Relaxed);
+    if dead == NO_SLOT {
+        return;
+    }
+    let t = thread::get(dead);
+    if t.policy().is_deadline() {
+        deadline::cancel_replenish(dead);
+        deadline::release(t.cpu.load(Ordering::Relaxed), &t.attr());
+    }
+    t.set_state(ThreadState::Exited);
+    let joiner = t.joiner.swap(JOIN_CLOSED, Ordering::AcqRel);
+    if joiner != NO_SLOT {
+        wake_slot(joiner);
+    }
+}
+
+/// Charge the time since the last switch to `t` (the outgoing thread).
+fn account(c: &CpuSched, t: &thread::Thread) {
+    let now = hal::time::now_ticks();
+    let since = now.saturating_sub(c.switched_in.swap(now, Ordering::Relaxed));
+    let ns = crate::time::ticks_to_ns(since);
+    t.cpu_ns.fetch_add(ns, Ordering::Relaxed);
+    match t.policy() {
+        Policy::Fair => {
+            let weighted = ns * NICE_0_WEIGHT / class::weight(t.nice.load(Ordering::Relaxed));
+            t.vruntime.fetch_add(weighted, Ordering::Relaxed);
+        }
+        Policy::Deadline => {
+            t.dl.remaining.fetch_sub(ns as i64, Ordering::Relaxed);
+        }
+        _ => {}
+    }
+}
+
+/// Arm the slice timer for the incoming thread's quantum or budget, or
+/// stop it for threads that run unsliced (idle and FIFO).
+fn restart_slice(c: &'static CpuSched, slice_ns: Option<u64>) {
+    match slice_ns {
+        Some(ns) => {
+            timer::start(&c.slice, ns);
+        }
+        None => {
+            timer::cancel(&c.slice);
+        }
+    }
+}
+
+pub fn stats(cpu: usize) -> SchedStats {
+    let c = cpu_sched(cpu);
+    let ready = with_rq(cpu, |rq| rq.rq.len);
+    let idle = c.idle.load(Ordering::Relaxed);
+    SchedStats {
+        switches: c.switches.load(Ordering::Relaxed),
+        preemptions: c.preemptions.load(Ordering::Relaxed),
+        ready,
+        idle_ns: if idle == NO_SLOT {
+            0
+        } else {
+            thread::get(idle).cpu_ns.load(Ordering::Relaxed)
+        },
+        migrations_in: c.migrations_in.load(Ordering::Relaxed),
+        migrations_out: c.migrations_out.load(Ordering::Relaxed),
+        balance_pulls: c.balance_pulls.load(Ordering::Relaxed),
+    }
+}
+
+/// Log `stats` for each CPU in `cpus`.
+pub fn print_stats(cpus: CpuMask) {
+    for cpu in cpu::iter(cpus) {
+        let s = stats(cpu);
+        crate::klogln!(
+            "[sched] cpu{} switches={} preempt={} ready={} idle_ms={} migr_in={} migr_out={} pulls={}",
+            cpu,
+            s.switches,
+            s.preemptions,
+            s.ready,
+            s.idle_ns / 1_000_000,
+            s.migrations_in,
+            s.migrations_out,
+            s.balance_pulls
+        );
+    }
+}
+
+/// Boot-time check: two threads interleave through `yield_now` and are
+/// joined with their exit codes, affinity masks and offlining reject what
+/// they must, a blocked thread is raised to the top real-time priority and
+/// woken, then a thread that never yields is preempted on a short slice so
+/// another can release it, then the deadline class runs a periodic thread
+/// under admission control and reports one that overruns, and last a CPU
+/// is taken offline and back.
+#[cfg(feature = "selftest")]
+pub fn self_test() -> bool {
+    use crate::selftest::{self, TestThread};
+
+    const MS: u64 = 1_000_000;
+    static RELEASED: AtomicBool = AtomicBool::new(false);
+    static WOKEN: AtomicBool = AtomicBool::new(false);
+    static OVERRAN: AtomicBool = AtomicBool::new(false);
+
+    fn yielder(arg: usize) -> usize {
+        for _ in 0..4 {
+            yield_now();
+        }
+        arg * 2
+    }
+
+    fn spinner(_: usize) -> usize {
+        let give_up = crate::time::monotonic_ns() + 1_000_000_000;
+        while !RELEASED.load(Ordering::Acquire) {
+            if crate::time::monotonic_ns() > give_up {
+                return 0;
+            }
+            core::hint::spin_loop();
+        }
+        cpu_time_ns(current()).is_some_and(|ns| ns > 0) as usize
+    }
+
+    fn releaser(_: usize) -> usize {
+        RELEASED.store(true, Ordering::Release);
+        1
+    }
+
+    fn sleeper(_: usize) -> usize {
+        loop {
+            prepare_block();
+            if WOKEN.load(Ordering::Acquire) {
+                cancel_block();
+                break;
+            }
+            block();
+        }
+        (attr(current()) == Some(SchedAttr::round_robin(MAX_RT_PRIO))) as usize
+    }
+
+    let yielders = [
+        TestThread::new("test-a", yielder, 1, 2),
+        TestThread::new("test-b", yielder, 2, 4),
+    ];
+    if !selftest::run_threads(ALL_CPUS, yielders) {
+        return false;
+    }
+
+    let here = cpu::mask_of(cpu::current());
+    let Ok(p) = spawn_on_cpus("test-pinned", yielder, 3, SchedAttr::default(), here) else {
+        return false;
+    };
+    let pinned = affinity(p) == Some(here) && !set_affinity(p, 0);
+    if join(p) != Some(6) || !pinned || cpu_offline(0) || cpu_online(cpu::MAX_CPUS) {
+        return false;
+    }
+
+    WOKEN.store(false, Ordering::Relaxed);
+    let Ok(s) = spawn("test-sleeper", sleeper, 0) else {
+        return false;
+    };
+    let rr = SchedAttr::round_robin(MAX_RT_PRIO);
+    let raised = set_attr(s, rr)
+        && !set_attr(s, SchedAttr::round_robin(MAX_RT_PRIO + 1))
+        && attr(s) == Some(rr);
+    WOKEN.store(true, Ordering::Release);
+    wake(s);
+    if join(s) != Some(1) || !raised {
+        return false;
+    }
+    if hal::time::current().is_none() {
+        // No timer, no preemption.
+        return true;
+    }
+
+    let preempted = [
+        TestThread::new("test-spin", spinner, 0, 1),
+        TestThread::new("test-release", releaser, 0, 1),
+    ];
+    let quantum = quantum_ns();
+    set_quantum_ns(MS);
+    let ok = selftest::run_threads(ALL_CPUS, preempted);
+    set_quantum_ns(quantum);
+    if !ok {
+        return false;
+    }
+
+    // Deadline class: a periodic thread runs across periods, a second
+    // reservation that would overcommit the CPU is refused, and the first
+    // one's bandwidth is returned when it exits. Then a thread that spins
+    // past its budget is throttled and reported.
+    fn periodic(_: usize) -> usize {
+        for _ in 0..3 {
+            wait_next_period();
+        }
+        1
+    }
+
+    fn overrunner(_: usize) -> usize {
+        crate::time::busy_wait(3 * MS);
+        overruns(current()).unwrap_or(0) as usize
+    }
+
+    fn note_overrun(_: ThreadId) {
+        OVERRAN.store(true, Ordering::Release);
+    }
+
+    let Ok(p) = spawn_with(
+        "test-dl",
+        periodic,
+        0,
+        SchedAttr::deadline(MS, 5 * MS, 5 * MS),
+    ) else {
+        return false;
+    };
+    let full = SchedAttr::deadline(5 * MS, 5 * MS, 5 * MS);
+    let refused = matches!(
+        spawn_with("test-dl-over", periodic, 0, full),
+        Err(SpawnError::Admission)
+    );
+    if join(p) != Some(1) || !refused || deadline::utilization_percent(cpu::current()) != 0 {
+        return false;
+    }
+
+    OVERRAN.store(false, Ordering::Relaxed);
+    let handler = set_overrun_handler(note_overrun);
+    let overran = spawn_with(
+        "test-dl-overrun",
+        overrunner,
+        0,
+        SchedAttr::deadline(MS, 5 * MS, 5 * MS),
+    )
+    .is_ok_and(|o| join(o).is_some_and(|n| n > 0));
+    // The handler runs from deferred work.
+    let give_up = crate::time::monotonic_ns() + 100 * MS;
+    while overran && !OVERRAN.load(Ordering::Acquire) && crate::time::monotonic_ns() < give_up {
+        yield_now();
+    }
+    set_overrun_handler(handler);
+    overran && OVERRAN.load(Ordering::Acquire) && smp::offline_self_test()
+}
+
+/// Body of the idle thread: run deferred work, hand the CPU to ready
+/// threads, pull some from busier CPUs, and otherwise halt until the next
+/// interrupt.
+pub fn run_idle() -> ! {
+    let cpu = cpu::current();
+    loop {
+        crate::sync::rcu::quiescent();
+        crate::sync::rcu::cpu_left();
+        crate::deferred::run_pending();
+        if has_ready() {
+            yield_now();
+            continue;
+        }
+        if smp::balance(cpu) != 0 {
+            continue;
+        }
+
+        crate::arch::disable_interrupts();
+        if crate::deferred::has_pending() || has_ready() {
+            crate::arch::enable_interrupts();
+            continue;
+        }
+        crate::time::tick::idle_enter();
+        // Idle balances on every pass; the timer would only wake it.
+        timer::cancel(&cpu_sched(cpu).balance);
+        if !cpu::is_online(cpu) {
+            crate::time::timer::hand_off();
+        }
+        crate::arch::wait_for_interrupt();
+        crate::time::tick::idle_exit();
+        if cpu::is_online(cpu) {
+            timer::start_periodic(&cpu_sched(cpu).balance, smp::BALANCE_INTERVAL_NS);
+        }
+    }
+}
@@ -0,0 +1,378 @@
+//! Placement across CPUs: affinity, wakeup placement, load balancing and
+//! CPU offlining.
+//!
+//! A thread lives on one CPU's run queue at a time, named by
+//! `Thread::cpu`, which only changes with that CPU's rq lock held (and the
+//! destination's, when the thread is queued). Threads that are still on a
+//! CPU (`Thread::on_cpu`, set from pick until the switch away completes)
+//! are never moved, so no two CPUs ever run on the same stack.
+//!
+//! Deadline threads keep their CPU unless affinity or offlining forces a
+//! move, since their bandwidth is reserved per CPU; a forced move carries
+//! the reservation along.
+
+use core::sync::atomic::Ordering;
+
+use crate::sync::SpinLockGuard;
+
+use super::class::Policy;
+use super::thread::{self, ThreadId};
+use super::{CpuRq, check_preempt, cpu_sched, deadline, kick, runqueue};
+use crate::cpu::{self, CpuMask, MAX_CPUS};
+
+/// Most threads pulled in one balancing pass.
+const MAX_PULL: usize = 8;
+
+/// Period of the background balancer on each CPU.
+pub(super) const BALANCE_INTERVAL_NS: u64 = 100_000_000;
+
+/// One or two rq locks, taken in CPU order so pairs never deadlock.
+pub(super) struct RqPair {
+    a: (usize, SpinLockGuard<'static, CpuRq>),
+    b: Option<(usize, SpinLockGuard<'static, CpuRq>)>,
+}
+
+impl RqPair {
+    pub(super) fn get(&mut self, cpu: usize) -> &mut CpuRq {
+        if self.a.0 == cpu {
+            return &mut self.a.1;
+        }
+        match &mut self.b {
+            Some((c, rq)) if *c == cpu => rq,
+            _ => panic!("cpu{} rq not locked", cpu),
+        }
+    }
+}
+
+impl Drop for RqPair {
+    fn drop(&mut self) {
+        super::publish_load(self.a.0, &self.a.1);
+        if let Some((cpu, rq)) = &self.b {
+            super::publish_load(*cpu, rq);
+        }
+    }
+}
+
+/// Lock the rqs of `x` and `y`. Interrupts must already be disabled.
+pub(super) fn lock_pair(x: usize, y: usize) -> RqPair {
+    if x == y {
+        return RqPair {
+            a: (x, runqueue(x).lock()),
+            b: None,
+        };
+    }
+    let (lo, hi) = if x < y { (x, y) } else { (y, x) };
+    let lo_rq = runqueue(lo).lock();
+    let hi_rq = runqueue(hi).lock();
+    RqPair {
+        a: (lo, lo_rq),
+        b: Some((hi, hi_rq)),
+    }
+}
+
+/// CPUs `slot` may run on now. A mask with no online CPU left (e.g. after
+/// offlining) falls back to every online CPU.
+pub(super) fn allowed_mask(slot: usize) -> CpuMask {
+    let online = cpu::online_mask();
+    let allowed = thread::get(slot).affinity.load(Ordering::Relaxed) & online;
+    if allowed == 0 { online } else { allowed }
+}
+
+pub(super) fn runnable_on(slot: usize, cpu: usize) -> bool {
+    allowed_mask(slot) & cpu::mask_of(cpu) != 0
+}
+
+/// Ready threads plus the running one, idle excluded.
+fn load(cpu: usize) -> usize {
+    let c = cpu_sched(cpu);
+    let busy = c.current.load(Ordering::Relaxed) != c.idle.load(Ordering::Relaxed);
+    c.nr_ready.load(Ordering::Relaxed) + busy as usize
+}
+
+fn is_idle_cpu(cpu: usize) -> bool {
+    load(cpu) == 0
+}
+
+/// Where `slot`, last on `prev`, should run next: `prev` if it is allowed
+/// and idle (or the thread is a deadline one), else an idle allowed CPU,
+/// else `prev` if allowed, else the least loaded allowed CPU.
+pub(super) fn select_cpu(slot: usize, prev: usize) -> usize {
+    let allowed = allowed_mask(slot);
+    let prev_ok = allowed & cpu::mask_of(prev) != 0;
+    if allowed == 0 {
+        return prev;
+    }
+    if prev_ok && (is_idle_cpu(prev) || thread::get(slot).policy() == Policy::Deadline) {
+        return prev;
+    }
+    if let Some(idle) = cpu::iter(allowed).find(|&c| is_idle_cpu(c)) {
+        return idle;
+    }
+    if prev_ok {
+        return prev;
+    }
+    cpu::iter(allowed).min_by_key(|&c| load(c)).unwrap_or(prev)
+}
+
+/// Re-home a thread that is in no queue. Caller holds both rq locks.
+pub(super) fn set_cpu(slot: usize, from: usize, to: usize) {
+    if from == to {
+        return;
+    }
+    let t = thread::get(slot);
+    if t.policy() == Policy::Deadline {
+        deadline::transfer(from, to, &t.attr());
+    }
+    t.cpu.store(to, Ordering::Release);
+    cpu_sched(from)
+        .migrations_out
+        .fetch_add(1, Ordering::Relaxed);
+    cpu_sched(to).migrations_in.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Move queued `slot` from `from` to `to`. Caller holds both rq locks.
+fn move_queued(pair: &mut RqPair, slot: usize, from: usize, to: usize) -> bool {
+    if !pair.get(from).rq.dequeue(slot) {
+        return false;
+    }
+    let t = thread::get(slot);
+    // Fair runtime is relative to each queue's floor.
+    let v = t.vruntime.load(Ordering::Relaxed);
+    let rel = v.saturating_sub(pair.get(from).rq.min_vruntime);
+    t.vruntime
+        .store(pair.get(to).rq.min_vruntime + rel, Ordering::Relaxed);
+    set_cpu(slot, from, to);
+    pair.get(to).rq.enqueue(slot, false);
+    true
+}
+
+/// Queue a Ready thread that was just switched out of `from` but may not
+/// stay there. Runs once its `on_cpu` is clear.
+pub(super) fn push_away(slot: usize, from: usize) {
+    let to = select_cpu(slot, from);
+    let irq = crate::arch::irq_save();
+    let mut pair = lock_pair(from, to);
+    set_cpu(slot, from, to);
+    pair.get(to).rq.enqueue(slot, false);
+    check_preempt(to, pair.get(to));
+    drop(pair);
+    crate::arch::irq_restore(irq);
+    kick(to);
+}
+
+/// Pull work from the busiest CPU onto `cpu` if the gap is at least two
+/// threads. Called from the idle loop and the periodic balancer. Returns
+/// how many threads moved.
+pub(super) fn balance(cpu: usize) -> usize {
+    if !cpu::is_online(cpu) {
+        return 0;
+    }
+    let mine = load(cpu);
+    let Some((busiest, theirs)) = cpu::iter(cpu::online_mask())
+        .filter(|&c| c != cpu)
+        .map(|c| (c, load(c)))
+        .max_by_key(|&(_, l)| l)
+    else {
+        return 0;
+    };
+    if theirs < mine + 2 {
+        return 0;
+    }
+    let want = ((theirs - mine) / 2).clamp(1, MAX_PULL);
+
+    let irq = crate::arch::irq_save();
+    let mut pair = lock_pair(cpu, busiest);
+    let mut picked = [0usize; MAX_PULL];
+    let mut n = 0;
+    for slot in pair.get(busiest).rq.iter() {
+        if n == want {
+            break;
+        }
+        let t = thread::get(slot);
+        if !t.on_cpu.load(Ordering::Acquire)
+            && t.policy() != Policy::Deadline
+            && runnable_on(slot, cpu)
+        {
+            picked[n] = slot;
+            n += 1;
+        }
+    }
+    let moved = picked[..n]
+        .iter()
+        .filter(|&&slot| move_queued(&mut pair, slot, busiest, cpu))
+        .count();
+    if moved != 0 {
+        cpu_sched(cpu).balance_pulls.fetch_add(1, Ordering::Relaxed);
+        check_preempt(cpu, pair.get(cpu));
+    }
+    drop(pair);
+    crate::arch::irq_restore(irq);
+    moved
+}
+
+/// Restrict `id` to the CPUs in `mask`. A queued thread moves at once; a
+/// running one is kicked off its CPU and moves at its next switch.
+/// Returns false if `id` is stale or `mask` has no online CPU.
+pub fn set_affinity(id: ThreadId, mask: CpuMask) -> bool {
+    let Some(t) = thread::lookup(id) else {
+        return false;
+    };
+    if mask & cpu::online_mask() == 0 {
+        return false;
+    }
+    let slot = id.slot();
+    t.affinity.store(mask, Ordering::Relaxed);
+
+    let irq = crate::arch::irq_save();
+    let from = t.cpu.load(Ordering::Acquire);
+    let mut kicked = None;
+    if !runnable_on(slot, from) {
+        let to = select_cpu(slot, from);
+        let mut pair = lock_pair(from, to);
+        if t.cpu.load(Ordering::Relaxed) == from {
+            if t.on_cpu.load(Ordering::Acquire) {
+                cpu_sched(from).need_resched.store(true, Ordering::Relaxed);
+                kicked = Some(from);
+            } else if move_queued(&mut pair, slot, from, to) {
+                check_preempt(to, pair.get(to));
+                kicked = Some(to);
+            }
+            // Blocked threads are placed when they wake.
+        }
+    }
+    crate::arch::irq_restore(irq);
+    if let Some(cpu) = kicked {
+        kick(cpu);
+    }
+    true
+}
+
+pub fn affinity(id: ThreadId) -> Option<CpuMask> {
+    thread::lookup(id).map(|t| t.affinity.load(Ordering::Relaxed))
+}
+
+/// Stop scheduling threads on `cpu`: it leaves the online mask, its
+/// queued threads move to other CPUs, and its running thread is kicked
+/// off at the next interrupt, after which it only runs idle and hands its
+/// timers to an online CPU. Returns false for the boot CPU, the last
+/// online CPU, or one already offline.
+pub fn cpu_offline(cpu: usize) -> bool {
+    if cpu == 0 || cpu >= MAX_CPUS || !cpu::is_online(cpu) {
+        return false;
+    }
+    if cpu::online_mask() == cpu::mask_of(cpu) {
+        return false;
+    }
+    crate::sync::rcu::cpu_leaving(cpu);
+    cpu::set_online(cpu, false);
+
+    let irq = crate::arch::irq_save();
+    loop {
+        // A queued thread that is still on the CPU (woken before it
+        // finished blocking) leaves at its next switch instead.
+        let next = runqueue(cpu)
+            .lock()
+            .rq
+            .iter()
+            .find(|&s| !thread::get(s).on_cpu.load(Ordering::Acquire));
+        let Some(slot) = next else {
+            break;
+        };
+        let to = select_cpu(slot, cpu);
+        let mut pair = lock_pair(cpu, to);
+        if thread::get(slot).cpu.load(Ordering::Relaxed) == cpu
+            && move_queued(&mut pair, slot, cpu, to)
+        {
+            check_preempt(to, pair.get(to));
+        }
+        drop(pair);
+        kick(to);
+    }
+    cpu_sched(cpu).need_resched.store(true, Ordering::Relaxed);
+    crate::arch::irq_restore(irq);
+    kick(cpu);
+    true
+}
+
+/// Let `cpu` take threads again after `cpu_offline`.
+pub fn cpu_online(cpu: usize) -> bool {
+    if cpu >= MAX_CPUS || cpu_sched(cpu).idle.load(Ordering::Relaxed) == thread::NO_SLOT {
+        return false;
+    }
+    cpu::set_online(cpu, true);
+    true
+}
+
+/// Boot-time check: a thread pinned to a CPU and a timer started there
+/// both leave it when it is taken offline, and the timer still fires.
+/// Trivially true with one CPU.
+#[cfg(feature = "selftest")]
+pub(super) fn offline_self_test() -> bool {
+    use core::sync::atomic::{AtomicBool, AtomicUsize};
+
+    use crate::time::timer::{self, Timer};
+
+    const MS: u64 = 1_000_000;
+    static STARTED: AtomicBool = AtomicBool::new(false);
+    static STOP: AtomicBool = AtomicBool::new(false);
+    static FIRED_ON: AtomicUsize = AtomicUsize::new(usize::MAX);
+    static TIMER: Timer = Timer::new("test-offline", on_timer, 0);
+
+    fn on_timer(_: usize) {
+        FIRED_ON.store(cpu::current(), Ordering::Release);
+    }
+
+    fn pinned(_: usize) -> usize {
+        timer::start(&TIMER, 200 * MS);
+        STARTED.store(true, Ordering::Release);
+        while !STOP.load(Ordering::Acquire) {
+            super::yield_now();
+        }
+        cpu::current()
+    }
+
+    /// Poll `done` for up to a second.
+    fn wait_for(done: impl Fn() -> bool) -> bool {
+        let give_up = crate::time::monotonic_ns() + 1_000 * MS;
+        while !done() {
+            if crate::time::monotonic_ns() > give_up {
+                return false;
+            }
+            crate::time::busy_wait(MS);
+        }
+        true
+    }
+
+    let Some(victim) = cpu::iter(cpu::online_mask()).filter(|&c| c != 0).last() else {
+        return true;
+    };
+    if hal::time::current().is_none() {
+        // Nothing would fire the timer.
+        return true;
+    }
+    STARTED.store(false, Ordering::Relaxed);
+    STOP.store(false, Ordering::Relaxed);
+    FIRED_ON.store(usize::MAX, Ordering::Relaxed);
+    let Ok(id) = super::spawn_on_cpus(
+        "test-offline",
+        pinned,
+        0,
+        Default::default(),
+        cpu::mask_of(victim),
+    ) else {
+        return false;
+    };
+
+    let armed = wait_for(|| STARTED.load(Ordering::Acquire)) && TIMER.cpu() == Some(victim);
+    let offlined = armed && cpu_offline(victim);
+    let fired = offlined && wait_for(|| FIRED_ON.load(Ordering::Acquire) != usize::MAX);
+    STOP.store(true, Ordering::Release);
+    let ran_on = super::join(id);
+    timer::cancel(&TIMER);
+    let onlined = !offlined || cpu_online(victim);
+
+    fired
+        && onlined
+        && FIRED_ON.load(Ordering::Acquire) != victim
+        && ran_on.is_some_and(|c| c != victim)
+}
@@ -0,0 +1,268 @@
+//! Thread control blocks and their stacks.
+//!
+//! Threads live in a fixed pool; a `ThreadId` is the pool index plus a
+//! generation so stale ids from a reaped thread never alias a new one.
+
+use core::cell::UnsafeCell;
+use core::sync::atomic::{
+    AtomicBool, AtomicI8, AtomicU8, AtomicU32, AtomicU64, AtomicUsize, Ordering,
+};
+
+use hal::context::Context;
+
+use super::class::{DL_PRIO, FAIR_KEY, NOT_QUEUED, Policy, SchedAttr};
+use super::deadline::DlState;
+use crate::cpu::ALL_CPUS;
+use crate::obj::{KernelObject, Obj, ObjHeader, ObjPool, ObjType};
+
+pub const MAX_THREADS: usize = 64;
+pub const STACK_SIZE: usize = 16 * 1024;
+
+/// Pool slot of the boot thread, which keeps running on the boot stack.
+pub const BOOT_SLOT: usize = 0;
+
+/// `Thread::next` / `joiner` value meaning "none".
+pub const NO_SLOT: usize = usize::MAX;
+
+#[repr(u8)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ThreadState {
+    Free = 0,
+    Ready = 1,
+    Running = 2,
+    Blocked = 3,
+    Exited = 4,
+}
+
+impl ThreadState {
+    fn from_u8(v: u8) -> Self {
+        match v {
+            1 => ThreadState::Ready,
+            2 => ThreadState::Running,
+            3 => ThreadState::Blocked,
+            4 => ThreadState::Exited,
+            _ => ThreadState::Free,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ThreadId {
+    pub(super) slot: u32,
+    pub(super) generation: u32,
+}
+
+impl ThreadId {
+    pub fn slot(&self) -> usize {
+        self.slot as usize
+    }
+}
+
+pub struct Thread {
+    pub(super) name: UnsafeCell<&'static str>,
+    pub(super) state: AtomicU8,
+    pub(super) generation: AtomicU32,
+    pub(super) ctx: UnsafeCell<Context>,
+    pub(super) entry: UnsafeCell<fn(usize) -> usize>,
+    pub(super) arg: AtomicUsize,
+    pub(super) exit_code: AtomicUsize,
+    /// Slot of the thread blocked in `join` on this one.
+    pub(super) joiner: AtomicUsize,
+    /// Run-queue link.
+    pub(super) next: AtomicUsize,
+    /// CPU whose run queue this thread belongs to.
+    pub(super) cpu: AtomicUsize,
+    /// CPUs the thread may run on.
+    pub(super) affinity: AtomicU64,
+    /// Picked to run on `cpu` and not yet fully switched away from it.
+    pub(super) on_cpu: AtomicBool,
+    /// The `block_until` deadline, rather than a wake, ended the last
+    /// timed block.
+    pub(super) timed_out: AtomicBool,
+    pub(super) policy: AtomicU8,
+    /// Base real-time priority; 0 for fair threads.
+    pub(super) rt_prio: AtomicU8,
+    pub(super) nice: AtomicI8,
+    /// Priority inherited from waiters on mutexes this thread holds.
+    pub(super) pi_prio: AtomicU8,
+    /// Queue the thread sits in (`class::NOT_QUEUED` if none).
+    pub(super) rq_key: AtomicU8,
+    /// Weighted runtime for the fair class, in ns.
+    pub(super) vruntime: AtomicU64,
+    /// CPU time consumed, in ns.
+    pub(super) cpu_ns: AtomicU64,
+    /// Nesting depth of `preempt_disable`.
+    pub(super) preempt_count: AtomicU32,
+    pub(super) dl: DlState,
+}
+
+// Non-atomic fields are only touched under the scheduler lock or by the
+// thread itself.
+unsafe impl Sync for Thread {}
+
+fn no_entry(_: usize) -> usize {
+    0
+}
+
+impl Thread {
+    const fn new() -> Self {
+        Self {
+            name: UnsafeCell::new(""),
+            state: AtomicU8::new(ThreadState::Free as u8),
+            generation: AtomicU32::new(0),
+            ctx: UnsafeCell::new(Context { sp: 0 }),
+            entry: UnsafeCell::new(no_entry),
+            arg: AtomicUsize::new(0),
+            exit_code: AtomicUsize::new(0),
+            joiner: AtomicUsize::new(NO_SLOT),
+            next: AtomicUsize::new(NO_SLOT),
+            cpu: AtomicUsize::new(0),
+            affinity: AtomicU64::new(ALL_CPUS),
+            on_cpu: AtomicBool::new(false),
+            timed_out: AtomicBool::new(false),
+            policy: AtomicU8::new(Policy::Fair as u8),
+            rt_prio: AtomicU8::new(0),
+            nice: AtomicI8::new(0),
+            pi_prio: AtomicU8::new(0),
+            rq_key: AtomicU8::new(NOT_QUEUED),
+            vruntime: AtomicU64::new(0),
+            cpu_ns: AtomicU64::new(0),
+            preempt_count: AtomicU32::new(0),
+            dl: DlState::new(),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        unsafe { *self.name.get() }
+    }
+
+    pub fn state(&self) -> ThreadState {
+        ThreadState::from_u8(self.state.load(Ordering::Acquire))
+    }
+
+    pub(super) fn set_state(&self, s: ThreadState) {
+        self.state.store(s as u8, Ordering::Release);
+    }
+
+    pub(super) fn policy(&self) -> Policy {
+        Policy::from_u8(self.policy.load(Ordering::Relaxed))
+    }
+
+    pub(super) fn attr(&self) -> SchedAttr {
+        SchedAttr {
+            policy: self.policy(),
+            priority: self.rt_prio.load(Ordering::Relaxed),
+            nice: self.nice.load(Ordering::Relaxed),
+            runtime_ns: self.dl.runtime_ns.load(Ordering::Relaxed),
+            deadline_ns: self.dl.deadline_ns.load(Ordering::Relaxed),
+            period_ns: self.dl.period_ns.load(Ordering::Relaxed),
+        }
+    }
+
+    pub(super) fn set_attr(&self, attr: SchedAttr) {
+        self.policy.store(attr.policy as u8, Ordering::Relaxed);
+        let prio = if attr.policy.is_realtime() {
+            attr.priority
+        } else {
+            FAIR_KEY
+        };
+        self.rt_prio.store(prio, Ordering::Relaxed);
+        self.nice.store(attr.nice, Ordering::Relaxed);
+        self.dl.reset(&attr);
+    }
+
+    /// Priority the thread is queued and compared by: `DL_PRIO` for
+    /// deadline threads, otherwise its real-time priority or an inherited
+    /// one, whichever is higher. 0 means fair.
+    pub(super) fn effective_prio(&self) -> u8 {
+        if self.policy().is_deadline() {
+            return DL_PRIO;
+        }
+        self.rt_prio
+            .load(Ordering::Relaxed)
+            .max(self.pi_prio.load(Ordering::Relaxed))
+    }
+}
+
+#[repr(C, align(16))]
+pub(super) struct Stack(pub(super) [u8; STACK_SIZE]);
+
+pub(super) static THREADS: [Thread; MAX_THREADS] = [const { Thread::new() }; MAX_THREADS];
+
+pub(super) static mut STACKS: [Stack; MAX_THREADS] =
+    [const { Stack([0; STACK_SIZE]) }; MAX_THREADS];
+
+#[inline(always)]
+pub(super) fn get(slot: usize) -> &'static Thread {
+    &THREADS[slot]
+}
+
+pub(super) fn id_of(slot: usize) -> ThreadId {
+    ThreadId {
+        slot: slot as u32,
+        generation: THREADS[slot].generation.load(Ordering::Acquire),
+    }
+}
+
+/// Resolve `id` to its slot if it still names the same thread.
+pub(super) fn lookup(id: ThreadId) -> Option<&'static Thread> {
+    let t = THREADS.get(id.slot())?;
+    if t.generation.load(Ordering::Acquire) != id.generation || t.state() == ThreadState::Free {
+        return None;
+    }
+    Some(t)
+}
+
+/// Top of the stack for `slot` (the boot slot has no pool stack).
+pub(super) fn stack_top(slot: usize) -> u64 {
+    let base = unsafe { core::ptr::addr_of!(STACKS[slot]) as u64 };
+    base + STACK_SIZE as u64
+}
+
+/// A thread as a kernel object, so handles can name it. The object can
+/// outlive the thread; once the thread is reaped its id stops resolving.
+#[repr(C)]
+pub struct ThreadObject {
+    hdr: ObjHeader,
+    /// `ThreadId` packed as generation << 32 | slot.
+    id: AtomicU64,
+}
+
+unsafe impl KernelObject for ThreadObject {
+    const TYPE: ObjType = ObjType::Thread;
+
+    fn header(&self) -> &ObjHeader {
+        &self.hdr
+    }
+}
+
+static THREAD_OBJECTS: ObjPool<ThreadObject, MAX_THREADS> = ObjPool::new(
+    [const {
+        ThreadObject {
+            hdr: ObjHeader::new(),
+            id: AtomicU64::new(0),
+        }
+    }; MAX_THREADS],
+);
+
+impl ThreadObject {
+    /// A new object naming `id`, or `None` if the thread is gone or the
+    /// object pool is exhausted.
+    pub fn create(id: ThreadId) -> Option<Obj<ThreadObject>> {
+        let t = lookup(id)?;
+        THREAD_OBJECTS.alloc(t.name(), |obj| {
+            obj.id.store(
+                (id.generation as u64) << 32 | id.slot as u64,
+                Ordering::Release,
+            );
+        })
+    }
+
+    pub fn id(&self) -> ThreadId {
+        let v = self.id.load(Ordering::Acquire);
+        ThreadId {
+            slot: v as u32,
+            generation: (v >> 32) as u32,
+        }
+    }
+}
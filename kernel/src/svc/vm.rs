@@ -1,8 +1,10 @@
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use crate::sync::SpinLock;
 use bootabi::{BootInfo, MemMapEntry, MemType};
 use hal::mmu::{
     AddressSpace, MapError, MapFlags, Mmu, PageTableFrameAlloc, PhysAddr, TranslateError, VirtAddr,
 };
-use spin::Mutex;
 
 const PAGE_SIZE: u64 = 4096;
 const MAX_FREE_FRAMES: usize = 128;
@@ -112,10 +114,13 @@ impl PageTableFrameAlloc for BootPtAlloc {
     }
 }
 
-static PT_ALLOC: Mutex<Option<BootPtAlloc>> = Mutex::new(None);
+static PT_ALLOC: SpinLock<Option<BootPtAlloc>> = SpinLock::new(None);
+
+static HHDM_OFFSET: AtomicU64 = AtomicU64::new(0);
 
 pub fn init(boot: &BootInfo) {
     let alloc = BootPtAlloc::new(boot).expect("vm: missing HHDM or memmap");
+    HHDM_OFFSET.store(alloc.hhdm_offset, Ordering::Relaxed);
     *PT_ALLOC.lock() = Some(alloc);
 
     unsafe {
@@ -170,6 +175,23 @@ pub fn translate(aspace: &AddressSpace, vaddr: VirtAddr) -> Result<PhysAddr, Tra
     unsafe { crate::arch::mmu().translate(aspace, vaddr) }
 }
 
+/// A zeroed 4 KiB frame for kernel use, e.g. a page shared with user
+/// space. Comes from the page table allocator.
+pub fn alloc_frame() -> Option<PhysAddr> {
+    PT_ALLOC.lock().as_mut()?.alloc_frame_4k()
+}
+
+pub fn free_frame(paddr: PhysAddr) {
+    if let Some(alloc) = PT_ALLOC.lock().as_mut() {
+        alloc.free_frame_4k(paddr);
+    }
+}
+
+/// Kernel address of physical memory, through the HHDM.
+pub fn phys_to_virt(paddr: PhysAddr) -> *mut u8 {
+    paddr.0.wrapping_add(HHDM_OFFSET.load(Ordering::Relaxed)) as *mut u8
+}
+
 fn is_usable(mem_type: MemType) -> bool {
     matches!(mem_type, MemType::Usable | MemType::BootloaderReclaimable)
 }
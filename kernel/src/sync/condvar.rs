@@ -0,0 +1,82 @@
+//! Condition variables for `Mutex`.
+//!
+//! `wait` releases the mutex and sleeps in one step with respect to
+//! `notify_*`: a notify issued after the waiter dropped the mutex always
+//! finds it queued. Wakeups may be spurious, so wait in a loop on the
+//! predicate, or use `wait_while`.
+
+use super::{Mutex, MutexGuard, WaitQueue};
+use crate::svc::sched;
+
+pub struct Condvar {
+    waiters: WaitQueue,
+}
+
+impl Condvar {
+    #[track_caller]
+    pub const fn new() -> Self {
+        Self {
+            waiters: WaitQueue::new(),
+        }
+    }
+
+    /// Release `guard`'s mutex, sleep until notified, and lock it again.
+    pub fn wait<'a, T>(&self, guard: MutexGuard<'a, T>) -> MutexGuard<'a, T> {
+        let mutex = unlock_and_queue(&self.waiters, guard);
+        sched::block();
+        self.waiters.finish_wait();
+        mutex.lock()
+    }
+
+    /// `wait`, giving up after `timeout_ns`. The flag is true if the
+    /// timeout ended the wait.
+    pub fn wait_timeout<'a, T>(
+        &self,
+        guard: MutexGuard<'a, T>,
+        timeout_ns: u64,
+    ) -> (MutexGuard<'a, T>, bool) {
+        let deadline = crate::time::monotonic_ns().saturating_add(timeout_ns);
+        let mutex = unlock_and_queue(&self.waiters, guard);
+        let notified = sched::block_until(deadline);
+        self.waiters.finish_wait();
+        (mutex.lock(), !notified)
+    }
+
+    /// Wait while `cond` holds on the protected value.
+    pub fn wait_while<'a, T>(
+        &self,
+        mut guard: MutexGuard<'a, T>,
+        mut cond: impl FnMut(&mut T) -> bool,
+    ) -> MutexGuard<'a, T> {
+        while cond(&mut guard) {
+            guard = self.wait(guard);
+        }
+        guard
+    }
+
+    /// Wake one waiter. Returns false if none was waiting.
+    pub fn notify_one(&self) -> bool {
+        self.waiters.wake_one()
+    }
+
+    /// Wake every waiter. Returns how many were waiting.
+    pub fn notify_all(&self) -> usize {
+        self.waiters.wake_all()
+    }
+}
+
+impl Default for Condvar {
+    #[track_caller]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Queue the caller before releasing the mutex, so a notifier that takes
+/// it next cannot miss us.
+fn unlock_and_queue<'a, T>(waiters: &WaitQueue, guard: MutexGuard<'a, T>) -> &'a Mutex<T> {
+    let mutex = MutexGuard::mutex(&guard);
+    waiters.prepare_to_wait();
+    drop(guard);
+    mutex
+}
@@ -0,0 +1,523 @@
+//! Lock dependency validator, built with the `lockdep` cargo feature.
+//!
+//! Every lock belongs to a class, named by the source location that
+//! constructed it: all run queue locks are one class, `PT_ALLOC` another.
+//! Each acquisition records "class B taken while holding class A" edges,
+//! and an edge that closes a cycle in that graph is a lock order that can
+//! deadlock on SMP (ABBA), reported through the logger with the sites
+//! that took each lock. A class taken both from interrupt handlers and
+//! with interrupts enabled is reported too: the interrupt can arrive
+//! while its own CPU holds the lock.
+//!
+//! Checks run before a lock spins, so a real deadlock is reported before
+//! it hangs. Without the feature the hooks compile to nothing.
+
+use core::panic::Location;
+
+/// Identity of a lock class: where its locks are constructed. Embed one
+/// per lock and create it in a `#[track_caller]` constructor.
+#[derive(Clone, Copy)]
+pub struct LockClass {
+    #[cfg(feature = "lockdep")]
+    site: &'static Location<'static>,
+}
+
+impl LockClass {
+    #[track_caller]
+    pub const fn new() -> Self {
+        Self {
+            #[cfg(feature = "lockdep")]
+            site: Location::caller(),
+        }
+    }
+}
+
+impl Default for LockClass {
+    #[track_caller]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Returned by `acquire`, handed back to `release`; records whose held
+/// list the lock went on, which may not be the releasing thread's (the
+/// scheduler drops the run queue lock after switching `current`).
+#[derive(Clone, Copy)]
+pub struct Held {
+    #[cfg(feature = "lockdep")]
+    owner: usize,
+}
+
+/// About to spin or sleep on a lock of `class`, from `site`.
+#[inline(always)]
+pub fn acquire(class: &LockClass, site: &'static Location<'static>) -> Held {
+    #[cfg(feature = "lockdep")]
+    {
+        imp::acquire(class.site, site, true)
+    }
+    #[cfg(not(feature = "lockdep"))]
+    {
+        let _ = (class, site);
+        Held {}
+    }
+}
+
+/// Took a lock of `class` without waiting (a successful `try_lock`): held
+/// from now on, but cannot have deadlocked.
+#[inline(always)]
+pub fn acquired_try(class: &LockClass, site: &'static Location<'static>) -> Held {
+    #[cfg(feature = "lockdep")]
+    {
+        imp::acquire(class.site, site, false)
+    }
+    #[cfg(not(feature = "lockdep"))]
+    {
+        let _ = (class, site);
+        Held {}
+    }
+}
+
+#[inline(always)]
+pub fn release(class: &LockClass, held: Held) {
+    #[cfg(feature = "lockdep")]
+    imp::release(class.site, held.owner);
+    #[cfg(not(feature = "lockdep"))]
+    let _ = (class, held);
+}
+
+/// Problems reported since boot.
+pub fn reports() -> usize {
+    #[cfg(feature = "lockdep")]
+    {
+        imp::REPORTS.load(core::sync::atomic::Ordering::Relaxed)
+    }
+    #[cfg(not(feature = "lockdep"))]
+    {
+        0
+    }
+}
+
+/// Boot-time check: an AB-BA order between two test classes and a class
+/// used both in and out of interrupt context are both caught. Trivially
+/// true without the feature.
+#[cfg(feature = "selftest")]
+pub fn self_test() -> bool {
+    #[cfg(feature = "lockdep")]
+    {
+        imp::self_test()
+    }
+    #[cfg(not(feature = "lockdep"))]
+    {
+        true
+    }
+}
+
+#[cfg(feature = "lockdep")]
+mod imp {
+    use core::panic::Location;
+    use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+    use spin::Mutex;
+
+    use super::Held;
+    use crate::cpu::MAX_CPUS;
+    use crate::svc::sched::{self, thread::MAX_THREADS};
+
+    type Site = &'static Location<'static>;
+    type ClassSet = u128;
+
+    const MAX_CLASSES: usize = ClassSet::BITS as usize;
+    const MAX_EDGES: usize = 512;
+    /// Deepest nesting tracked per context.
+    const MAX_HELD: usize = 16;
+    const NO_CLASS: u8 = u8::MAX;
+
+    #[derive(Clone, Copy)]
+    struct Edge {
+        from: u8,
+        to: u8,
+        /// Where `from` was taken, and where `to` was taken under it.
+        held_at: Site,
+        taken_at: Site,
+    }
+
+    struct Graph {
+        classes: [Option<Site>; MAX_CLASSES],
+        len: usize,
+        /// Bit `b` of `after[a]`: `b` has been taken while holding `a`.
+        after: [ClassSet; MAX_CLASSES],
+        edges: [Option<Edge>; MAX_EDGES],
+        nr_edges: usize,
+        /// First acquisition from an interrupt handler, per class.
+        irq_site: [Option<Site>; MAX_CLASSES],
+        /// First acquisition with interrupts enabled, per class.
+        irq_on_site: [Option<Site>; MAX_CLASSES],
+        irq_reported: ClassSet,
+        full_reported: bool,
+    }
+
+    static GRAPH: Mutex<Graph> = Mutex::new(Graph {
+        classes: [None; MAX_CLASSES],
+        len: 0,
+        after: [0; MAX_CLASSES],
+        edges: [None; MAX_EDGES],
+        nr_edges: 0,
+        irq_site: [None; MAX_CLASSES],
+        irq_on_site: [None; MAX_CLASSES],
+        irq_reported: 0,
+        full_reported: false,
+    });
+
+    #[derive(Clone, Copy)]
+    struct HeldLock {
+        class: u8,
+        site: Site,
+    }
+
+    struct HeldList {
+        locks: [Option<HeldLock>; MAX_HELD],
+        len: usize,
+    }
+
+    /// Locks held per thread slot, then per CPU for code that runs before
+    /// the CPU has a current thread.
+    static HELD: [Mutex<HeldList>; MAX_THREADS + MAX_CPUS] = [const {
+        Mutex::new(HeldList {
+            locks: [None; MAX_HELD],
+            len: 0,
+        })
+    }; MAX_THREADS + MAX_CPUS];
+
+    /// Set while this CPU is inside the validator or printing a report;
+    /// locks taken meanwhile (the logger's) are not tracked.
+    static BUSY: [AtomicBool; MAX_CPUS] = [const { AtomicBool::new(false) }; MAX_CPUS];
+
+    pub(super) static REPORTS: AtomicUsize = AtomicUsize::new(0);
+
+    /// Count reports without printing them (the self-test).
+    static SILENT: AtomicBool = AtomicBool::new(false);
+
+    const UNTRACKED: usize = usize::MAX;
+
+    fn owner() -> usize {
+        let slot = sched::current_slot();
+        if slot < MAX_THREADS {
+            slot
+        } else {
+            MAX_THREADS + crate::cpu::current()
+        }
+    }
+
+    /// What went wrong, filled under `GRAPH` and printed after.
+    enum Report {
+        Cycle {
+            path: [Option<Edge>; 8],
+            classes: [Option<Site>; MAX_CLASSES],
+            held: HeldLock,
+            taken: Site,
+            class: u8,
+        },
+        IrqUnsafe {
+            class: Site,
+            irq_at: Site,
+            irq_on_at: Site,
+        },
+        Full,
+    }
+
+    pub(super) fn acquire(class_site: Site, site: Site, may_wait: bool) -> Held {
+        let irq = crate::arch::irq_save();
+        let cpu = crate::cpu::current();
+        if BUSY[cpu].swap(true, Ordering::Acquire) {
+            crate::arch::irq_restore(irq);
+            return Held { owner: UNTRACKED };
+        }
+        let owner = owner();
+        let in_irq = crate::interrupts::in_interrupt();
+        let irqs_were_on = irq != 0;
+
+        let mut report = None;
+        let class = {
+            let mut g = GRAPH.lock();
+            match g.class_id(class_site) {
+                Some(class) => {
+                    let held = HELD[owner].lock();
+                    if may_wait {
+                        for h in held.locks[..held.len].iter().flatten() {
+                            if report.is_none() {
+                                report = g.add_edge(*h, class, site);
+                            } else {
+                                g.add_edge(*h, class, site);
+                            }
+                        }
+                    }
+                    if report.is_none() {
+                        report = g.note_irq_use(class, site, in_irq, irqs_were_on);
+                    }
+                    class
+                }
+                None => {
+                    if !g.full_reported {
+                        g.full_reported = true;
+                        report = Some(Report::Full);
+                    }
+                    NO_CLASS
+                }
+            }
+        };
+
+        let mut held = HELD[owner].lock();
+        if class != NO_CLASS && held.len < MAX_HELD {
+            let n = held.len;
+            held.locks[n] = Some(HeldLock { class, site });
+            held.len += 1;
+        }
+        drop(held);
+
+        if let Some(r) = report {
+            REPORTS.fetch_add(1, Ordering::Relaxed);
+            if !SILENT.load(Ordering::Relaxed) {
+                print(&r, class_site);
+            }
+        }
+        BUSY[cpu].store(false, Ordering::Release);
+        crate::arch::irq_restore(irq);
+        Held { owner }
+    }
+
+    pub(super) fn release(class_site: Site, owner: usize) {
+        if owner == UNTRACKED {
+            return;
+        }
+        let irq = crate::arch::irq_save();
+        let class = GRAPH.lock().lookup(class_site);
+        if let Some(class) = class {
+            let mut held = HELD[owner].lock();
+            let n = held.len;
+            // Usually the innermost, but locks may be dropped out of order.
+            if let Some(i) = held.locks[..n]
+                .iter()
+                .rposition(|h| h.is_some_and(|h| h.class == class))
+            {
+                held.locks.copy_within(i + 1..n, i);
+                held.locks[n - 1] = None;
+                held.len -= 1;
+            }
+        }
+        crate::arch::irq_restore(irq);
+    }
+
+    impl Graph {
+        fn lookup(&self, site: Site) -> Option<u8> {
+            self.classes[..self.len]
+                .iter()
+                .position(|c| c.is_some_and(|c| same_site(c, site)))
+                .map(|i| i as u8)
+        }
+
+        fn class_id(&mut self, site: Site) -> Option<u8> {
+            if let Some(id) = self.lookup(site) {
+                return Some(id);
+            }
+            if self.len == MAX_CLASSES {
+                return None;
+            }
+            self.classes[self.len] = Some(site);
+            self.len += 1;
+            Some((self.len - 1) as u8)
+        }
+
+        /// Record `held` -> `class`. Returns a report if the reverse order
+        /// was already seen, directly or through other classes.
+        fn add_edge(&mut self, held: HeldLock, class: u8, site: Site) -> Option<Report> {
+            let (a, b) = (held.class, class);
+            // Locks of one class (per-CPU arrays) nest by their own rules.
+            if a == b || self.after[a as usize] & (1 << b) != 0 {
+                return None;
+            }
+            let cycle = self.path(b, a);
+            self.after[a as usize] |= 1 << b;
+            if self.nr_edges < MAX_EDGES {
+                self.edges[self.nr_edges] = Some(Edge {
+                    from: a,
+                    to: b,
+                    held_at: held.site,
+                    taken_at: site,
+                });
+                self.nr_edges += 1;
+            }
+            cycle.map(|path| Report::Cycle {
+                path,
+                classes: self.classes,
+                held,
+                taken: site,
+                class: b,
+            })
+        }
+
+        /// Shortest chain of recorded edges from `from` to `to`.
+        fn path(&self, from: u8, to: u8) -> Option<[Option<Edge>; 8]> {
+            let mut prev = [NO_CLASS; MAX_CLASSES];
+            let mut seen: ClassSet = 1 << from;
+            let mut frontier: ClassSet = 1 << from;
+            while frontier != 0 && seen & (1 << to) == 0 {
+                let mut next = 0;
+                for c in bits(frontier) {
+                    let new = self.after[c] & !seen;
+                    for n in bits(new) {
+                        prev[n] = c as u8;
+                    }
+                    next |= new;
+                    seen |= new;
+                }
+                frontier = next;
+            }
+            if seen & (1 << to) == 0 {
+                return None;
+            }
+            // Walk back from `to`, keeping the last eight hops.
+            let mut hops = [None; 8];
+            let mut n = 0;
+            let mut cur = to;
+            while cur != from && n < hops.len() {
+                let p = prev[cur as usize];
+                hops[n] = self.edge(p, cur);
+                n += 1;
+                cur = p;
+            }
+            hops[..n].reverse();
+            Some(hops)
+        }
+
+        fn edge(&self, from: u8, to: u8) -> Option<Edge> {
+            self.edges[..self.nr_edges]
+                .iter()
+                .flatten()
+                .find(|e| e.from == from && e.to == to)
+                .copied()
+        }
+
+        fn note_irq_use(
+            &mut self,
+            class: u8,
+            site: Site,
+            in_irq: bool,
+            irqs_on: bool,
+        ) -> Option<Report> {
+            let c = class as usize;
+            if in_irq && self.irq_site[c].is_none() {
+                self.irq_site[c] = Some(site);
+            }
+            if irqs_on && !in_irq && self.irq_on_site[c].is_none() {
+                self.irq_on_site[c] = Some(site);
+            }
+            match (self.irq_site[c], self.irq_on_site[c]) {
+                (Some(irq_at), Some(irq_on_at)) if self.irq_reported & (1 << c) == 0 => {
+                    self.irq_reported |= 1 << c;
+                    Some(Report::IrqUnsafe {
+                        class: self.classes[c]?,
+                        irq_at,
+                        irq_on_at,
+                    })
+                }
+                _ => None,
+            }
+        }
+    }
+
+    fn bits(set: ClassSet) -> impl Iterator<Item = usize> {
+        (0..MAX_CLASSES).filter(move |&i| set & (1 << i) != 0)
+    }
+
+    fn same_site(a: Site, b: Site) -> bool {
+        a.line() == b.line() && a.column() == b.column() && a.file() == b.file()
+    }
+
+    fn print(r: &Report, class_site: Site) {
+        match r {
+            Report::Cycle {
+                path,
+                classes,
+                held,
+                taken,
+                class,
+            } => {
+                let name = |c: u8| classes[c as usize].unwrap_or(class_site);
+                crate::klogln!("[lockdep] possible ABBA deadlock");
+                crate::klogln!("[lockdep]   taking {} at {}", name(*class), taken);
+                crate::klogln!(
+                    "[lockdep]   while holding {} taken at {}",
+                    name(held.class),
+                    held.site
+                );
+                crate::klogln!("[lockdep]   but the reverse order was seen before:");
+                for e in path.iter().flatten() {
+                    crate::klogln!(
+                        "[lockdep]     {} held from {}, then {} taken at {}",
+                        name(e.from),
+                        e.held_at,
+                        name(e.to),
+                        e.taken_at
+                    );
+                }
+            }
+            Report::IrqUnsafe {
+                class,
+                irq_at,
+                irq_on_at,
+            } => {
+                crate::klogln!("[lockdep] {} is not interrupt-safe", class);
+                crate::klogln!("[lockdep]   taken in an interrupt handler at {}", irq_at);
+                crate::klogln!("[lockdep]   and with interrupts enabled at {}", irq_on_at);
+            }
+            Report::Full => {
+                crate::klogln!(
+                    "[lockdep] more than {} lock classes; {} and later ones are not checked",
+                    MAX_CLASSES,
+                    class_site
+                );
+            }
+        }
+    }
+
+    #[cfg(feature = "selftest")]
+    pub(super) fn self_test() -> bool {
+        use super::{LockClass, acquire, release};
+
+        static A: LockClass = LockClass::new();
+        static B: LockClass = LockClass::new();
+        static IRQ: LockClass = LockClass::new();
+        let here = Location::caller();
+
+        SILENT.store(true, Ordering::Relaxed);
+        let before = REPORTS.load(Ordering::Relaxed);
+
+        // A then B, later B then A: no deadlock on one CPU, but a report.
+        let a = acquire(&A, here);
+        let b = acquire(&B, here);
+        release(&B, b);
+        release(&A, a);
+        let b = acquire(&B, here);
+        let a = acquire(&A, here);
+        release(&A, a);
+        release(&B, b);
+        let abba = REPORTS.load(Ordering::Relaxed) == before + 1;
+
+        // Taken with interrupts on, then from (pretend) interrupt context.
+        let irq = crate::arch::irq_save();
+        crate::arch::enable_interrupts();
+        let h = acquire(&IRQ, here);
+        release(&IRQ, h);
+        crate::arch::disable_interrupts();
+        crate::interrupts::irq_enter();
+        let h = acquire(&IRQ, here);
+        release(&IRQ, h);
+        crate::interrupts::irq_leave();
+        crate::arch::irq_restore(irq);
+        let irq_unsafe = REPORTS.load(Ordering::Relaxed) == before + 2;
+
+        SILENT.store(false, Ordering::Relaxed);
+        REPORTS.store(before, Ordering::Relaxed);
+        abba && irq_unsafe
+    }
+}
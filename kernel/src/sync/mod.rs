@@ -0,0 +1,89 @@
+//! Blocking synchronization primitives built on the scheduler, the
+//! spinlocks they are built from, read-copy-update, and the lock order
+//! validator.
+
+pub mod condvar;
+pub mod lockdep;
+pub mod mutex;
+pub mod rcu;
+pub mod semaphore;
+pub mod spinlock;
+pub mod waitqueue;
+
+pub use condvar::Condvar;
+pub use mutex::{Mutex, MutexGuard};
+pub use semaphore::Semaphore;
+pub use spinlock::{SpinLock, SpinLockGuard, SpinLockIrq};
+pub use waitqueue::WaitQueue;
+
+/// Boot-time checks for the primitives in this module.
+#[cfg(feature = "selftest")]
+pub fn self_test() -> bool {
+    lockdep::self_test() && mutex::self_test() && wait_self_test() && rcu::self_test()
+}
+
+/// A producer and consumer hand items over through a semaphore and a
+/// condvar, then timed waits with nothing to wait for time out.
+#[cfg(feature = "selftest")]
+fn wait_self_test() -> bool {
+    use crate::selftest::{self, TestThread};
+
+    const ITEMS: usize = 8;
+    const MS: u64 = 1_000_000;
+    static ITEMS_READY: Semaphore = Semaphore::new(0);
+    static QUEUE: Mutex<usize> = Mutex::new(0);
+    static DRAINED: Condvar = Condvar::new();
+
+    fn producer(_: usize) -> usize {
+        for _ in 0..ITEMS {
+            *QUEUE.lock() += 1;
+            ITEMS_READY.up();
+        }
+        // Wait for the consumer to take everything.
+        let q = DRAINED.wait_while(QUEUE.lock(), |q| *q != 0);
+        (*q == 0) as usize
+    }
+
+    fn consumer(_: usize) -> usize {
+        let mut taken = 0;
+        for _ in 0..ITEMS {
+            ITEMS_READY.down();
+            let mut q = QUEUE.lock();
+            *q -= 1;
+            taken += 1;
+            if *q == 0 {
+                DRAINED.notify_all();
+            }
+        }
+        taken
+    }
+
+    // Idle (the boot thread) must not block, so the timed waits run in
+    // a thread too.
+    fn timeouts(_: usize) -> usize {
+        let start = crate::time::monotonic_ns();
+        let sem_timed_out = !ITEMS_READY.down_timeout(MS);
+        let (_q, cv_timed_out) = DRAINED.wait_timeout(QUEUE.lock(), MS);
+        let waited = crate::time::monotonic_ns() - start >= 2 * MS;
+        (sem_timed_out && cv_timed_out && waited) as usize
+    }
+
+    let handed_over = selftest::run_threads(
+        crate::cpu::ALL_CPUS,
+        [
+            TestThread::new("test-producer", producer, 0, 1),
+            TestThread::new("test-consumer", consumer, 0, ITEMS),
+        ],
+    );
+    if !handed_over || ITEMS_READY.count() != 0 {
+        return false;
+    }
+    if hal::time::current().is_none() {
+        // Timeouts need a running clock.
+        return true;
+    }
+    selftest::run_threads(
+        crate::cpu::ALL_CPUS,
+        [TestThread::new("test-timeout", timeouts, 0, 1)],
+    )
+}
@@ -0,0 +1,421 @@
+//! Sleeping mutex with priority inheritance.
+//!
+//! A contended `lock` blocks the caller instead of spinning. While a
+//! thread waits, the owner inherits the waiter's priority, transitively
+//! along the chain of owners that are themselves waiting, so a low-priority
+//! holder cannot be starved by medium-priority work while a high-priority
+//! thread waits on it. `unlock` hands the mutex directly to the
+//! highest-priority waiter and drops the inheritance it no longer needs.
+//!
+//! Thread context only: never lock one from an interrupt handler.
+
+use core::cell::UnsafeCell;
+use core::marker::PhantomData;
+use core::ops::{Deref, DerefMut};
+use core::panic::Location;
+use core::ptr;
+use core::sync::atomic::{AtomicPtr, AtomicU8, AtomicUsize, Ordering};
+
+use super::SpinLockIrq;
+use super::lockdep::{self, LockClass};
+use crate::svc::sched::{self, thread::MAX_THREADS};
+
+const NO_SLOT: usize = usize::MAX;
+
+/// Longest owner chain followed when propagating a priority; deeper chains
+/// only happen in a deadlock.
+const MAX_CHAIN: usize = MAX_THREADS;
+
+/// Per-thread inheritance state, indexed by thread slot.
+struct PiThread {
+    /// Mutex the thread is waiting for; written under that mutex's
+    /// waiter lock.
+    blocked_on: AtomicPtr<RawMutex>,
+    /// Head of the list of mutexes the thread holds.
+    held: AtomicPtr<RawMutex>,
+    /// Waiter-list link; guarded by the lock of the mutex waited on.
+    wait_next: AtomicUsize,
+}
+
+static PI: [PiThread; MAX_THREADS] = [const {
+    PiThread {
+        blocked_on: AtomicPtr::new(ptr::null_mut()),
+        held: AtomicPtr::new(ptr::null_mut()),
+        wait_next: AtomicUsize::new(NO_SLOT),
+    }
+}; MAX_THREADS];
+
+/// Serialises priority changes, chain walks and the held lists.
+static PI_LOCK: SpinLockIrq<()> = SpinLockIrq::new(());
+
+fn with_pi<R>(f: impl FnOnce() -> R) -> R {
+    let _guard = PI_LOCK.lock();
+    f()
+}
+
+/// Waiters in arrival order, linked through `PiThread::wait_next`.
+struct Waiters {
+    head: usize,
+    tail: usize,
+}
+
+impl Waiters {
+    fn push(&mut self, slot: usize) {
+        PI[slot].wait_next.store(NO_SLOT, Ordering::Relaxed);
+        if self.tail == NO_SLOT {
+            self.head = slot;
+        } else {
+            PI[self.tail].wait_next.store(slot, Ordering::Relaxed);
+        }
+        self.tail = slot;
+    }
+
+    fn iter(&self) -> impl Iterator<Item = usize> {
+        let mut cur = self.head;
+        core::iter::from_fn(move || {
+            if cur == NO_SLOT {
+                return None;
+            }
+            let slot = cur;
+            cur = PI[slot].wait_next.load(Ordering::Relaxed);
+            Some(slot)
+        })
+    }
+
+    /// Remove the highest-priority waiter, oldest first among equals.
+    /// Priorities are read now, so boosts after queueing count.
+    fn pop_highest(&mut self) -> Option<usize> {
+        let mut best = None;
+        let mut best_prio = 0;
+        for slot in self.iter() {
+            let prio = sched::prio_of(slot);
+            if best.is_none() || prio > best_prio {
+                best = Some(slot);
+                best_prio = prio;
+            }
+        }
+        let slot = best?;
+        self.remove(slot);
+        Some(slot)
+    }
+
+    fn remove(&mut self, slot: usize) {
+        let mut prev = NO_SLOT;
+        let mut cur = self.head;
+        while cur != NO_SLOT {
+            let next = PI[cur].wait_next.load(Ordering::Relaxed);
+            if cur == slot {
+                if prev == NO_SLOT {
+                    self.head = next;
+                } else {
+                    PI[prev].wait_next.store(next, Ordering::Relaxed);
+                }
+                if self.tail == slot {
+                    self.tail = prev;
+                }
+                return;
+            }
+            prev = cur;
+            cur = next;
+        }
+    }
+
+    fn top_prio(&self) -> u8 {
+        self.iter().map(sched::prio_of).max().unwrap_or(0)
+    }
+}
+
+/// The lock itself, without the data; what inheritance chains walk.
+struct RawMutex {
+    waiters: SpinLockIrq<Waiters>,
+    /// Owning thread slot; written under `waiters`.
+    owner: AtomicUsize,
+    /// Highest priority among the waiters (0 if none or all fair).
+    top_prio: AtomicU8,
+    /// Next mutex in the owner's held list (`PI_LOCK`).
+    held_next: AtomicPtr<RawMutex>,
+    class: LockClass,
+}
+
+/// Outside `RawMutex::new` so every waiter list shares one lock class
+/// rather than taking the mutex's own.
+const fn waiter_list() -> SpinLockIrq<Waiters> {
+    SpinLockIrq::new(Waiters {
+        head: NO_SLOT,
+        tail: NO_SLOT,
+    })
+}
+
+impl RawMutex {
+    #[track_caller]
+    const fn new() -> Self {
+        Self {
+            waiters: waiter_list(),
+            owner: AtomicUsize::new(NO_SLOT),
+            top_prio: AtomicU8::new(0),
+            held_next: AtomicPtr::new(ptr::null_mut()),
+            class: LockClass::new(),
+        }
+    }
+
+    fn as_ptr(&self) -> *mut RawMutex {
+        self as *const RawMutex as *mut RawMutex
+    }
+
+    fn lock(&self) {
+        let me = sched::current_slot();
+        let mut w = self.waiters.lock();
+        let owner = self.owner.load(Ordering::Relaxed);
+        if owner == NO_SLOT {
+            self.owner.store(me, Ordering::Relaxed);
+            drop(w);
+            with_pi(|| push_held(me, self));
+            return;
+        }
+        assert!(owner != me, "recursive mutex lock");
+
+        w.push(me);
+        PI[me].blocked_on.store(self.as_ptr(), Ordering::Relaxed);
+        let prio = sched::prio_of(me);
+        self.top_prio.fetch_max(prio, Ordering::Relaxed);
+        sched::prepare_block();
+        drop(w);
+
+        with_pi(|| propagate(self, prio));
+
+        // `unlock` hands over ownership before waking us; anything else
+        // is a stray wakeup. Mark ourselves Blocked before checking, so a
+        // hand-over between the check and `block` is not lost.
+        loop {
+            sched::block();
+            sched::prepare_block();
+            if self.owner.load(Ordering::Acquire) == me {
+                sched::cancel_block();
+                return;
+            }
+        }
+    }
+
+    fn unlock(&self) {
+        let me = sched::current_slot();
+        let mut w = self.waiters.lock();
+        debug_assert_eq!(self.owner.load(Ordering::Relaxed), me);
+        let next = w.pop_highest();
+        self.top_prio.store(w.top_prio(), Ordering::Relaxed);
+        if let Some(next) = next {
+            PI[next]
+                .blocked_on
+                .store(ptr::null_mut(), Ordering::Relaxed);
+        }
+        self.owner.store(next.unwrap_or(NO_SLOT), Ordering::Release);
+        drop(w);
+
+        with_pi(|| {
+            remove_held(me, self);
+            if let Some(next) = next {
+                push_held(next, self);
+            }
+            sched::set_inherited_prio(me, held_top_prio(me));
+        });
+        if let Some(next) = next {
+            sched::wake_slot(next);
+        }
+    }
+
+    fn try_lock(&self) -> bool {
+        let me = sched::current_slot();
+        let w = self.waiters.lock();
+        let free = self.owner.load(Ordering::Relaxed) == NO_SLOT;
+        if free {
+            self.owner.store(me, Ordering::Relaxed);
+        }
+        drop(w);
+        if free {
+            with_pi(|| push_held(me, self));
+        }
+        free
+    }
+}
+
+/// Raise the owner of `m`, and the owners down the chain they wait on, to
+/// at least `prio`. Caller holds `PI_LOCK`.
+fn propagate(mut m: &RawMutex, prio: u8) {
+    for _ in 0..MAX_CHAIN {
+        let owner = m.owner.load(Ordering::Acquire);
+        if owner == NO_SLOT || sched::prio_of(owner) >= prio {
+            return;
+        }
+        sched::set_inherited_prio(owner, prio.max(sched::inherited_prio(owner)));
+        let next = PI[owner].blocked_on.load(Ordering::Relaxed);
+        if next.is_null() {
+            return;
+        }
+        m = unsafe { &*next };
+        m.top_prio.fetch_max(prio, Ordering::Relaxed);
+    }
+}
+
+/// Caller holds `PI_LOCK`.
+fn push_held(slot: usize, m: &RawMutex) {
+    let head = PI[slot].held.load(Ordering::Relaxed);
+    m.held_next.store(head, Ordering::Relaxed);
+    PI[slot].held.store(m.as_ptr(), Ordering::Relaxed);
+    sched::set_inherited_prio(
+        slot,
+        sched::inherited_prio(slot).max(m.top_prio.load(Ordering::Relaxed)),
+    );
+}
+
+/// Caller holds `PI_LOCK`.
+fn remove_held(slot: usize, m: &RawMutex) {
+    let target = m.as_ptr();
+    let mut link = &PI[slot].held;
+    loop {
+        let cur = link.load(Ordering::Relaxed);
+        if cur.is_null() {
+            return;
+        }
+        let cur_ref = unsafe { &*cur };
+        if cur == target {
+            link.store(cur_ref.held_next.load(Ordering::Relaxed), Ordering::Relaxed);
+            cur_ref.held_next.store(ptr::null_mut(), Ordering::Relaxed);
+            return;
+        }
+        link = &cur_ref.held_next;
+    }
+}
+
+/// Highest waiter priority over every mutex `slot` holds. Caller holds
+/// `PI_LOCK`.
+fn held_top_prio(slot: usize) -> u8 {
+    let mut top = 0;
+    let mut cur = PI[slot].held.load(Ordering::Relaxed);
+    while let Some(m) = unsafe { cur.as_ref() } {
+        top = top.max(m.top_prio.load(Ordering::Relaxed));
+        cur = m.held_next.load(Ordering::Relaxed);
+    }
+    top
+}
+
+/// A sleeping mutual-exclusion lock protecting a `T`.
+pub struct Mutex<T> {
+    raw: RawMutex,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: Send> Send for Mutex<T> {}
+unsafe impl<T: Send> Sync for Mutex<T> {}
+
+impl<T> Mutex<T> {
+    /// The lock class is the caller's source location.
+    #[track_caller]
+    pub const fn new(value: T) -> Self {
+        Self {
+            raw: RawMutex::new(),
+            data: UnsafeCell::new(value),
+        }
+    }
+
+    /// Acquire the mutex, sleeping while another thread holds it.
+    #[cfg_attr(feature = "lockdep", track_caller)]
+    pub fn lock(&self) -> MutexGuard<'_, T> {
+        let held = lockdep::acquire(&self.raw.class, Location::caller());
+        self.raw.lock();
+        MutexGuard {
+            mutex: self,
+            held,
+            _not_send: PhantomData,
+        }
+    }
+
+    /// Acquire the mutex only if it is free.
+    #[cfg_attr(feature = "lockdep", track_caller)]
+    pub fn try_lock(&self) -> Option<MutexGuard<'_, T>> {
+        if !self.raw.try_lock() {
+            return None;
+        }
+        Some(MutexGuard {
+            mutex: self,
+            held: lockdep::acquired_try(&self.raw.class, Location::caller()),
+            _not_send: PhantomData,
+        })
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.raw.owner.load(Ordering::Relaxed) != NO_SLOT
+    }
+}
+
+/// Must be dropped by the thread that locked it.
+pub struct MutexGuard<'a, T> {
+    mutex: &'a Mutex<T>,
+    held: lockdep::Held,
+    _not_send: PhantomData<*const ()>,
+}
+
+impl<'a, T> MutexGuard<'a, T> {
+    /// The mutex this guard holds, for relocking after a wait.
+    pub(super) fn mutex(guard: &Self) -> &'a Mutex<T> {
+        guard.mutex
+    }
+}
+
+impl<T> Deref for MutexGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.mutex.data.get() }
+    }
+}
+
+impl<T> DerefMut for MutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.mutex.data.get() }
+    }
+}
+
+impl<T> Drop for MutexGuard<'_, T> {
+    fn drop(&mut self) {
+        self.mutex.raw.unlock();
+        lockdep::release(&self.mutex.raw.class, self.held);
+    }
+}
+
+/// Boot-time check: a fair thread holding a mutex inherits the priority of
+/// a real-time thread that blocks on it, and drops it again on unlock.
+#[cfg(feature = "selftest")]
+pub fn self_test() -> bool {
+    use crate::cpu;
+    use crate::selftest::{self, TestThread};
+    use crate::svc::sched::SchedAttr;
+
+    const TEST_PRIO: u8 = 50;
+    static M: Mutex<usize> = Mutex::new(0);
+
+    fn waiter(_: usize) -> usize {
+        let mut g = M.lock();
+        *g += 1;
+        *g
+    }
+
+    fn holder(_: usize) -> usize {
+        let me = sched::current_slot();
+        let mut g = M.lock();
+        let here = cpu::mask_of(cpu::current());
+        let attr = SchedAttr::fifo(TEST_PRIO);
+        let Ok(w) = sched::spawn_on_cpus("test-pi-high", waiter, 0, attr, here) else {
+            return 0;
+        };
+        // The real-time waiter runs first and blocks on `M`.
+        sched::yield_now();
+        let boosted = sched::prio_of(me) == TEST_PRIO;
+        *g += 1;
+        drop(g);
+        let restored = sched::prio_of(me) == 0;
+        (boosted && restored && sched::join(w) == Some(2)) as usize
+    }
+
+    // Both stay on one CPU so the waiter must block while the holder runs.
+    let here = cpu::mask_of(cpu::current());
+    selftest::run_threads(here, [TestThread::new("test-pi-low", holder, 0, 1)])
+}
@@ -0,0 +1,505 @@
+//! Read-copy-update for read-mostly data.
+//!
+//! Readers follow a published pointer inside a `read_lock` section, which
+//! only disables preemption: no locks, no shared writes. A writer
+//! publishes a new version, then waits for a grace period before freeing
+//! the old one, either by sleeping in `synchronize` or by handing a
+//! callback to `call_rcu`.
+//!
+//! This is quiescent-state based. Grace periods are numbered; each CPU
+//! records the latest one it has seen while outside any read section (a
+//! context switch, an idle loop pass, or an interrupt that arrived with
+//! preemption enabled). A grace period is complete once every online CPU
+//! has recorded it, as has every CPU taken offline that has not reached
+//! its idle loop yet. CPUs that are slow to get there, busy in a thread or
+//! halted in idle, are sent a reschedule IPI so the interrupt exit
+//! records one for them.
+//!
+//! Callbacks wait on the CPU that queued them and run from that CPU's
+//! deferred work, never from the interrupt or switch that ended the
+//! grace period.
+
+use core::marker::PhantomData;
+use core::ptr;
+use core::sync::atomic::{AtomicBool, AtomicPtr, AtomicU32, AtomicU64, Ordering, fence};
+
+use super::{SpinLockIrq, WaitQueue};
+use crate::cpu::{self, CpuMask, MAX_CPUS};
+use crate::deferred::{self, WorkItem};
+use crate::svc::sched;
+use crate::time::timer::{self, Timer};
+
+/// How long a grace period may lag before stragglers are kicked again.
+const KICK_INTERVAL_NS: u64 = 1_000_000;
+
+/// Number of the newest grace period started.
+static GP_SEQ: AtomicU64 = AtomicU64::new(0);
+/// Every grace period up to this one has completed.
+static GP_DONE: AtomicU64 = AtomicU64::new(0);
+/// Newest grace period each CPU has passed a quiescent state in.
+static QS_SEQ: [AtomicU64; MAX_CPUS] = [const { AtomicU64::new(0) }; MAX_CPUS];
+/// Read-side nesting per CPU.
+static NESTING: [AtomicU32; MAX_CPUS] = [const { AtomicU32::new(0) }; MAX_CPUS];
+/// CPUs taken offline that may still be running a thread, and so a read
+/// section. Grace periods wait for them until they reach their idle loop.
+static LEAVING: AtomicU64 = AtomicU64::new(0);
+
+/// Threads sleeping in `synchronize`.
+static GP_WAIT: WaitQueue = WaitQueue::new();
+
+/// Callbacks waiting for their grace period, per CPU.
+static CALLBACKS: [SpinLockIrq<CbList>; MAX_CPUS] =
+    [const { SpinLockIrq::new(CbList::new()) }; MAX_CPUS];
+/// Queued callbacks per CPU, readable without the list lock.
+static QUEUED: [AtomicU32; MAX_CPUS] = [const { AtomicU32::new(0) }; MAX_CPUS];
+
+static CB_WORK: [WorkItem; MAX_CPUS] =
+    [const { WorkItem::new("rcu-callbacks", run_callbacks, 0) }; MAX_CPUS];
+/// Keeps grace periods moving while a CPU has callbacks queued.
+static GP_TIMER: [Timer; MAX_CPUS] = [const { Timer::new("rcu-gp", on_gp_timer, 0) }; MAX_CPUS];
+
+static CALLBACKS_RUN: AtomicU64 = AtomicU64::new(0);
+
+/// A callback to run after a grace period, embedded in the object it
+/// frees. Like a `WorkItem`, it is owned by the caller and can be queued
+/// once at a time.
+pub struct RcuHead {
+    func: fn(usize),
+    arg: usize,
+    /// Grace period that must complete first.
+    gp: AtomicU64,
+    pending: AtomicBool,
+    next: AtomicPtr<RcuHead>,
+}
+
+impl RcuHead {
+    pub const fn new(func: fn(usize), arg: usize) -> Self {
+        Self {
+            func,
+            arg,
+            gp: AtomicU64::new(0),
+            pending: AtomicBool::new(false),
+            next: AtomicPtr::new(ptr::null_mut()),
+        }
+    }
+
+    pub fn is_pending(&self) -> bool {
+        self.pending.load(Ordering::Acquire)
+    }
+}
+
+/// Queued callbacks in `call_rcu` order.
+struct CbList {
+    head: *mut RcuHead,
+    tail: *mut RcuHead,
+}
+
+// Entries point at `'static` heads.
+unsafe impl Send for CbList {}
+
+impl CbList {
+    const fn new() -> Self {
+        Self {
+            head: ptr::null_mut(),
+            tail: ptr::null_mut(),
+        }
+    }
+
+    fn push(&mut self, head: &'static RcuHead) {
+        let raw = head as *const RcuHead as *mut RcuHead;
+        head.next.store(ptr::null_mut(), Ordering::Relaxed);
+        if self.tail.is_null() {
+            self.head = raw;
+        } else {
+            unsafe { &*self.tail }.next.store(raw, Ordering::Relaxed);
+        }
+        self.tail = raw;
+    }
+
+    /// Unlink the first head whose grace period is `<= done`.
+    fn pop_ready(&mut self, done: u64) -> Option<&'static RcuHead> {
+        let mut prev: *mut RcuHead = ptr::null_mut();
+        let mut cur = self.head;
+        while !cur.is_null() {
+            let head: &'static RcuHead = unsafe { &*cur };
+            let next = head.next.load(Ordering::Relaxed);
+            if head.gp.load(Ordering::Relaxed) <= done {
+                if prev.is_null() {
+                    self.head = next;
+                } else {
+                    unsafe { &*prev }.next.store(next, Ordering::Relaxed);
+                }
+                if self.tail == cur {
+                    self.tail = prev;
+                }
+                return Some(head);
+            }
+            prev = cur;
+            cur = next;
+        }
+        None
+    }
+}
+
+/// An open read-side section; dropping it ends the section.
+pub struct RcuReadGuard {
+    /// Pinned to the CPU it was taken on.
+    _not_send: PhantomData<*const ()>,
+}
+
+/// Begin a read-side section. Sections nest and must not sleep.
+pub fn read_lock() -> RcuReadGuard {
+    sched::preempt_disable();
+    NESTING[cpu::current()].fetch_add(1, Ordering::Relaxed);
+    RcuReadGuard {
+        _not_send: PhantomData,
+    }
+}
+
+impl Drop for RcuReadGuard {
+    fn drop(&mut self) {
+        NESTING[cpu::current()].fetch_sub(1, Ordering::Relaxed);
+        sched::preempt_enable();
+    }
+}
+
+/// Whether the current CPU is inside a read-side section.
+pub fn in_read_section() -> bool {
+    NESTING[cpu::current()].load(Ordering::Relaxed) != 0
+}
+
+/// A pointer that readers follow under `read_lock` while writers swap it.
+pub struct RcuPtr<T: 'static> {
+    ptr: AtomicPtr<T>,
+}
+
+unsafe impl<T: Sync> Sync for RcuPtr<T> {}
+
+impl<T> RcuPtr<T> {
+    pub const fn null() -> Self {
+        Self {
+            ptr: AtomicPtr::new(ptr::null_mut()),
+        }
+    }
+
+    /// The published value, valid until the section ends.
+    pub fn read<'g>(&self, _guard: &'g RcuReadGuard) -> Option<&'g T> {
+        unsafe { self.ptr.load(Ordering::Acquire).as_ref() }
+    }
+
+    /// Publish `new` and return the previous value. Readers may still
+    /// hold the old one; reuse it only after a grace period.
+    pub fn replace(&self, new: Option<&'static T>) -> Option<&'static T> {
+        let raw = new.map_or(ptr::null_mut(), |v| v as *const T as *mut T);
+        unsafe { self.ptr.swap(raw, Ordering::AcqRel).as_ref() }
+    }
+}
+
+/// Note a quiescent state on this CPU: it holds no references from
+/// earlier read sections. Called on context switch, from the idle loop and
+/// on interrupt exit when preemption is enabled.
+pub fn quiescent() {
+    let cpu = cpu::current();
+    if NESTING[cpu].load(Ordering::Relaxed) != 0 {
+        return;
+    }
+    let gp = GP_SEQ.load(Ordering::Acquire);
+    if QS_SEQ[cpu].load(Ordering::Relaxed) == gp {
+        return;
+    }
+    // Order this CPU's earlier reads before the report.
+    fence(Ordering::SeqCst);
+    QS_SEQ[cpu].store(gp, Ordering::Release);
+    advance();
+}
+
+/// Record that `cpu` is about to leave the online mask. Call before
+/// clearing it there, so grace periods keep waiting for the thread the CPU
+/// is still running until `cpu_left` reports for it.
+pub fn cpu_leaving(cpu: usize) {
+    LEAVING.fetch_or(cpu::mask_of(cpu), Ordering::AcqRel);
+}
+
+/// Called from the idle loop of an offline CPU. Only idle runs there now,
+/// so report a quiescent state for the CPU and then stop waiting for it.
+pub fn cpu_left() {
+    let cpu = cpu::current();
+    if LEAVING.load(Ordering::Acquire) & cpu::mask_of(cpu) == 0 || cpu::is_online(cpu) {
+        return;
+    }
+    fence(Ordering::SeqCst);
+    QS_SEQ[cpu].store(GP_SEQ.load(Ordering::Acquire), Ordering::Release);
+    LEAVING.fetch_and(!cpu::mask_of(cpu), Ordering::AcqRel);
+    advance();
+}
+
+/// CPUs grace periods wait for.
+fn tracked() -> CpuMask {
+    cpu::online_mask() | LEAVING.load(Ordering::Acquire)
+}
+
+/// Move `GP_DONE` up to the oldest grace period some tracked CPU is still
+/// in, and release whatever was waiting for it.
+fn advance() {
+    let online = cpu::online_mask();
+    let done = cpu::iter(tracked())
+        .map(|c| QS_SEQ[c].load(Ordering::Acquire))
+        .min()
+        .unwrap_or_else(|| GP_SEQ.load(Ordering::Acquire));
+    if GP_DONE.fetch_max(done, Ordering::AcqRel) >= done {
+        return;
+    }
+    GP_WAIT.wake_all();
+    for c in cpu::iter(online) {
+        if QUEUED[c].load(Ordering::Acquire) != 0 {
+            deferred::queue_on(c, &CB_WORK[c]);
+        }
+    }
+}
+
+fn completed(gp: u64) -> bool {
+    GP_DONE.load(Ordering::Acquire) >= gp
+}
+
+/// Start a grace period and return its number.
+fn start_gp() -> u64 {
+    GP_SEQ.fetch_add(1, Ordering::AcqRel) + 1
+}
+
+/// Interrupt the tracked CPUs that have not reached `gp` yet.
+fn kick(gp: u64) {
+    let me = cpu::current();
+    for c in cpu::iter(tracked()) {
+        if c != me && QS_SEQ[c].load(Ordering::Acquire) < gp {
+            crate::smp::send_reschedule(c);
+        }
+    }
+}
+
+/// Wait until every read-side section open at the time of the call has
+/// ended. Sleeps: not from interrupts, the idle thread or a read section.
+pub fn synchronize() {
+    assert!(
+        !in_read_section(),
+        "rcu: synchronize inside a read-side section"
+    );
+    let gp = start_gp();
+    quiescent();
+    kick(gp);
+    while !GP_WAIT.wait_until_timeout(|| completed(gp), KICK_INTERVAL_NS) {
+        advance();
+        kick(gp);
+    }
+}
+
+/// Run `head`'s callback from deferred work on this CPU once the read
+/// sections open now have ended. Safe from any context, including a read
+/// section. Returns false if `head` is already queued.
+pub fn call_rcu(head: &'static RcuHead) -> bool {
+    if head.pending.swap(true, Ordering::AcqRel) {
+        return false;
+    }
+    head.gp.store(start_gp(), Ordering::Relaxed);
+    let cpu = cpu::current();
+    CALLBACKS[cpu].lock().push(head);
+    QUEUED[cpu].fetch_add(1, Ordering::AcqRel);
+    if !GP_TIMER[cpu].is_pending() {
+        timer::start(&GP_TIMER[cpu], KICK_INTERVAL_NS);
+    }
+    true
+}
+
+/// Wait until every callback queued before the call has run.
+pub fn barrier() {
+    synchronize();
+    loop {
+        let queued: u32 = cpu::iter(cpu::online_mask())
+            .map(|c| QUEUED[c].load(Ordering::Acquire))
+            .sum();
+        if queued == 0 {
+            return;
+        }
+        synchronize();
+    }
+}
+
+/// Callbacks run since boot.
+pub fn callbacks_run() -> u64 {
+    CALLBACKS_RUN.load(Ordering::Relaxed)
+}
+
+fn on_gp_timer(_: usize) {
+    let cpu = cpu::current();
+    advance();
+    if QUEUED[cpu].load(Ordering::Acquire) == 0 {
+        return;
+    }
+    deferred::queue(&CB_WORK[cpu]);
+    kick(GP_SEQ.load(Ordering::Acquire));
+    timer::start(&GP_TIMER[cpu], KICK_INTERVAL_NS);
+}
+
+/// Deferred work: run this CPU's callbacks whose grace period is over.
+fn run_callbacks(_: usize) {
+    let cpu = cpu::current();
+    loop {
+        let done = GP_DONE.load(Ordering::Acquire);
+        let Some(head) = CALLBACKS[cpu].lock().pop_ready(done) else {
+            break;
+        };
+        QUEUED[cpu].fetch_sub(1, Ordering::AcqRel);
+        // Clear first so the callback may queue the head again.
+        head.pending.store(false, Ordering::Release);
+        (head.func)(head.arg);
+        CALLBACKS_RUN.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Readers pinned to every online CPU keep dereferencing a published node
+/// while a writer retires nodes through both `synchronize` and `call_rcu`.
+/// A reader must never see a node that was freed under it.
+#[cfg(feature = "selftest")]
+pub fn self_test() -> bool {
+    const NODES: usize = 8;
+    const ROUNDS: usize = 256;
+    const POISON: u64 = u64::MAX;
+    const FREE: u32 = 0;
+    const LIVE: u32 = 1;
+    const RETIRED: u32 = 2;
+
+    struct Node {
+        value: AtomicU64,
+        state: AtomicU32,
+        head: RcuHead,
+    }
+
+    static POOL: [Node; NODES] = pool();
+    static CURRENT: RcuPtr<Node> = RcuPtr::null();
+    static STOP: AtomicBool = AtomicBool::new(false);
+    static BAD_READS: AtomicU64 = AtomicU64::new(0);
+    static FREED: AtomicU64 = AtomicU64::new(0);
+    static PUBLISHED: AtomicU64 = AtomicU64::new(0);
+
+    const fn pool() -> [Node; NODES] {
+        let mut nodes = [const {
+            Node {
+                value: AtomicU64::new(POISON),
+                state: AtomicU32::new(FREE),
+                head: RcuHead::new(free_node, 0),
+            }
+        }; NODES];
+        let mut i = 0;
+        while i < NODES {
+            nodes[i].head = RcuHead::new(free_node, i);
+            i += 1;
+        }
+        nodes
+    }
+
+    fn free_node(i: usize) {
+        let node = &POOL[i];
+        node.value.store(POISON, Ordering::Release);
+        node.state.store(FREE, Ordering::Release);
+        FREED.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn reader(_: usize) -> usize {
+        let mut reads = 0;
+        while !STOP.load(Ordering::Acquire) {
+            {
+                let guard = read_lock();
+                if let Some(node) = CURRENT.read(&guard) {
+                    let first = node.value.load(Ordering::Acquire);
+                    core::hint::spin_loop();
+                    if first == POISON || node.value.load(Ordering::Acquire) != first {
+                        BAD_READS.fetch_add(1, Ordering::Relaxed);
+                    }
+                    reads += 1;
+                }
+            }
+            sched::yield_now();
+        }
+        reads
+    }
+
+    fn writer(_: usize) -> usize {
+        for round in 0..ROUNDS {
+            let Some(i) = (0..NODES).find(|&i| {
+                POOL[i]
+                    .state
+                    .compare_exchange(FREE, LIVE, Ordering::AcqRel, Ordering::Relaxed)
+                    .is_ok()
+            }) else {
+                // Everything is waiting on callbacks.
+                synchronize();
+                continue;
+            };
+            POOL[i].value.store(round as u64, Ordering::Release);
+            PUBLISHED.fetch_add(1, Ordering::Relaxed);
+            let Some(old) = CURRENT.replace(Some(&POOL[i])) else {
+                continue;
+            };
+            old.state.store(RETIRED, Ordering::Release);
+            if round % 2 == 0 {
+                synchronize();
+                free_node(old.head.arg);
+            } else {
+                call_rcu(&old.head);
+            }
+            if round % 16 == 0 {
+                sched::yield_now();
+            }
+        }
+        if let Some(last) = CURRENT.replace(None) {
+            synchronize();
+            free_node(last.head.arg);
+        }
+        barrier();
+        STOP.store(true, Ordering::Release);
+        1
+    }
+
+    if hal::time::current().is_none() {
+        // Grace periods are driven by timers.
+        return true;
+    }
+    let freed_before = FREED.load(Ordering::Relaxed);
+    let published_before = PUBLISHED.load(Ordering::Relaxed);
+    let run_before = callbacks_run();
+
+    let mut readers = [None; MAX_CPUS];
+    for (n, c) in cpu::iter(cpu::online_mask()).enumerate() {
+        match sched::spawn_on_cpus(
+            "test-rcu-reader",
+            reader,
+            c,
+            Default::default(),
+            cpu::mask_of(c),
+        ) {
+            Ok(id) => readers[n] = Some(id),
+            Err(_) => {
+                STOP.store(true, Ordering::Release);
+                return false;
+            }
+        }
+    }
+    let writer_ok = match sched::spawn("test-rcu-writer", writer, 0) {
+        Ok(w) => sched::join(w) == Some(1),
+        Err(_) => {
+            STOP.store(true, Ordering::Release);
+            false
+        }
+    };
+    let mut reads = 0;
+    for id in readers.into_iter().flatten() {
+        reads += sched::join(id).unwrap_or(0);
+    }
+
+    writer_ok
+        && reads > 0
+        && BAD_READS.load(Ordering::Relaxed) == 0
+        && FREED.load(Ordering::Relaxed) - freed_before
+            == PUBLISHED.load(Ordering::Relaxed) - published_before
+        && callbacks_run() > run_before
+        && POOL.iter().all(|n| n.state.load(Ordering::Acquire) == FREE)
+}
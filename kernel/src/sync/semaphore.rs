@@ -0,0 +1,63 @@
+//! Counting semaphore.
+//!
+//! `down` takes a unit, sleeping while there are none; `up` returns one
+//! and wakes a sleeper. No ownership and no priority inheritance: use
+//! `Mutex` for mutual exclusion. `up` and `try_down` are safe from
+//! interrupt handlers.
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use super::WaitQueue;
+
+pub struct Semaphore {
+    count: AtomicUsize,
+    waiters: WaitQueue,
+}
+
+impl Semaphore {
+    #[track_caller]
+    pub const fn new(count: usize) -> Self {
+        Self {
+            count: AtomicUsize::new(count),
+            waiters: WaitQueue::new(),
+        }
+    }
+
+    /// Take a unit, sleeping until one is available.
+    pub fn down(&self) {
+        self.waiters.wait_until(|| self.try_down());
+    }
+
+    /// `down`, giving up after `timeout_ns`. Returns false on timeout.
+    pub fn down_timeout(&self, timeout_ns: u64) -> bool {
+        self.waiters
+            .wait_until_timeout(|| self.try_down(), timeout_ns)
+    }
+
+    /// Take a unit only if one is available now.
+    pub fn try_down(&self) -> bool {
+        let mut count = self.count.load(Ordering::Relaxed);
+        while count != 0 {
+            match self.count.compare_exchange_weak(
+                count,
+                count - 1,
+                Ordering::Acquire,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => return true,
+                Err(cur) => count = cur,
+            }
+        }
+        false
+    }
+
+    /// Return a unit and wake one sleeper.
+    pub fn up(&self) {
+        self.count.fetch_add(1, Ordering::Release);
+        self.waiters.wake_one();
+    }
+
+    pub fn count(&self) -> usize {
+        self.count.load(Ordering::Relaxed)
+    }
+}
@@ -0,0 +1,162 @@
+//! Spinlocks, checked by `lockdep` when that feature is on.
+//!
+//! `SpinLock` is a plain `spin::Mutex` with a lock class. It must not be
+//! taken from an interrupt handler unless every holder disables
+//! interrupts first.
+//!
+//! `SpinLockIrq` keeps interrupts off on the local CPU while held: it
+//! saves the interrupt state with `arch::irq_save` before spinning and
+//! restores it after the lock is released, so it may be shared between
+//! threads and interrupt handlers. Keep critical sections short: they
+//! delay every interrupt on the CPU.
+
+use core::marker::PhantomData;
+use core::mem::ManuallyDrop;
+use core::ops::{Deref, DerefMut};
+
+use super::lockdep::{self, LockClass};
+
+pub struct SpinLock<T> {
+    inner: spin::Mutex<T>,
+    class: LockClass,
+}
+
+impl<T> SpinLock<T> {
+    /// The lock class is the caller's source location.
+    #[track_caller]
+    pub const fn new(value: T) -> Self {
+        Self {
+            inner: spin::Mutex::new(value),
+            class: LockClass::new(),
+        }
+    }
+
+    #[cfg_attr(feature = "lockdep", track_caller)]
+    pub fn lock(&self) -> SpinLockGuard<'_, T> {
+        let held = lockdep::acquire(&self.class, core::panic::Location::caller());
+        SpinLockGuard {
+            guard: ManuallyDrop::new(self.inner.lock()),
+            class: &self.class,
+            held,
+        }
+    }
+
+    #[cfg_attr(feature = "lockdep", track_caller)]
+    pub fn try_lock(&self) -> Option<SpinLockGuard<'_, T>> {
+        let guard = self.inner.try_lock()?;
+        let held = lockdep::acquired_try(&self.class, core::panic::Location::caller());
+        Some(SpinLockGuard {
+            guard: ManuallyDrop::new(guard),
+            class: &self.class,
+            held,
+        })
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.inner.is_locked()
+    }
+}
+
+pub struct SpinLockGuard<'a, T> {
+    guard: ManuallyDrop<spin::MutexGuard<'a, T>>,
+    class: &'a LockClass,
+    held: lockdep::Held,
+}
+
+impl<T> Deref for SpinLockGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.guard
+    }
+}
+
+impl<T> DerefMut for SpinLockGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.guard
+    }
+}
+
+impl<T> Drop for SpinLockGuard<'_, T> {
+    fn drop(&mut self) {
+        unsafe { ManuallyDrop::drop(&mut self.guard) };
+        lockdep::release(self.class, self.held);
+    }
+}
+
+pub struct SpinLockIrq<T> {
+    inner: SpinLock<T>,
+}
+
+impl<T> SpinLockIrq<T> {
+    /// The lock class is the caller's source location.
+    #[track_caller]
+    pub const fn new(value: T) -> Self {
+        Self {
+            inner: SpinLock::new(value),
+        }
+    }
+
+    /// Disable interrupts and spin until the lock is ours.
+    #[cfg_attr(feature = "lockdep", track_caller)]
+    pub fn lock(&self) -> SpinLockIrqGuard<'_, T> {
+        let irq = crate::arch::irq_save();
+        SpinLockIrqGuard {
+            guard: ManuallyDrop::new(self.inner.lock()),
+            irq,
+            _not_send: PhantomData,
+        }
+    }
+
+    /// Take the lock only if it is free, leaving interrupts as they were
+    /// otherwise.
+    #[cfg_attr(feature = "lockdep", track_caller)]
+    pub fn try_lock(&self) -> Option<SpinLockIrqGuard<'_, T>> {
+        let irq = crate::arch::irq_save();
+        match self.inner.try_lock() {
+            Some(guard) => Some(SpinLockIrqGuard {
+                guard: ManuallyDrop::new(guard),
+                irq,
+                _not_send: PhantomData,
+            }),
+            None => {
+                crate::arch::irq_restore(irq);
+                None
+            }
+        }
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.inner.is_locked()
+    }
+}
+
+/// Releases the lock, then restores the interrupt state. Must be dropped
+/// on the CPU that locked it.
+pub struct SpinLockIrqGuard<'a, T> {
+    guard: ManuallyDrop<SpinLockGuard<'a, T>>,
+    irq: usize,
+    _not_send: PhantomData<*const ()>,
+}
+
+impl<T> Deref for SpinLockIrqGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.guard
+    }
+}
+
+impl<T> DerefMut for SpinLockIrqGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.guard
+    }
+}
+
+impl<T> Drop for SpinLockIrqGuard<'_, T> {
+    fn drop(&mut self) {
+        // Unlock before interrupts can come back on.
+        unsafe { ManuallyDrop::drop(&mut self.guard) };
+        crate::arch::irq_restore(self.irq);
+    }
+}
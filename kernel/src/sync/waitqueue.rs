@@ -0,0 +1,193 @@
+//! Wait queues: sleep until a condition holds.
+//!
+//! A waiter queues itself and marks itself Blocked before it checks its
+//! condition, so a wake that lands between the check and `sched::block`
+//! just makes `block` return at once. Wakers change the condition first,
+//! then call `wake_one` or `wake_all`. Wakeups may be spurious; waiters
+//! always re-check.
+//!
+//! Waking is safe from interrupt handlers; waiting needs a thread.
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use super::SpinLockIrq;
+use crate::svc::sched::{self, thread::MAX_THREADS};
+
+const NO_SLOT: usize = usize::MAX;
+
+/// Queue links, indexed by thread slot. A thread waits on at most one
+/// queue at a time; guarded by that queue's lock.
+static LINKS: [AtomicUsize; MAX_THREADS] = [const { AtomicUsize::new(NO_SLOT) }; MAX_THREADS];
+
+/// Waiters in arrival order, linked through `LINKS`.
+struct List {
+    head: usize,
+    tail: usize,
+}
+
+impl List {
+    fn push(&mut self, slot: usize) {
+        LINKS[slot].store(NO_SLOT, Ordering::Relaxed);
+        if self.tail == NO_SLOT {
+            self.head = slot;
+        } else {
+            LINKS[self.tail].store(slot, Ordering::Relaxed);
+        }
+        self.tail = slot;
+    }
+
+    fn pop(&mut self) -> Option<usize> {
+        let slot = self.head;
+        if slot == NO_SLOT {
+            return None;
+        }
+        self.head = LINKS[slot].load(Ordering::Relaxed);
+        if self.head == NO_SLOT {
+            self.tail = NO_SLOT;
+        }
+        Some(slot)
+    }
+
+    fn remove(&mut self, slot: usize) -> bool {
+        let mut prev = NO_SLOT;
+        let mut cur = self.head;
+        while cur != NO_SLOT {
+            let next = LINKS[cur].load(Ordering::Relaxed);
+            if cur == slot {
+                if prev == NO_SLOT {
+                    self.head = next;
+                } else {
+                    LINKS[prev].store(next, Ordering::Relaxed);
+                }
+                if self.tail == slot {
+                    self.tail = prev;
+                }
+                return true;
+            }
+            prev = cur;
+            cur = next;
+        }
+        false
+    }
+
+    fn contains(&self, slot: usize) -> bool {
+        let mut cur = self.head;
+        while cur != NO_SLOT {
+            if cur == slot {
+                return true;
+            }
+            cur = LINKS[cur].load(Ordering::Relaxed);
+        }
+        false
+    }
+}
+
+pub struct WaitQueue {
+    waiters: SpinLockIrq<List>,
+}
+
+impl WaitQueue {
+    /// The queue's lock class is the caller's source location.
+    #[track_caller]
+    pub const fn new() -> Self {
+        Self {
+            waiters: SpinLockIrq::new(List {
+                head: NO_SLOT,
+                tail: NO_SLOT,
+            }),
+        }
+    }
+
+    /// Queue the current thread and mark it Blocked. Check the condition
+    /// next, then `sched::block` if it still does not hold, and call
+    /// `finish_wait` once done waiting.
+    pub fn prepare_to_wait(&self) {
+        let me = sched::current_slot();
+        let mut w = self.waiters.lock();
+        if !w.contains(me) {
+            w.push(me);
+        }
+        sched::prepare_block();
+    }
+
+    /// Leave the queue, if still on it, and undo a pending
+    /// `prepare_to_wait`.
+    pub fn finish_wait(&self) {
+        let me = sched::current_slot();
+        self.waiters.lock().remove(me);
+        sched::cancel_block();
+    }
+
+    /// Sleep until `cond` returns true.
+    pub fn wait_until(&self, mut cond: impl FnMut() -> bool) {
+        loop {
+            self.prepare_to_wait();
+            if cond() {
+                self.finish_wait();
+                return;
+            }
+            sched::block();
+        }
+    }
+
+    /// `wait_until`, giving up after `timeout_ns`. Returns the final value
+    /// of `cond`.
+    pub fn wait_until_timeout(&self, mut cond: impl FnMut() -> bool, timeout_ns: u64) -> bool {
+        let deadline = crate::time::monotonic_ns().saturating_add(timeout_ns);
+        loop {
+            self.prepare_to_wait();
+            if cond() {
+                self.finish_wait();
+                return true;
+            }
+            if crate::time::monotonic_ns() >= deadline || !sched::block_until(deadline) {
+                self.finish_wait();
+                return cond();
+            }
+        }
+    }
+
+    /// Wake the longest waiter. Returns false if none was asleep.
+    pub fn wake_one(&self) -> bool {
+        loop {
+            let Some(slot) = self.waiters.lock().pop() else {
+                return false;
+            };
+            // A waiter that already woke (timeout or a spurious wake) is
+            // about to re-check on its own; pass the wakeup on.
+            if sched::wake_slot(slot) {
+                return true;
+            }
+        }
+    }
+
+    /// Wake every waiter. Returns how many were asleep.
+    pub fn wake_all(&self) -> usize {
+        // Empty the queue first: a waiter that wakes early may re-queue
+        // itself, and must not be woken (or its link read) twice.
+        let mut slots = [NO_SLOT; MAX_THREADS];
+        let mut n = 0;
+        {
+            let mut w = self.waiters.lock();
+            while let Some(slot) = w.pop() {
+                slots[n] = slot;
+                n += 1;
+            }
+        }
+        slots[..n]
+            .iter()
+            .filter(|&&slot| sched::wake_slot(slot))
+            .count()
+    }
+
+    pub fn has_waiters(&self) -> bool {
+        self.waiters.lock().head != NO_SLOT
+    }
+}
+
+impl Default for WaitQueue {
+    #[track_caller]
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,226 @@
+use core::sync::atomic::{AtomicU32, AtomicU64, Ordering, fence};
+
+pub mod tick;
+pub mod timer;
+pub mod wall;
+
+pub use wall::realtime_ns;
+
+const NS_PER_SEC: u64 = 1_000_000_000;
+
+/// Longest tick delta the mult/shift fast path must handle without
+/// overflowing u64; longer deltas take the u128 path.
+const MAX_CONV_SECS: u64 = 600;
+
+/// Tick->ns conversion anchored at the last clocksource (re)selection:
+/// ns = base_ns + ((ticks - base_ticks) * mult) >> shift.
+/// Writers bump `SEQ` to odd while updating; readers retry.
+static SEQ: AtomicU32 = AtomicU32::new(0);
+static BASE_TICKS: AtomicU64 = AtomicU64::new(0);
+static BASE_NS: AtomicU64 = AtomicU64::new(0);
+static MULT: AtomicU32 = AtomicU32::new(0);
+static SHIFT: AtomicU32 = AtomicU32::new(0);
+static MAX_DELTA: AtomicU64 = AtomicU64::new(0);
+
+pub fn init() {
+    select_clocksource();
+    wall::init();
+    tick::start_cpu();
+}
+
+/// Activate the highest-rated timer the arch layer registered. Called at
+/// boot and again whenever ratings may have changed (e.g. a clocksource
+/// was found unreliable once all CPUs were up); switching keeps
+/// `monotonic_ns` continuous and re-arms pending timers on every CPU.
+pub fn select_clocksource() {
+    let Some(best) = hal::time::timers().max_by_key(|t| t.rating()) else {
+        crate::klogln!("[time] no timer available");
+        return;
+    };
+    let current = hal::time::current();
+    if current.is_some_and(|c| c.name() == best.name()) {
+        return;
+    }
+    if current.is_none() {
+        for t in hal::time::timers() {
+            crate::klogln!("[time] candidate {} rating={}", t.name(), t.rating());
+        }
+    }
+
+    switch_to(best);
+    crate::klogln!(
+        "[time] clocksource {} freq={} Hz mult={} shift={}",
+        best.name(),
+        best.frequency_hz(),
+        MULT.load(Ordering::Relaxed),
+        SHIFT.load(Ordering::Relaxed)
+    );
+    if let Some(deadline) = timer::next_deadline() {
+        timer::reprogram(deadline);
+    }
+    // The other CPUs still have the old timer's interrupt source programmed.
+    crate::smp::smp_call_function(crate::cpu::ALL_CPUS, activate_cpu, 0, true);
+}
+
+/// Program the newly selected timer on this CPU and re-arm its queue.
+fn activate_cpu(_: usize) {
+    hal::time::activate_cpu();
+    if let Some(deadline) = timer::next_deadline() {
+        timer::reprogram(deadline);
+    }
+}
+
+/// Make `t` the active timer and re-anchor the conversion on it so that
+/// it continues from the current time. Both happen inside one `SEQ` write
+/// section, so no reader mixes the new timer's ticks with the old
+/// anchor. Interrupts stay off so no handler on this CPU spins on it.
+fn switch_to(t: &'static dyn hal::time::TimerOps) {
+    let hz = t.frequency_hz();
+    let (mult, shift) = if hz == 0 {
+        (0, 0)
+    } else {
+        calc_mult_shift(hz, NS_PER_SEC, MAX_CONV_SECS)
+    };
+    let max_delta = if mult == 0 { 0 } else { u64::MAX / mult as u64 };
+
+    let irq = crate::arch::irq_save();
+    let seq = SEQ.fetch_add(1, Ordering::AcqRel);
+    fence(Ordering::Release);
+    let now_ns = anchored_ns();
+    unsafe {
+        hal::time::select_timer(t);
+    }
+    BASE_TICKS.store(hal::time::now_ticks(), Ordering::Relaxed);
+    BASE_NS.store(now_ns, Ordering::Relaxed);
+    MULT.store(mult, Ordering::Relaxed);
+    SHIFT.store(shift, Ordering::Relaxed);
+    MAX_DELTA.store(max_delta, Ordering::Relaxed);
+    SEQ.store(seq.wrapping_add(2), Ordering::Release);
+    crate::arch::irq_restore(irq);
+}
+
+/// Pick the largest shift such that `mult = (to << shift) / from` fits in
+/// u32 and `max_secs` worth of `from` ticks times `mult` fits in u64.
+fn calc_mult_shift(from: u64, to: u64, max_secs: u64) -> (u32, u32) {
+    let mut tmp = max_secs.saturating_mul(from) >> 32;
+    let mut sftacc = 32u32;
+    while tmp != 0 {
+        tmp >>= 1;
+        sftacc -= 1;
+    }
+
+    let mut mult = 0u64;
+    let mut shift = 32u32;
+    while shift > 0 {
+        mult = ((to << shift) + from / 2) / from;
+        if (mult >> sftacc) == 0 {
+            break;
+        }
+        shift -= 1;
+    }
+    (mult as u32, shift)
+}
+
+/// Nanoseconds since the clock was first anchored at boot. Returns 0
+/// until a clocksource is selected.
+pub fn monotonic_ns() -> u64 {
+    loop {
+        let seq = SEQ.load(Ordering::Acquire);
+        if seq & 1 != 0 {
+            core::hint::spin_loop();
+            continue;
+        }
+        // The counter is read inside the section too: a switch between
+        // reading the anchor and the counter must send us round again.
+        let ns = anchored_ns();
+        fence(Ordering::Acquire);
+        if SEQ.load(Ordering::Relaxed) == seq {
+            return ns;
+        }
+    }
+}
+
+/// The active timer's counter converted with the current anchor. Only
+/// consistent inside a `SEQ` read or write section.
+fn anchored_ns() -> u64 {
+    let base_ticks = BASE_TICKS.load(Ordering::Relaxed);
+    let base_ns = BASE_NS.load(Ordering::Relaxed);
+    let mult = MULT.load(Ordering::Relaxed) as u64;
+    let shift = SHIFT.load(Ordering::Relaxed);
+    let max_delta = MAX_DELTA.load(Ordering::Relaxed);
+    if mult == 0 {
+        return base_ns;
+    }
+    let delta = hal::time::now_ticks().saturating_sub(base_ticks);
+    let ns = if delta <= max_delta {
+        (delta * mult) >> shift
+    } else {
+        ((delta as u128 * mult as u128) >> shift) as u64
+    };
+    base_ns.saturating_add(ns)
+}
+
+/// Time since boot, as (seconds, nanoseconds).
+pub fn uptime() -> (u64, u32) {
+    let ns = monotonic_ns();
+    (ns / NS_PER_SEC, (ns % NS_PER_SEC) as u32)
+}
+
+/// Convert a nanosecond span to ticks of the active timer.
+pub fn ns_to_ticks(ns: u64) -> u64 {
+    let hz = hal::time::frequency_hz();
+    ((ns as u128 * hz as u128) / NS_PER_SEC as u128) as u64
+}
+
+/// Convert a span of ticks of the active timer to nanoseconds.
+pub fn ticks_to_ns(ticks: u64) -> u64 {
+    let hz = hal::time::frequency_hz();
+    if hz == 0 {
+        return 0;
+    }
+    ((ticks as u128 * NS_PER_SEC as u128) / hz as u128) as u64
+}
+
+/// Log the wall clock, jiffies and each CPU in `cpus`'s tick counts.
+pub fn print_stats(cpus: crate::cpu::CpuMask) {
+    let real = realtime_ns();
+    crate::klogln!(
+        "[time] realtime={}.{:09} jiffies={}",
+        real / NS_PER_SEC,
+        real % NS_PER_SEC,
+        tick::jiffies()
+    );
+    for cpu in crate::cpu::iter(cpus) {
+        let s = tick::stats(cpu);
+        crate::klogln!(
+            "[time] cpu{} ticks={} missed={} idle_wakeups={}",
+            cpu,
+            s.ticks,
+            s.missed,
+            s.wakeups
+        );
+    }
+}
+
+/// Spin until `monotonic_ns()` reaches `deadline_ns`.
+///
+/// There is nothing to block on yet, so this polls; it returns at once if
+/// no clocksource is running.
+pub fn sleep_until(deadline_ns: u64) {
+    if hal::time::frequency_hz() == 0 {
+        return;
+    }
+    while monotonic_ns() < deadline_ns {
+        crate::arch::cpu_relax();
+    }
+}
+
+/// Spin for at least `ns` nanoseconds.
+pub fn busy_wait(ns: u64) {
+    sleep_until(monotonic_ns().saturating_add(ns));
+}
+
+/// Timer interrupt: run expired timers and re-arm for the next one.
+pub fn on_timer_tick() {
+    timer::run_expired();
+}
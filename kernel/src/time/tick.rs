@@ -0,0 +1,119 @@
+//! Per-CPU periodic tick with tickless idle (NO_HZ).
+//!
+//! Each CPU runs a `TICK_NS` periodic timer while busy. When it goes idle
+//! the tick is stopped and the hardware is armed only for the next real
+//! timer (or `MAX_IDLE_NS` if there is none). On wakeup the ticks that were
+//! skipped are accounted and the tick restarts on its original cadence.
+
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+use super::timer::{self, Timer};
+use crate::cpu::{self, MAX_CPUS};
+
+pub const TICK_NS: u64 = 10_000_000;
+
+/// Longest the hardware is left unarmed while idle with no timers pending.
+const MAX_IDLE_NS: u64 = 1_000_000_000;
+
+/// Tick periods elapsed since boot (includes periods skipped while idle).
+static JIFFIES: AtomicU64 = AtomicU64::new(0);
+
+struct CpuTick {
+    timer: Timer,
+    /// Tick is stopped because the CPU is idle.
+    stopped: AtomicBool,
+    stopped_at_ns: AtomicU64,
+    /// Ticks that actually fired.
+    ticks: AtomicU64,
+    /// Ticks skipped while idle.
+    missed: AtomicU64,
+    /// Times the CPU left tickless idle.
+    wakeups: AtomicU64,
+}
+
+impl CpuTick {
+    const fn new() -> Self {
+        Self {
+            timer: Timer::new("tick", on_tick, 0),
+            stopped: AtomicBool::new(false),
+            stopped_at_ns: AtomicU64::new(0),
+            ticks: AtomicU64::new(0),
+            missed: AtomicU64::new(0),
+            wakeups: AtomicU64::new(0),
+        }
+    }
+}
+
+static TICKS: [CpuTick; MAX_CPUS] = [const { CpuTick::new() }; MAX_CPUS];
+
+#[derive(Clone, Copy, Default)]
+pub struct TickStats {
+    pub ticks: u64,
+    pub missed: u64,
+    pub wakeups: u64,
+}
+
+/// Start the periodic tick on the current CPU.
+pub fn start_cpu() {
+    let t = &TICKS[cpu::current()];
+    timer::start_at(&t.timer, next_boundary(super::monotonic_ns()), TICK_NS);
+}
+
+fn on_tick(_: usize) {
+    TICKS[cpu::current()].ticks.fetch_add(1, Ordering::Relaxed);
+    advance_jiffies(super::monotonic_ns());
+}
+
+/// First tick boundary strictly after `now_ns`.
+fn next_boundary(now_ns: u64) -> u64 {
+    (now_ns / TICK_NS + 1) * TICK_NS
+}
+
+fn advance_jiffies(now_ns: u64) {
+    JIFFIES.fetch_max(now_ns / TICK_NS, Ordering::Relaxed);
+}
+
+/// Tick periods since boot.
+pub fn jiffies() -> u64 {
+    JIFFIES.load(Ordering::Relaxed)
+}
+
+/// Stop the tick before idling. Call with interrupts disabled, right
+/// before waiting for an interrupt.
+pub fn idle_enter() {
+    let t = &TICKS[cpu::current()];
+    if t.stopped.load(Ordering::Relaxed) {
+        return;
+    }
+    let now = super::monotonic_ns();
+    timer::cancel(&t.timer);
+    t.stopped_at_ns.store(now, Ordering::Relaxed);
+    t.stopped.store(true, Ordering::Relaxed);
+
+    let deadline = timer::next_deadline().unwrap_or(now.saturating_add(MAX_IDLE_NS));
+    timer::reprogram(deadline.min(now.saturating_add(MAX_IDLE_NS)));
+}
+
+/// Restart the tick after an idle wakeup and account the skipped ticks.
+pub fn idle_exit() {
+    let t = &TICKS[cpu::current()];
+    if !t.stopped.swap(false, Ordering::Relaxed) {
+        return;
+    }
+    let now = super::monotonic_ns();
+    let stopped_at = t.stopped_at_ns.load(Ordering::Relaxed);
+    let missed = now / TICK_NS - stopped_at / TICK_NS;
+    t.missed.fetch_add(missed, Ordering::Relaxed);
+    t.wakeups.fetch_add(1, Ordering::Relaxed);
+    advance_jiffies(now);
+    timer::start_at(&t.timer, next_boundary(now), TICK_NS);
+}
+
+pub fn stats(cpu: usize) -> TickStats {
+    let t = &TICKS[cpu];
+    TickStats {
+        ticks: t.ticks.load(Ordering::Relaxed),
+        missed: t.missed.load(Ordering::Relaxed),
+        wakeups: t.wakeups.load(Ordering::Relaxed),
+    }
+}
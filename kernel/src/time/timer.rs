@@ -0,0 +1,369 @@
+//! High-resolution one-shot and periodic timers.
+//!
+//! Each CPU keeps a binary min-heap of pending timers ordered by deadline.
+//! The hardware timer is always armed for the earliest entry; on expiry
+//! `run_expired` pops every timer whose deadline has passed and runs its
+//! callback. Callbacks run in interrupt context with interrupts disabled,
+//! so they must be short; anything longer belongs in deferred work.
+
+use core::ptr;
+use core::sync::atomic::{AtomicU32, AtomicU64, AtomicUsize, Ordering};
+
+use crate::cpu::{self, MAX_CPUS};
+use crate::sync::SpinLock;
+
+/// Pending timers per CPU.
+const HEAP_CAPACITY: usize = 128;
+
+/// `Timer::cpu` value while the timer is not queued.
+const NO_CPU: usize = usize::MAX;
+
+/// A kernel timer.
+///
+/// Timers are owned by the caller (usually a `static`) and sit in at most
+/// one queue at a time; starting a pending timer moves its deadline.
+pub struct Timer {
+    name: &'static str,
+    func: fn(usize),
+    arg: usize,
+    deadline_ns: AtomicU64,
+    /// Re-arm interval in ns; 0 for one-shot.
+    period_ns: AtomicU64,
+    /// CPU whose heap holds this timer, or `NO_CPU`.
+    cpu: AtomicUsize,
+    /// Index in that heap; only meaningful under the heap lock.
+    slot: AtomicUsize,
+    /// Bumped on every start/cancel so an expiry in flight can tell it
+    /// was superseded.
+    generation: AtomicU32,
+}
+
+impl Timer {
+    pub const fn new(name: &'static str, func: fn(usize), arg: usize) -> Self {
+        Self {
+            name,
+            func,
+            arg,
+            deadline_ns: AtomicU64::new(0),
+            period_ns: AtomicU64::new(0),
+            cpu: AtomicUsize::new(NO_CPU),
+            slot: AtomicUsize::new(0),
+            generation: AtomicU32::new(0),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    pub fn is_pending(&self) -> bool {
+        self.cpu.load(Ordering::Acquire) != NO_CPU
+    }
+
+    /// CPU whose queue holds the timer, if it is pending.
+    pub fn cpu(&self) -> Option<usize> {
+        let cpu = self.cpu.load(Ordering::Acquire);
+        (cpu != NO_CPU).then_some(cpu)
+    }
+
+    /// Deadline of the last start, in `monotonic_ns` time.
+    pub fn deadline_ns(&self) -> u64 {
+        self.deadline_ns.load(Ordering::Relaxed)
+    }
+}
+
+struct Heap {
+    len: usize,
+    entries: [*const Timer; HEAP_CAPACITY],
+}
+
+// Entries point at `'static` timers.
+unsafe impl Send for Heap {}
+
+impl Heap {
+    const fn new() -> Self {
+        Self {
+            len: 0,
+            entries: [ptr::null(); HEAP_CAPACITY],
+        }
+    }
+
+    #[inline(always)]
+    fn at(&self, i: usize) -> &'static Timer {
+        unsafe { &*self.entries[i] }
+    }
+
+    #[inline(always)]
+    fn key(&self, i: usize) -> u64 {
+        self.at(i).deadline_ns.load(Ordering::Relaxed)
+    }
+
+    fn set(&mut self, i: usize, t: &'static Timer) {
+        self.entries[i] = t;
+        t.slot.store(i, Ordering::Relaxed);
+    }
+
+    fn peek(&self) -> Option<&'static Timer> {
+        if self.len == 0 {
+            None
+        } else {
+            Some(self.at(0))
+        }
+    }
+
+    fn push(&mut self, t: &'static Timer) -> bool {
+        if self.len == HEAP_CAPACITY {
+            return false;
+        }
+        let i = self.len;
+        self.len += 1;
+        self.set(i, t);
+        self.sift_up(i);
+        true
+    }
+
+    fn remove(&mut self, i: usize) -> &'static Timer {
+        let t = self.at(i);
+        self.len -= 1;
+        if i != self.len {
+            let last = self.at(self.len);
+            self.set(i, last);
+            self.sift_down(i);
+            self.sift_up(i);
+        }
+        self.entries[self.len] = ptr::null();
+        t
+    }
+
+    fn sift_up(&mut self, mut i: usize) {
+        while i > 0 {
+            let parent = (i - 1) / 2;
+            if self.key(parent) <= self.key(i) {
+                break;
+            }
+            self.swap(i, parent);
+            i = parent;
+        }
+    }
+
+    fn sift_down(&mut self, mut i: usize) {
+        loop {
+            let l = 2 * i + 1;
+            let r = l + 1;
+            let mut min = i;
+            if l < self.len && self.key(l) < self.key(min) {
+                min = l;
+            }
+            if r < self.len && self.key(r) < self.key(min) {
+                min = r;
+            }
+            if min == i {
+                break;
+            }
+            self.swap(i, min);
+            i = min;
+        }
+    }
+
+    fn swap(&mut self, a: usize, b: usize) {
+        let ta = self.at(a);
+        let tb = self.at(b);
+        self.set(a, tb);
+        self.set(b, ta);
+    }
+}
+
+static HEAPS: [SpinLock<Heap>; MAX_CPUS] = [const { SpinLock::new(Heap::new()) }; MAX_CPUS];
+
+/// Run `f` on `cpu`'s heap with interrupts disabled.
+fn with_heap<R>(cpu: usize, f: impl FnOnce(&mut Heap) -> R) -> R {
+    let irq = crate::arch::irq_save();
+    let r = f(&mut HEAPS[cpu].lock());
+    crate::arch::irq_restore(irq);
+    r
+}
+
+/// Remove `t` from whichever heap holds it. Caller holds no heap lock.
+fn dequeue(t: &'static Timer) -> bool {
+    loop {
+        let cpu = t.cpu.load(Ordering::Acquire);
+        if cpu == NO_CPU {
+            return false;
+        }
+        let removed = with_heap(cpu, |heap| {
+            // Recheck under the lock: it may have expired or moved.
+            if t.cpu.load(Ordering::Relaxed) != cpu {
+                return None;
+            }
+            heap.remove(t.slot.load(Ordering::Relaxed));
+            t.cpu.store(NO_CPU, Ordering::Release);
+            Some(())
+        });
+        if removed.is_some() {
+            return true;
+        }
+    }
+}
+
+/// Queue `t` on the current CPU to fire at `deadline_ns`, then every
+/// `period_ns` after that if non-zero. Restarts a pending timer.
+///
+/// Returns false if this CPU's timer queue is full.
+pub fn start_at(t: &'static Timer, deadline_ns: u64, period_ns: u64) -> bool {
+    t.generation.fetch_add(1, Ordering::AcqRel);
+    let cpu = cpu::current();
+    loop {
+        dequeue(t);
+        let queued = with_heap(cpu, |heap| {
+            // Claim the timer first; a racing start on another CPU wins.
+            if t.cpu
+                .compare_exchange(NO_CPU, cpu, Ordering::AcqRel, Ordering::Relaxed)
+                .is_err()
+            {
+                return None;
+            }
+            // The key may only change while no heap holds the timer.
+            t.deadline_ns.store(deadline_ns, Ordering::Relaxed);
+            t.period_ns.store(period_ns, Ordering::Relaxed);
+            if !heap.push(t) {
+                t.cpu.store(NO_CPU, Ordering::Release);
+                return Some((false, false));
+            }
+            Some((true, heap.peek().is_some_and(|head| ptr::eq(head, t))))
+        });
+        let Some((ok, first)) = queued else {
+            continue;
+        };
+        if first {
+            reprogram(deadline_ns);
+        }
+        return ok;
+    }
+}
+
+/// Fire `t` once, `delay_ns` from now.
+pub fn start(t: &'static Timer, delay_ns: u64) -> bool {
+    start_at(t, super::monotonic_ns().saturating_add(delay_ns), 0)
+}
+
+/// Fire `t` every `period_ns`, starting one period from now.
+pub fn start_periodic(t: &'static Timer, period_ns: u64) -> bool {
+    start_at(
+        t,
+        super::monotonic_ns().saturating_add(period_ns),
+        period_ns,
+    )
+}
+
+/// Stop `t`. Returns true if it was pending. A callback already running
+/// on another CPU finishes, but a periodic timer is not re-armed.
+pub fn cancel(t: &'static Timer) -> bool {
+    t.generation.fetch_add(1, Ordering::AcqRel);
+    dequeue(t)
+}
+
+/// Earliest pending deadline on the current CPU.
+pub fn next_deadline() -> Option<u64> {
+    with_heap(cpu::current(), |heap| heap.peek().map(|t| t.deadline_ns()))
+}
+
+/// Hand every timer queued on this CPU to an online CPU, which re-arms for
+/// them. For an offline CPU about to idle: nothing there would run them.
+pub fn hand_off() {
+    let me = cpu::current();
+    if next_deadline().is_none() {
+        return;
+    }
+    let Some(to) = cpu::iter(cpu::online_mask()).find(|&c| c != me) else {
+        return;
+    };
+    crate::smp::smp_call_function(cpu::mask_of(to), pull_from, me, false);
+}
+
+/// Move every timer queued on `from` to this CPU's queue (as many as fit)
+/// and re-arm for the earliest.
+fn pull_from(from: usize) {
+    let me = cpu::current();
+    if from == me {
+        return;
+    }
+    let irq = crate::arch::irq_save();
+    let (lo, hi) = (from.min(me), from.max(me));
+    let mut lo_heap = HEAPS[lo].lock();
+    let mut hi_heap = HEAPS[hi].lock();
+    let (src, dst) = if from == lo {
+        (&mut *lo_heap, &mut *hi_heap)
+    } else {
+        (&mut *hi_heap, &mut *lo_heap)
+    };
+    while src.len != 0 && dst.len != HEAP_CAPACITY {
+        let t = src.remove(src.len - 1);
+        // Both locks are held, so `dequeue` sees one heap or the other.
+        t.cpu.store(me, Ordering::Release);
+        dst.push(t);
+    }
+    drop(hi_heap);
+    drop(lo_heap);
+    crate::arch::irq_restore(irq);
+    if let Some(deadline) = next_deadline() {
+        reprogram(deadline);
+    }
+}
+
+/// Timer interrupt path: run every expired callback on this CPU and re-arm
+/// the hardware for the next deadline.
+pub fn run_expired() {
+    let cpu = cpu::current();
+    loop {
+        let now = super::monotonic_ns();
+        let expired = with_heap(cpu, |heap| {
+            let head = heap.peek()?;
+            if head.deadline_ns() > now {
+                return None;
+            }
+            heap.remove(0);
+            head.cpu.store(NO_CPU, Ordering::Release);
+            Some((head, head.generation.load(Ordering::Acquire)))
+        });
+        let Some((t, generation)) = expired else {
+            break;
+        };
+
+        (t.func)(t.arg);
+
+        let period = t.period_ns.load(Ordering::Relaxed);
+        if period != 0 && t.generation.load(Ordering::Acquire) == generation {
+            // Keep the cadence, skipping periods we slept through.
+            let mut next = t.deadline_ns().saturating_add(period);
+            let now = super::monotonic_ns();
+            if next <= now {
+                next = now + period - (now - next) % period;
+            }
+            with_heap(cpu, |heap| {
+                // A restart or cancel since expiry owns the timer now.
+                if t.generation.load(Ordering::Acquire) != generation
+                    || t.cpu
+                        .compare_exchange(NO_CPU, cpu, Ordering::AcqRel, Ordering::Relaxed)
+                        .is_err()
+                {
+                    return;
+                }
+                t.deadline_ns.store(next, Ordering::Relaxed);
+                if !heap.push(t) {
+                    t.cpu.store(NO_CPU, Ordering::Release);
+                }
+            });
+        }
+    }
+
+    if let Some(deadline) = next_deadline() {
+        reprogram(deadline);
+    }
+}
+
+/// Arm the hardware timer for `deadline_ns`.
+pub(super) fn reprogram(deadline_ns: u64) {
+    let now_ns = super::monotonic_ns();
+    let delta = super::ns_to_ticks(deadline_ns.saturating_sub(now_ns));
+    hal::time::arm_one_shot(hal::time::now_ticks().wrapping_add(delta.max(1)));
+}
@@ -0,0 +1,59 @@
+//! Wall-clock (calendar) time.
+//!
+//! The hardware RTC is read once at boot and turned into an epoch offset;
+//! after that realtime is `epoch + monotonic`, so it never jumps with RTC
+//! reads and shares the monotonic clock's resolution.
+
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use hal::rtc::RtcTime;
+
+use super::NS_PER_SEC;
+
+/// Unix time in ns at `monotonic_ns() == 0`; 0 if unknown.
+static EPOCH_NS: AtomicU64 = AtomicU64::new(0);
+
+pub fn init() {
+    let Some(rtc) = hal::rtc::read() else {
+        crate::klogln!("[time] no wall clock");
+        return;
+    };
+    let now_ns = super::monotonic_ns();
+    let unix_ns = unix_secs(&rtc).saturating_mul(NS_PER_SEC);
+    EPOCH_NS.store(unix_ns.saturating_sub(now_ns), Ordering::Relaxed);
+    crate::klogln!(
+        "[time] wall clock {:04}-{:02}-{:02} {:02}:{:02}:{:02} UTC",
+        rtc.year,
+        rtc.month,
+        rtc.day,
+        rtc.hour,
+        rtc.minute,
+        rtc.second
+    );
+}
+
+/// Nanoseconds since the Unix epoch, or 0 if no wall clock was found.
+pub fn realtime_ns() -> u64 {
+    let epoch = EPOCH_NS.load(Ordering::Relaxed);
+    if epoch == 0 {
+        return 0;
+    }
+    epoch.saturating_add(super::monotonic_ns())
+}
+
+fn unix_secs(t: &RtcTime) -> u64 {
+    let days = days_from_civil(t.year as i64, t.month as i64, t.day as i64);
+    let secs = days * 86_400 + t.hour as i64 * 3600 + t.minute as i64 * 60 + t.second as i64;
+    secs.max(0) as u64
+}
+
+/// Days since 1970-01-01 in the proleptic Gregorian calendar.
+fn days_from_civil(y: i64, m: i64, d: i64) -> i64 {
+    let y = if m <= 2 { y - 1 } else { y };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let mp = (m + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + d - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
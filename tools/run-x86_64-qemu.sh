@@ -8,7 +8,7 @@ qemu-system-x86_64 \
   -cpu host,+invtsc \
   -enable-kvm \
   -m 1G \
-  -smp 1 \
+  -smp 8 \
   -drive if=pflash,format=raw,readonly=on,file=$OVMF_CODE \
   -drive if=pflash,format=raw,file=$OVMF_VARS \
   -cdrom rincos.iso \